@@ -156,6 +156,14 @@ parser_register.add_argument(
     help = 'the name you want to register in the server'
 )
 
+# subparser for login with an existing token
+parser_login = subparsers.add_parser('login', help = 'use the token of a registered user or the admin')
+parser_login.add_argument(
+    '-t', '--token', action = 'store', type = str, required = True,
+    default = None,
+    help = 'the token returned when registering or the admin token of the server'
+)
+
 # subparser for create emunet
 parser_create = subparsers.add_parser('netcrt', help = 'create a emunet under a user with specified capacity')
 parser_create.add_argument(
@@ -170,6 +178,21 @@ parser_create.add_argument(
     '-c', '--capacity', action = 'store', type = int, dest = 'capacity', default = None,
     required = True, help = 'the emunet capacity that you want to create'
 )
+parser_create.add_argument(
+    '-q', '--queue', action = 'store_true', dest = 'queue', default = False,
+    help = 'wait in the queue if the cluster does not have enough capacity'
+)
+parser_create.add_argument(
+    '-p', '--priority', action = 'store', type = int, dest = 'priority', default = 0,
+    help = 'the priority of the request in the queue, higher is served first'
+)
+
+# subparser for polling a queued emunet creation
+parser_ticket = subparsers.add_parser('ticket', help = 'query a queued emunet creation')
+parser_ticket.add_argument(
+    '-i', '--id', action = 'store', type = str, dest = 'ticket_uuid', default = None,
+    required = True, help = 'the ticket returned when the creation is queued'
+)
 
 # subparser for get emunet infomation
 parser_emuinfo = subparsers.add_parser('listall', help = 'get the global information')
@@ -240,11 +263,13 @@ def register_user(url, name):
     )
     return response
 
-def create_emunet(url, user_name, emunet_name, emunet_capacity):
+def create_emunet(url, user_name, emunet_name, emunet_capacity, queue, priority):
     body = {
         "user": user_name,
         "emunet": emunet_name,
-        "capacity": emunet_capacity
+        "capacity": emunet_capacity,
+        "queue": queue,
+        "priority": priority
     }
     response = requests.post(
         url = url,
@@ -302,6 +327,10 @@ def get_emunet_info(url, uuid):
     )
     return response
 
+# the failed responses carry a code that identifies the error, e.g. EMUNET_NOT_FOUND
+def error_message(response_json):
+    return "[%s] %s" % (response_json.get('code', 'UNKNOWN'), response_json['message'])
+
 # the stage of normal operation 
 print("*** note: input '-h' or '--help' to show these subcommands for operation, or 'exit' to quit")
 while True:
@@ -316,25 +345,46 @@ while True:
             response_json = response.json()
             #print(response.status_code)
             if response_json['success'] == True:
-                print("successfully register as '%s'" % args_op.user_name)
+                # the following requests are authenticated with the returned token
+                POST_HEADER['Authorization'] = 'Bearer %s' % response_json['data']['token']
+                print("successfully register as '%s', token: %s" % (args_op.user_name, response_json['data']['token']))
             else:
-                print("error! the message is:%s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
         
+        if args_op.CmdType == 'login':
+            POST_HEADER['Authorization'] = 'Bearer %s' % args_op.token
+
         if args_op.CmdType == 'netcrt':
             response = create_emunet(url="http://localhost:3030/v1/create_emunet", 
                         user_name = args_op.user_name,
                         emunet_name = args_op.net_name,
                         emunet_capacity = args_op.capacity,
+                        queue = args_op.queue,
+                        priority = args_op.priority,
             )
             #print(response.text)
             #print(response.status_code)
             response_json = response.json()
-            if response_json['success'] == True:
+            if response_json['success'] == True and isinstance(response_json['data'], dict):
+                print("the cluster does not have enough capacity, ticket '%s' is at position %s of the queue"
+                % (response_json['data']['ticket_uuid'], response_json['data']['position']))
+            elif response_json['success'] == True:
                 print("successfully create emunet named '%s' with user '%s' and capacity '%s', the UUID of emunet is: '%s'" 
                 % (args_op.user_name, args_op.net_name, args_op.capacity, response_json['data']))
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
         
+        if args_op.CmdType == 'ticket':
+            response = requests.get(
+                url = 'http://localhost:3030/v1/tickets/%s' % args_op.ticket_uuid,
+                headers = POST_HEADER
+            )
+            response_json = response.json()
+            if response_json['success'] == True:
+                print(json.dumps(response_json['data'], indent=4))
+            else:
+                print("error! the message is: %s" % error_message(response_json))
+
         if args_op.CmdType == 'netinit':
             nodes_dicts = list()
             links_dicts = list()
@@ -350,7 +400,7 @@ while True:
             if response_json['success'] == True:
                 print('successfullt init the emunet, now it\'s working!')
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'listall':
             response = get_net_info(url='http://localhost:3030/v1/list_all')
@@ -360,7 +410,7 @@ while True:
             if response_json['success'] == True:
                 print(fmt_data)
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'netlist':
             response = emunet_list(url='http://localhost:3030/v1/list_emunet', name=args_op.user_name)
@@ -369,7 +419,7 @@ while True:
                 for net in response_json['data']:
                     print('name: %s, uuid: %s' % (net, response_json['data'][net]))
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'netdel':
             response = delete_user(url='http://localhost:3030/v1/delete_emunet', name=args_op.name)
@@ -377,7 +427,7 @@ while True:
             if response_json['success'] == True:
                 print('successfully delete user: %s', args_op.name)
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'netupdate':
             nodes_dicts = list()
@@ -393,7 +443,7 @@ while True:
             if response_json['success'] == True:
                 print('successfullt update the emunet, now it\'s working!')
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'usrdel':
             response = get_net_info(url='http://localhost:3030/v1/delete_user', uuid=args_op.uuid)
@@ -401,7 +451,7 @@ while True:
             if response_json['success'] == True:
                 print("suscessfully delete the emunet!")
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'netinfo':
             response = get_emunet_info(url='http://localhost:3030/v1/get_emunet_info', uuid=args_op.uuid)
@@ -411,7 +461,7 @@ while True:
             if response_json['success'] == True:
                 print(fmt_data)
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
         if args_op.CmdType == 'netstat':
             response = get_emunet_info(url='http://localhost:3030/v1/get_emunet_state', uuid=args_op.uuid)
@@ -421,7 +471,7 @@ while True:
             if response_json['success'] == True:
                 print(fmt_data)
             else:
-                print("error! the message is: %s" % response_json['message'])
+                print("error! the message is: %s" % error_message(response_json))
 
     except:
         if character != '--help' and character != '' :
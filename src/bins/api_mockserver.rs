@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::sync::Mutex;
 
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{transport::Server, Request, Response, Status};
 
 use mocknet::k8s_api::mocknet_server::{Mocknet, MocknetServer};
@@ -142,17 +144,48 @@ impl Mocknet for MockServer {
         Ok(Response::new(reply))
     }
 
-    async fn exec(&self, request: Request<ExecReq>) -> Result<Response<ExecResp>, Status> {
-        let inner = request.into_inner();
+    type ExecStream = ReceiverStream<Result<ExecResp, Status>>;
+
+    async fn exec(
+        &self,
+        request: Request<tonic::Streaming<ExecReq>>,
+    ) -> Result<Response<Self::ExecStream>, Status> {
+        let mut stream = request.into_inner();
+        let first = stream
+            .message()
+            .await?
+            .ok_or(Status::invalid_argument("missing exec request"))?;
         println!("---------Got a new exec request---------");
         println!(
             "executing command '{}' on pod {}",
-            inner.cmd, inner.pod_name
+            first.cmd, first.pod_name
         );
 
-        Ok(Response::new(ExecResp {
-            std_out: String::new(),
-        }))
+        // echo the stdin back as stdout until the client closes the stream
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Ok(Some(req)) = stream.message().await {
+                let resp = ExecResp {
+                    std_out: req.std_in,
+                    std_err: Vec::new(),
+                    exited: false,
+                    exit_code: 0,
+                };
+                if tx.send(Ok(resp)).await.is_err() {
+                    return;
+                }
+            }
+            let _ = tx
+                .send(Ok(ExecResp {
+                    std_out: Vec::new(),
+                    std_err: Vec::new(),
+                    exited: true,
+                    exit_code: 0,
+                }))
+                .await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 }
 
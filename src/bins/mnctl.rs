@@ -13,6 +13,8 @@ pub async fn main() -> Result<(), Box<dyn StdError>> {
         }
     };
 
+    mnctl_util::set_token(arg.token.clone());
+
     match arg.subcmd {
         UserSubcmd::History => {
             match list_user_history::mnctl_user_history(&arg.user, &arg.warp_addr).await {
@@ -20,12 +22,26 @@ pub async fn main() -> Result<(), Box<dyn StdError>> {
                 _ => {}
             };
         }
+        UserSubcmd::Quota => {
+            match user_info::mnctl_user_info(&arg.user, &arg.warp_addr).await {
+                Err(msg) => println!("{}", msg),
+                _ => {}
+            };
+        }
+        UserSubcmd::Calendar => {
+            match list_reservations::mnctl_calendar(&arg.warp_addr).await {
+                Err(msg) => println!("{}", msg),
+                _ => {}
+            };
+        }
         UserSubcmd::NetworkOp(emunet_name, subcmd) => match subcmd {
-            NetworkSubcmd::Update(input_file) => {
+            NetworkSubcmd::Update(input_file, format, id_map) => {
                 match emunet_update::mnctl_network_update(
                     &arg.user,
                     &emunet_name,
                     &input_file,
+                    format.as_deref(),
+                    id_map.as_deref(),
                     &arg.warp_addr,
                 )
                 .await
@@ -34,6 +50,49 @@ pub async fn main() -> Result<(), Box<dyn StdError>> {
                     _ => {}
                 };
             }
+            NetworkSubcmd::Validate(input_file, format, max_degree) => {
+                match topology_validation::mnctl_network_validate(
+                    &arg.user,
+                    &emunet_name,
+                    &input_file,
+                    format.as_deref(),
+                    max_degree,
+                    &arg.warp_addr,
+                )
+                .await
+                {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            NetworkSubcmd::Export(format, output_file) => {
+                match get_emunet_info::mnctl_network_export(
+                    &arg.user,
+                    &emunet_name,
+                    &format,
+                    output_file.as_deref(),
+                    &arg.warp_addr,
+                )
+                .await
+                {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            NetworkSubcmd::Generate(shape, output_file) => {
+                match topology_generation::mnctl_network_generate(
+                    &arg.user,
+                    &emunet_name,
+                    &shape,
+                    output_file.as_deref(),
+                    &arg.warp_addr,
+                )
+                .await
+                {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
             NetworkSubcmd::Restore(history_index) => {
                 match emunet_update::mnctl_network_restore(
                     &arg.user,
@@ -138,8 +197,75 @@ pub async fn main() -> Result<(), Box<dyn StdError>> {
                     _ => {}
                 }
             }
+            NetworkSubcmd::Exec(dev_id, cmd) => {
+                match exec_session::mnctl_network_exec(
+                    &arg.user,
+                    &emunet_name,
+                    dev_id,
+                    &cmd,
+                    &arg.warp_addr,
+                )
+                .await
+                {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            NetworkSubcmd::BatchExec(dev_ids, cmd) => {
+                match execute_batch::mnctl_network_batch_exec(
+                    &arg.user,
+                    &emunet_name,
+                    dev_ids,
+                    &cmd,
+                    &arg.warp_addr,
+                )
+                .await
+                {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            NetworkSubcmd::Renew(lease_secs) => {
+                match lease_renewal::mnctl_network_renew(
+                    &arg.user,
+                    &emunet_name,
+                    lease_secs,
+                    &arg.warp_addr,
+                )
+                .await
+                {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
             _ => {}
         },
+        UserSubcmd::Admin(subcmd) => match subcmd {
+            AdminSubcmd::Migrate(dry_run) => {
+                match schema_migration::mnctl_admin_migrate(dry_run, &arg.warp_addr).await {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            AdminSubcmd::Backup(file_path) => {
+                match state_export::mnctl_admin_backup(&file_path, &arg.warp_addr).await {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            AdminSubcmd::Restore(file_path) => {
+                match state_import::mnctl_admin_restore(&file_path, &arg.warp_addr).await {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+            AdminSubcmd::Fsck(repair) => {
+                match consistency_check::mnctl_admin_fsck(repair, &arg.warp_addr).await {
+                    Err(msg) => println!("{}", msg),
+                    _ => {}
+                }
+            }
+        },
     }
 
     Ok(())
@@ -1,3 +1,6 @@
+// the route filters of warp are nested deeply
+#![recursion_limit = "256"]
+
 use std::error::Error as StdError;
 
 use tokio::fs::read_to_string;
@@ -16,7 +19,7 @@ pub async fn main() -> Result<(), Box<dyn StdError>> {
         .warp_addr
         .parse::<std::net::SocketAddr>()
         .expect("invalid warp listening address");
-    let connector = new_connector(format!("grpc://{}", &arg.indradb_addr)).await?;
+    let connector = new_connector(arg.storage).await?;
 
     if let Some(config_file) = arg.cluster_config_path {
         let json_str = read_to_string(&config_file).await?;
@@ -42,21 +45,51 @@ pub async fn main() -> Result<(), Box<dyn StdError>> {
         }
     };
 
-    let routes = user_registration::build_filter(connector.clone());
-    let routes = routes.or(emunet_creation::build_filter(connector.clone()));
-    let routes = routes.or(list_all::build_filter(connector.clone()));
-    let routes = routes.or(list_emunet::build_filter(connector.clone()));
-    let routes = routes.or(user_deletion::build_filter(connector.clone()));
-    let routes = routes.or(emunet_init::build_filter(connector.clone()));
-    let routes = routes.or(emunet_deletion::build_filter(connector.clone()));
-    let routes = routes.or(get_emunet_info::build_filter(connector.clone()));
-    let routes = routes.or(get_emunet_state::build_filter(connector.clone()));
-    let routes = routes.or(emunet_update::build_filter(connector.clone()));
-    let routes = routes.or(add_nodes::build_filter(connector.clone()));
-    let routes = routes.or(clear_garbage_servers::build_filter(connector.clone()));
-    let routes = routes.or(list_user_history::build_filter(connector.clone()));
-    let routes = routes.or(route_command::build_filter(connector.clone()));
-    let routes = routes.or(execute_command::build_filter(connector.clone()));
+    match migrate(&connector).await? {
+        Ok(steps) => {
+            for step in steps {
+                println!("migrated the database schema to version {}", step);
+            }
+        }
+        Err(e) => {
+            println!("{}", e);
+            return Ok(());
+        }
+    }
+
+    // check the stored state without serving the apis
+    if arg.fsck {
+        let violations = fsck(&connector, arg.repair).await?;
+        if violations.len() == 0 {
+            println!("no violation is found");
+        }
+        for (message, repaired) in violations {
+            if repaired {
+                println!("repaired: {}", message);
+            } else {
+                println!("{}", message);
+            }
+        }
+        return Ok(());
+    }
+
+    let admin_token = match arg.admin_token {
+        Some(token) => token,
+        None => {
+            let token = auth::generate_token();
+            println!("admin token: {}", token);
+            token
+        }
+    };
+    auth::set_admin_token(admin_token);
+    set_body_limit(arg.body_limit);
+    set_topology_limit(arg.topology_limit);
+
+    let routes = build_routes(connector.clone()).recover(handle_rejection);
+
+    tokio::spawn(emunet_deletion::lease_reaper(connector.clone()));
+    tokio::spawn(emunet_creation::reservation_starter(connector.clone()));
+    tokio::spawn(emunet_creation::ticket_scheduler(connector.clone()));
 
     warp::serve(routes).run(warp_socket_addr).await;
     Ok(())
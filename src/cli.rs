@@ -1,14 +1,35 @@
 use clap::{App, Arg, SubCommand};
 
+use crate::database::StorageConfig;
+use crate::topology::Shape;
+
 pub struct CliArg {
     pub warp_addr: String,
-    pub indradb_addr: String,
+    pub storage: StorageConfig,
     pub cluster_config_path: Option<String>,
+    pub admin_token: Option<String>,
+    pub body_limit: u64,
+    pub topology_limit: u64,
+    pub fsck: bool,
+    pub repair: bool,
 }
 
 const WARP_ADDR: &str = "WARP_ADDR";
 const INDRADB_ADDR: &str = "INDRADB_ADDR";
+const STORAGE: &str = "STORAGE";
+const STORAGE_PATH: &str = "STORAGE_PATH";
 const CLUSTER_CONFIG_PATH: &str = "CLUSTER_CONFIG_PATH";
+const ADMIN_TOKEN: &str = "ADMIN_TOKEN";
+const BODY_LIMIT: &str = "BODY_LIMIT";
+const TOPOLOGY_LIMIT: &str = "TOPOLOGY_LIMIT";
+const FSCK: &str = "FSCK";
+const REPAIR: &str = "REPAIR";
+
+fn is_size(s: String) -> Result<(), String> {
+    s.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("{} is not a size in bytes", s))
+}
 
 pub fn parse_cli_arg() -> CliArg {
     let warp_addr_arg = Arg::with_name(WARP_ADDR)
@@ -25,34 +46,115 @@ pub fn parse_cli_arg() -> CliArg {
         .takes_value(true)
         .default_value("127.0.0.1:27615");
 
+    let storage_arg = Arg::with_name(STORAGE)
+        .help("Where the data is stored, remote uses the indradb server at --indradb-addr")
+        .long("storage")
+        .value_name(STORAGE)
+        .takes_value(true)
+        .possible_values(&["remote", "rocksdb", "sled", "memory"])
+        .default_value("remote");
+
+    let storage_path_arg = Arg::with_name(STORAGE_PATH)
+        .help("Directory of the embedded rocksdb or sled datastore")
+        .long("storage-path")
+        .value_name(STORAGE_PATH)
+        .takes_value(true)
+        .default_value("mocknet_data");
+
     let cluster_config_path_arg = Arg::with_name(CLUSTER_CONFIG_PATH)
         .help("Cluster configure file path")
         .long("cluster-config")
         .value_name(CLUSTER_CONFIG_PATH)
         .takes_value(true);
 
+    let admin_token_arg = Arg::with_name(ADMIN_TOKEN)
+        .help("Bearer token of the admin, a random one is generated if absent")
+        .long("admin-token")
+        .value_name(ADMIN_TOKEN)
+        .env("MOCKNET_ADMIN_TOKEN")
+        .takes_value(true);
+
+    let body_limit_arg = Arg::with_name(BODY_LIMIT)
+        .help("Size limit in bytes of the JSON request bodies")
+        .long("body-limit")
+        .value_name(BODY_LIMIT)
+        .takes_value(true)
+        .validator(is_size)
+        .default_value("1048576");
+
+    let topology_limit_arg = Arg::with_name(TOPOLOGY_LIMIT)
+        .help("Size limit in bytes of the decompressed topology uploads")
+        .long("topology-limit")
+        .value_name(TOPOLOGY_LIMIT)
+        .takes_value(true)
+        .validator(is_size)
+        .default_value("67108864");
+
+    let fsck_arg = Arg::with_name(FSCK)
+        .help("Check the stored state and exit without serving")
+        .long("fsck");
+
+    let repair_arg = Arg::with_name(REPAIR)
+        .help("Repair the violations found by --fsck")
+        .long("repair")
+        .requires(FSCK);
+
     let matches = App::new("mocknet-server")
         .arg(&warp_addr_arg)
         .arg(&indradb_addr_arg)
+        .arg(&storage_arg)
+        .arg(&storage_path_arg)
         .arg(&cluster_config_path_arg)
+        .arg(&admin_token_arg)
+        .arg(&body_limit_arg)
+        .arg(&topology_limit_arg)
+        .arg(&fsck_arg)
+        .arg(&repair_arg)
         .get_matches();
 
+    let storage_path = matches.value_of(STORAGE_PATH).unwrap().to_string();
+    let storage = match matches.value_of(STORAGE).unwrap() {
+        "rocksdb" => StorageConfig::Rocksdb(storage_path),
+        "sled" => StorageConfig::Sled(storage_path),
+        "memory" => StorageConfig::Memory,
+        _ => StorageConfig::Remote(format!(
+            "grpc://{}",
+            matches.value_of(INDRADB_ADDR).unwrap()
+        )),
+    };
+
     CliArg {
         warp_addr: matches.value_of(WARP_ADDR).unwrap().to_string(),
-        indradb_addr: matches.value_of(INDRADB_ADDR).unwrap().to_string(),
+        storage,
         cluster_config_path: matches.value_of(CLUSTER_CONFIG_PATH).map(|s| s.to_string()),
+        admin_token: matches.value_of(ADMIN_TOKEN).map(|s| s.to_string()),
+        body_limit: matches.value_of(BODY_LIMIT).unwrap().parse().unwrap(),
+        topology_limit: matches.value_of(TOPOLOGY_LIMIT).unwrap().parse().unwrap(),
+        fsck: matches.is_present(FSCK),
+        repair: matches.is_present(REPAIR),
     }
 }
 #[derive(Debug)]
 pub struct CtlArg {
     pub user: String,
+    pub token: Option<String>,
     pub warp_addr: String,
     pub subcmd: UserSubcmd,
 }
 #[derive(Debug)]
 pub enum UserSubcmd {
     History,
+    Quota,
+    Calendar,
     NetworkOp(String, NetworkSubcmd),
+    Admin(AdminSubcmd),
+}
+#[derive(Debug)]
+pub enum AdminSubcmd {
+    Migrate(bool),
+    Backup(String),
+    Restore(String),
+    Fsck(bool),
 }
 #[derive(Debug)]
 pub enum NetworkSubcmd {
@@ -63,16 +165,89 @@ pub enum NetworkSubcmd {
     Disconnect(u64, u64),
     ConnectionHistory,
     Restore(u64),
-    Update(String),
+    Update(String, Option<String>, Option<String>),
+    Export(String, Option<String>),
+    Generate(Shape, Option<String>),
+    Validate(String, Option<String>, Option<u64>),
+    Exec(u64, String),
+    BatchExec(Option<Vec<u64>>, String),
+    Renew(u64),
 }
 
 const USERNAME: &str = "USERNAME";
+const TOKEN: &str = "TOKEN";
 const DEVID: &str = "DEVID";
 const SRCID: &str = "SRCID";
 const DSTID: &str = "DSTID";
 const HISTORYIDX: &str = "HISTORYIDX";
 const FILEPATH: &str = "FILEPATH";
 const NETWORKNAME: &str = "NETWORKNAME";
+const CMD: &str = "CMD";
+const DEVS: &str = "DEVS";
+const BATCHCMD: &str = "BATCHCMD";
+const LEASESECS: &str = "LEASESECS";
+const FORMAT: &str = "FORMAT";
+const IDMAP: &str = "IDMAP";
+const SHAPE: &str = "SHAPE";
+const MAXDEGREE: &str = "MAXDEGREE";
+
+// the value of a numeric argument of the generate subcommand
+fn shape_arg<T: std::str::FromStr>(
+    matches: &clap::ArgMatches,
+    name: &str,
+    shape: &str,
+) -> Result<T, String> {
+    matches
+        .value_of(name)
+        .ok_or(format!("{} topology needs --{}", shape, name))?
+        .parse::<T>()
+        .map_err(|_| format!("--{} should be a valid number", name))
+}
+
+fn parse_shape(matches: &clap::ArgMatches) -> Result<Shape, String> {
+    let shape = matches.value_of(SHAPE).ok_or("missing shape".to_string())?;
+    let seed = match matches.value_of("seed") {
+        Some(seed) => seed
+            .parse::<u64>()
+            .map_err(|_| "--seed should be a valid positive integer".to_string())?,
+        None => 0,
+    };
+    let res = match shape {
+        "line" => Shape::Line {
+            nodes: shape_arg(matches, "nodes", shape)?,
+        },
+        "ring" => Shape::Ring {
+            nodes: shape_arg(matches, "nodes", shape)?,
+        },
+        "star" => Shape::Star {
+            nodes: shape_arg(matches, "nodes", shape)?,
+        },
+        "grid" | "torus" => Shape::Grid {
+            rows: shape_arg(matches, "rows", shape)?,
+            cols: shape_arg(matches, "cols", shape)?,
+            torus: shape == "torus",
+        },
+        "fat-tree" => Shape::FatTree {
+            k: shape_arg(matches, "k", shape)?,
+        },
+        "leaf-spine" => Shape::LeafSpine {
+            leaves: shape_arg(matches, "leaves", shape)?,
+            spines: shape_arg(matches, "spines", shape)?,
+        },
+        "erdos-renyi" => Shape::ErdosRenyi {
+            nodes: shape_arg(matches, "nodes", shape)?,
+            p: shape_arg(matches, "p", shape)?,
+            seed,
+        },
+        "barabasi-albert" => Shape::BarabasiAlbert {
+            nodes: shape_arg(matches, "nodes", shape)?,
+            m: shape_arg(matches, "m", shape)?,
+            seed,
+        },
+        _ => return Err(format!("unknown shape {}", shape)),
+    };
+    Ok(res)
+}
 
 pub fn parse_ctl_arg() -> Result<CtlArg, String> {
     let username = Arg::with_name(USERNAME)
@@ -81,6 +256,14 @@ pub fn parse_ctl_arg() -> Result<CtlArg, String> {
         .value_name(USERNAME)
         .takes_value(true);
 
+    let token = Arg::with_name(TOKEN)
+        .help("bearer token returned when the user is registered")
+        .short("t")
+        .long("token")
+        .value_name(TOKEN)
+        .env("MNCTL_TOKEN")
+        .takes_value(true);
+
     let warp_addr_arg = Arg::with_name(WARP_ADDR)
         .help("Warp server listening address")
         .long("warp-addr")
@@ -155,11 +338,201 @@ pub fn parse_ctl_arg() -> Result<CtlArg, String> {
                 .value_name(FILEPATH)
                 .help("file path that stores the input network format")
                 .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(FORMAT)
+                .long("format")
+                .value_name(FORMAT)
+                .help("format of the input file, guessed from the file extension if absent")
+                .possible_values(&["json", "graphml", "gml", "dot", "clab"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(IDMAP)
+                .long("id-map")
+                .value_name(IDMAP)
+                .help("file to write the device ids assigned to the non-integer node ids into")
+                .takes_value(true),
+        );
+    let validate = SubCommand::with_name("validate")
+        .about("check an input file against the emulation network without applying it")
+        .arg(
+            Arg::with_name(FILEPATH)
+                .value_name(FILEPATH)
+                .help("file path that stores the input network format")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(FORMAT)
+                .long("format")
+                .value_name(FORMAT)
+                .help("format of the input file, guessed from the file extension if absent")
+                .possible_values(&["json", "graphml", "gml", "dot", "clab"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(MAXDEGREE)
+                .long("max-degree")
+                .value_name(MAXDEGREE)
+                .help("number of interfaces that a device can have")
+                .takes_value(true),
+        );
+    let exec = SubCommand::with_name("exec")
+        .about("run an interactive command on a device, or a command on many devices")
+        .arg(
+            Arg::with_name(DEVID)
+                .value_name(DEVID)
+                .help("ID of the device to run the command on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(CMD)
+                .value_name(CMD)
+                .help("command to run")
+                .takes_value(true)
+                .default_value("/bin/sh"),
+        )
+        .arg(
+            Arg::with_name("all")
+                .long("all")
+                .help("run the command on all the devices in parallel")
+                .conflicts_with_all(&[DEVID, DEVS])
+                .requires(BATCHCMD),
+        )
+        .arg(
+            Arg::with_name(DEVS)
+                .long("devs")
+                .value_name(DEVS)
+                .help("comma separated IDs of the devices to run the command on in parallel")
+                .takes_value(true)
+                .conflicts_with(DEVID)
+                .requires(BATCHCMD),
+        )
+        .arg(
+            Arg::with_name(BATCHCMD)
+                .long("cmd")
+                .value_name(CMD)
+                .help("command to run on the devices given by --all or --devs")
+                .takes_value(true)
+                .conflicts_with(DEVID),
+        );
+    let export = SubCommand::with_name("export")
+        .about("write the emulation network with its placement and addresses to a file")
+        .arg(
+            Arg::with_name(FORMAT)
+                .long("format")
+                .value_name(FORMAT)
+                .help("format of the output")
+                .possible_values(&["json", "graphml", "dot", "clab"])
+                .default_value("json")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(FILEPATH)
+                .value_name(FILEPATH)
+                .help("file path to write to, the output is printed if absent")
+                .takes_value(true),
+        );
+    let mut generate = SubCommand::with_name("generate")
+        .about("update the emulation network to a generated topology")
+        .arg(
+            Arg::with_name(SHAPE)
+                .value_name(SHAPE)
+                .help("shape of the topology")
+                .possible_values(&[
+                    "line",
+                    "ring",
+                    "star",
+                    "grid",
+                    "torus",
+                    "fat-tree",
+                    "leaf-spine",
+                    "erdos-renyi",
+                    "barabasi-albert",
+                ])
+                .takes_value(true),
+        );
+    for (name, value_name, help) in [
+        ("nodes", "NODES", "number of devices"),
+        ("rows", "ROWS", "number of rows of the grid"),
+        ("cols", "COLS", "number of columns of the grid"),
+        ("k", "K", "number of ports of the fat-tree switches"),
+        ("leaves", "LEAVES", "number of leaf switches"),
+        ("spines", "SPINES", "number of spine switches"),
+        ("p", "P", "probability that two devices are linked"),
+        ("m", "M", "number of links added with every device"),
+        ("seed", "SEED", "seed of the random topologies"),
+    ]
+    .iter()
+    {
+        generate = generate.arg(
+            Arg::with_name(name)
+                .long(name)
+                .value_name(value_name)
+                .help(help)
+                .takes_value(true),
+        );
+    }
+    let generate = generate.arg(
+        Arg::with_name("output")
+            .long("output")
+            .value_name(FILEPATH)
+            .help("write the topology to a file for update instead of applying it")
+            .takes_value(true),
+    );
+    let renew = SubCommand::with_name("renew")
+        .about("renew the lease of the emulation network")
+        .arg(
+            Arg::with_name(LEASESECS)
+                .value_name(LEASESECS)
+                .help("number of seconds from now until the lease expires")
+                .takes_value(true),
         );
 
     // user subcommand
     let history =
         SubCommand::with_name("history").about("show the history of this emulation network");
+    let quota = SubCommand::with_name("quota").about("show the resource usage against the quota");
+    let calendar =
+        SubCommand::with_name("calendar").about("show the capacity reserved by all the users");
+    // admin subcommand
+    let migrate = SubCommand::with_name("migrate")
+        .about("upgrade the stored records to the schema of the server")
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("only list the pending migration steps"),
+        );
+    let backup = SubCommand::with_name("backup")
+        .about("save the whole control-plane state to a file")
+        .arg(
+            Arg::with_name(FILEPATH)
+                .value_name(FILEPATH)
+                .help("file path to save the archive to")
+                .takes_value(true),
+        );
+    let restore_state = SubCommand::with_name("restore")
+        .about("replace the whole control-plane state with an archive")
+        .arg(
+            Arg::with_name(FILEPATH)
+                .value_name(FILEPATH)
+                .help("file path of the archive saved by backup")
+                .takes_value(true),
+        );
+    let fsck = SubCommand::with_name("fsck")
+        .about("check the stored state for broken invariants")
+        .arg(
+            Arg::with_name("repair")
+                .long("repair")
+                .help("repair the violations that can be fixed automatically"),
+        );
+    let admin = SubCommand::with_name("admin")
+        .about("database administration, requires the admin token")
+        .subcommand(migrate)
+        .subcommand(backup)
+        .subcommand(restore_state)
+        .subcommand(fsck);
+
     let network_op = SubCommand::with_name("network")
         .about("operations on the emulation network")
         .arg(
@@ -174,23 +547,41 @@ pub fn parse_ctl_arg() -> Result<CtlArg, String> {
         .subcommand(connect)
         .subcommand(disconnect)
         .subcommand(restore)
-        .subcommand(update);
+        .subcommand(update)
+        .subcommand(validate)
+        .subcommand(export)
+        .subcommand(generate)
+        .subcommand(exec)
+        .subcommand(renew);
 
     let matches = App::new("ctl-cli")
         .arg(&username)
+        .arg(&token)
         .arg(&warp_addr_arg)
         .subcommand(history)
+        .subcommand(quota)
+        .subcommand(calendar)
         .subcommand(network_op)
+        .subcommand(admin)
         .get_matches();
 
+    // the admin subcommands do not operate on a user
+    let user = match matches.value_of(USERNAME) {
+        Some(user) => user.to_string(),
+        None if matches.subcommand_matches("admin").is_some() => String::new(),
+        None => return Err("missing user name".to_string()),
+    };
+
     let res = CtlArg {
-        user: matches
-            .value_of(USERNAME)
-            .ok_or("missing user name".to_string())?
-            .to_string(),
+        user,
+        token: matches.value_of(TOKEN).map(|s| s.to_string()),
         warp_addr: matches.value_of(WARP_ADDR).unwrap().to_string(),
         subcmd: if let Some(_) = matches.subcommand_matches("history") {
             UserSubcmd::History
+        } else if let Some(_) = matches.subcommand_matches("quota") {
+            UserSubcmd::Quota
+        } else if let Some(_) = matches.subcommand_matches("calendar") {
+            UserSubcmd::Calendar
         } else if let Some(matches) = matches.subcommand_matches("network") {
             let network_subcmd = if let Some(_) = matches.subcommand_matches("info") {
                 NetworkSubcmd::Info
@@ -279,6 +670,76 @@ pub fn parse_ctl_arg() -> Result<CtlArg, String> {
                         .value_of("FILEPATH")
                         .ok_or("missing file path".to_string())?
                         .to_string(),
+                    matches.value_of(FORMAT).map(|s| s.to_string()),
+                    matches.value_of(IDMAP).map(|s| s.to_string()),
+                )
+            } else if let Some(matches) = matches.subcommand_matches("validate") {
+                NetworkSubcmd::Validate(
+                    matches
+                        .value_of(FILEPATH)
+                        .ok_or("missing file path".to_string())?
+                        .to_string(),
+                    matches.value_of(FORMAT).map(|s| s.to_string()),
+                    match matches.value_of(MAXDEGREE) {
+                        Some(max_degree) => Some(max_degree.parse::<u64>().map_err(|_| {
+                            "max degree should be a valid positive integer".to_string()
+                        })?),
+                        None => None,
+                    },
+                )
+            } else if let Some(matches) = matches.subcommand_matches("export") {
+                NetworkSubcmd::Export(
+                    matches.value_of(FORMAT).unwrap().to_string(),
+                    matches.value_of(FILEPATH).map(|s| s.to_string()),
+                )
+            } else if let Some(matches) = matches.subcommand_matches("generate") {
+                NetworkSubcmd::Generate(
+                    parse_shape(matches)?,
+                    matches.value_of("output").map(|s| s.to_string()),
+                )
+            } else if let Some(matches) = matches.subcommand_matches("exec") {
+                if matches.is_present("all") || matches.is_present(DEVS) {
+                    let dev_idxs = match matches.value_of(DEVS) {
+                        Some(devs) => Some(
+                            devs.split(',')
+                                .map(|dev| dev.trim().parse::<u64>())
+                                .collect::<Result<Vec<_>, _>>()
+                                .map_err(|_| {
+                                    "device ids should be valid positive integers".to_string()
+                                })?,
+                        ),
+                        None => None,
+                    };
+                    NetworkSubcmd::BatchExec(
+                        dev_idxs,
+                        matches
+                            .value_of(BATCHCMD)
+                            .ok_or("missing command".to_string())?
+                            .to_string(),
+                    )
+                } else {
+                    NetworkSubcmd::Exec(
+                        matches
+                            .value_of(DEVID)
+                            .ok_or("missing device id".to_string())?
+                            .to_string()
+                            .parse::<u64>()
+                            .map_err(|_| {
+                                "device id should be a valid positive integer".to_string()
+                            })?,
+                        matches.value_of(CMD).unwrap().to_string(),
+                    )
+                }
+            } else if let Some(matches) = matches.subcommand_matches("renew") {
+                NetworkSubcmd::Renew(
+                    matches
+                        .value_of(LEASESECS)
+                        .ok_or("missing lease duration".to_string())?
+                        .to_string()
+                        .parse::<u64>()
+                        .map_err(|_| {
+                            "lease duration should be a valid positive integer".to_string()
+                        })?,
                 )
             } else {
                 return Err("missing subcommand after network".to_string());
@@ -290,6 +751,28 @@ pub fn parse_ctl_arg() -> Result<CtlArg, String> {
                     .to_string(),
                 network_subcmd,
             )
+        } else if let Some(matches) = matches.subcommand_matches("admin") {
+            if let Some(matches) = matches.subcommand_matches("migrate") {
+                UserSubcmd::Admin(AdminSubcmd::Migrate(matches.is_present("dry-run")))
+            } else if let Some(matches) = matches.subcommand_matches("backup") {
+                UserSubcmd::Admin(AdminSubcmd::Backup(
+                    matches
+                        .value_of(FILEPATH)
+                        .ok_or("missing file path".to_string())?
+                        .to_string(),
+                ))
+            } else if let Some(matches) = matches.subcommand_matches("restore") {
+                UserSubcmd::Admin(AdminSubcmd::Restore(
+                    matches
+                        .value_of(FILEPATH)
+                        .ok_or("missing file path".to_string())?
+                        .to_string(),
+                ))
+            } else if let Some(matches) = matches.subcommand_matches("fsck") {
+                UserSubcmd::Admin(AdminSubcmd::Fsck(matches.is_present("repair")))
+            } else {
+                return Err("missing subcommand after admin".to_string());
+            }
         } else {
             return Err("missing subcommand after user".to_string());
         },
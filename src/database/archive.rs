@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::fsck::{self, Violation};
+use super::helpers::{self, EmunetWrite};
+use super::migration::{self, SCHEMA_VERSION};
+use super::storage::Transaction;
+use crate::emunet::{self, Device, DeviceMeta, Emunet, EmunetState, Link, LinkMeta};
+use crate::errors::Error;
+
+// the layout of the archive itself, independent of the schema version of
+// the records inside
+pub(crate) static ARCHIVE_VERSION: u64 = 1;
+
+// the properties of the core vertex that make up the control-plane state,
+// the retired emunets are kept in the user map
+static CORE_PROPERTIES: [&str; 5] = [
+    "user_map",
+    "cluster_info",
+    "emunet_id_allocator",
+    "garbage_servers",
+    "ticket_queue",
+];
+
+// EmunetRecord keeps an emunet together with the device vertices and the
+// link edges that hang off it.
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct EmunetRecord {
+    pub(crate) emunet: serde_json::Value,
+    pub(crate) devices: Vec<(Uuid, serde_json::Value)>,
+    pub(crate) links: Vec<serde_json::Value>,
+}
+
+// Archive is a dump of the whole control-plane state, the records are
+// kept as stored so that an archive can be loaded by a newer server.
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct Archive {
+    pub(crate) archive_version: u64,
+    pub(crate) schema_version: u64,
+    pub(crate) core: HashMap<String, serde_json::Value>,
+    pub(crate) emunets: Vec<EmunetRecord>,
+}
+
+// the devices and links are sorted so that exporting the same state twice
+// produces the same archive
+fn emunet_record(emunet: &Emunet) -> EmunetRecord {
+    // emunets stored in the old format get their device vertices here
+    let device_vertices = emunet.assign_device_vertices();
+    let devices = emunet.devices();
+    let mut dev_ids: Vec<u64> = devices.keys().map(|id| *id).collect();
+    dev_ids.sort();
+    EmunetRecord {
+        emunet: serde_json::to_value(emunet).unwrap(),
+        devices: dev_ids
+            .iter()
+            .map(|dev_id| {
+                (
+                    device_vertices.get(dev_id).unwrap().clone(),
+                    serde_json::to_value(devices.get(dev_id).unwrap()).unwrap(),
+                )
+            })
+            .collect(),
+        links: dev_ids
+            .iter()
+            .map(|dev_id| {
+                let links = devices.get(dev_id).unwrap().links();
+                let mut links: Vec<&Link<LinkMeta>> = links.iter().collect();
+                links.sort_by_key(|link| link.link_id());
+                links
+                    .into_iter()
+                    .map(|link| serde_json::to_value(link).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .flatten()
+            .collect(),
+    }
+}
+
+pub(crate) async fn export(tran: &mut Transaction) -> Result<Archive, Error> {
+    let mut core = HashMap::new();
+    for name in CORE_PROPERTIES.iter() {
+        if let Some(jv) =
+            helpers::get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), name).await?
+        {
+            core.insert(name.to_string(), jv);
+        }
+    }
+
+    let mut emunet_uuids: Vec<Uuid> = helpers::get_user_map(tran)
+        .await?
+        .values()
+        .map(|user| user.emunet_uuids())
+        .flatten()
+        .collect();
+    emunet_uuids.sort();
+    let mut emunets = Vec::new();
+    for emunet_uuid in emunet_uuids {
+        if let Some(emunet) = helpers::get_emunet(tran, emunet_uuid).await? {
+            emunets.push(emunet_record(&emunet));
+        }
+    }
+
+    Ok(Archive {
+        archive_version: ARCHIVE_VERSION,
+        schema_version: helpers::get_schema_version(tran).await?,
+        core,
+        emunets,
+    })
+}
+
+// the emunet of the record with its devices and links loaded
+fn load_record(record: &EmunetRecord) -> Result<Emunet, String> {
+    let emunet: Emunet = serde_json::from_value(record.emunet.clone())
+        .map_err(|e| format!("invalid emunet in the archive: {}", e))?;
+    let mut devices = Vec::new();
+    for (vid, jv) in record.devices.iter() {
+        let dev: Device<DeviceMeta, LinkMeta> = serde_json::from_value(jv.clone())
+            .map_err(|e| format!("invalid device in the archive: {}", e))?;
+        devices.push((vid.clone(), dev));
+    }
+    let mut links = Vec::new();
+    for jv in record.links.iter() {
+        let link: Link<LinkMeta> = serde_json::from_value(jv.clone())
+            .map_err(|e| format!("invalid link in the archive: {}", e))?;
+        if devices.iter().all(|(_, dev)| dev.id() != link.link_id().0) {
+            return Err(format!(
+                "link {:?} of emunet {} has no source device",
+                link.link_id(),
+                emunet.emunet_uuid()
+            ));
+        }
+        links.push(link);
+    }
+    emunet.load_graph(devices, links);
+    Ok(emunet)
+}
+
+// replace the whole control-plane state with the archive, the stored
+// emunets missing from the archive are deleted from the database but not
+// from the k8s cluster, return the violations found in the imported state
+pub(crate) async fn import(
+    tran: &mut Transaction,
+    archive: Archive,
+) -> Result<Result<Vec<Violation>, String>, Error> {
+    if archive.archive_version != ARCHIVE_VERSION {
+        return Ok(Err(format!(
+            "unsupported archive version {}",
+            archive.archive_version
+        )));
+    }
+    if archive.schema_version > SCHEMA_VERSION {
+        return Ok(Err(format!(
+            "the archive has schema version {}, which is newer than version {} of this server",
+            archive.schema_version, SCHEMA_VERSION
+        )));
+    }
+    for name in CORE_PROPERTIES.iter() {
+        if !archive.core.contains_key(*name) {
+            return Ok(Err(format!("the archive misses the {}", name)));
+        }
+    }
+    let mut emunets = Vec::new();
+    for record in archive.emunets.iter() {
+        match load_record(record) {
+            Ok(emunet) => emunets.push((emunet, &record.emunet)),
+            Err(s) => return Ok(Err(s)),
+        }
+    }
+
+    // the background task of a working emunet would write its emunet back
+    // over the imported one
+    let stored_uuids: Vec<Uuid> = helpers::get_user_map(tran)
+        .await?
+        .values()
+        .map(|user| user.emunet_uuids())
+        .flatten()
+        .collect();
+    for emunet_uuid in stored_uuids.iter() {
+        if let Some(emunet) = helpers::get_emunet_header(tran, emunet_uuid.clone()).await? {
+            if let EmunetState::Working = emunet.state() {
+                return Ok(Err(format!(
+                    "emunet {} is working, retry the import after its job finishes",
+                    emunet_uuid
+                )));
+            }
+        }
+    }
+
+    // the new records are written before the stale ones are deleted, so an
+    // interrupted import never leaves the database without the emunets
+    let imported_uuids: Vec<Uuid> = emunets
+        .iter()
+        .map(|(emunet, _)| emunet.emunet_uuid())
+        .collect();
+    for (emunet, header) in emunets {
+        let emunet_uuid = emunet.emunet_uuid();
+        helpers::create_vertex(tran, emunet_uuid.clone()).await?;
+        // the stored header carries the revision that the restore expects
+        helpers::set_vertex_json_value(tran, emunet_uuid, emunet::EMUNET_NODE_PROPERTY, header)
+            .await?;
+        let fut = EmunetWrite::restore(&emunet).store(tran);
+        if !fut.await? {
+            return Err(Error::Conflict(emunet_uuid));
+        }
+    }
+    for (name, jv) in archive.core.iter() {
+        helpers::set_vertex_json_value(tran, super::CORE_INFO_ID.clone(), name, jv).await?;
+    }
+    for emunet_uuid in stored_uuids {
+        if !imported_uuids.contains(&emunet_uuid) {
+            helpers::delete_emunet(tran, emunet_uuid).await?;
+        }
+    }
+
+    // bring the records of an older archive up to date
+    helpers::set_schema_version(tran, archive.schema_version).await?;
+    if let Err(s) = migration::run_migration(tran, false).await? {
+        return Ok(Err(s));
+    }
+    Ok(Ok(fsck::check(tran, false).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    #[tokio::test]
+    async fn import_of_an_export_exports_the_same_archive() {
+        let source = testing::memory_connector().await;
+        let mut client = source.connect().await.unwrap();
+        let mut tran = client.guarded_tran().await.unwrap();
+        testing::seed_emunet(&mut tran, "alice", "line", 3).await;
+        testing::seed_emunet(&mut tran, "alice", "pair", 2).await;
+        testing::seed_emunet(&mut tran, "bob", "single", 1).await;
+        let archive = serde_json::to_value(export(&mut tran).await.unwrap()).unwrap();
+
+        let target = testing::memory_connector().await;
+        let mut client = target.connect().await.unwrap();
+        let mut tran = client.guarded_tran().await.unwrap();
+        testing::seed_emunet(&mut tran, "carol", "stale", 2).await;
+        let violations = import(&mut tran, serde_json::from_value(archive.clone()).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(violations.len(), 0);
+
+        let reexported = serde_json::to_value(export(&mut tran).await.unwrap()).unwrap();
+        assert_eq!(reexported, archive);
+    }
+}
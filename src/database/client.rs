@@ -1,44 +1,152 @@
-use std::{collections::HashMap, ops::Deref, ops::DerefMut, str::FromStr, sync::Arc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::{ops::Deref, ops::DerefMut};
 
 use super::errors::ConnectorError;
 use super::helpers;
 use super::message_queue;
 use super::message_queue::{Queue, Sender};
-use crate::emunet::{ClusterInfo, IdAllocator, User, EMUNET_NUM_POWER};
+use super::storage::{self, Storage, StorageConfig, Transaction};
+use crate::emunet::{ClusterInfo, IdAllocator, TicketQueue, User, EMUNET_NUM_POWER};
+use crate::errors::Error;
 
 use indradb_proto as proto;
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use uuid::Uuid;
 
-type ConnectorResponse = Result<(proto::Client, u64, Arc<Semaphore>), ConnectorError>;
+type ConnectorResponse = Result<(Arc<dyn Storage>, u64, Arc<LockTable>), ConnectorError>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
+// Scope lists the resources that a transaction reads or writes, the core
+// info vertex is one resource and every emunet, together with its devices
+// and jobs, is another.
+#[derive(Default)]
+pub(crate) struct Scope {
+    core: Option<Access>,
+    emunets: BTreeMap<Uuid, Access>,
+}
+
+impl Scope {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn read_core(mut self) -> Self {
+        self.core.get_or_insert(Access::Read);
+        self
+    }
+
+    pub(crate) fn write_core(mut self) -> Self {
+        self.core = Some(Access::Write);
+        self
+    }
+
+    pub(crate) fn read_emunet(mut self, emunet_uuid: Uuid) -> Self {
+        self.emunets.entry(emunet_uuid).or_insert(Access::Read);
+        self
+    }
+
+    pub(crate) fn write_emunet(mut self, emunet_uuid: Uuid) -> Self {
+        self.emunets.insert(emunet_uuid, Access::Write);
+        self
+    }
+}
+
+// the locks held by a transaction, they are released when it is dropped
+#[derive(Default)]
+struct LockGuards {
+    _read: Vec<OwnedRwLockReadGuard<()>>,
+    _write: Vec<OwnedRwLockWriteGuard<()>>,
+}
+
+impl LockGuards {
+    async fn lock(&mut self, rw_lock: Arc<RwLock<()>>, access: Access) {
+        match access {
+            Access::Read => self._read.push(rw_lock.read_owned().await),
+            Access::Write => self._write.push(rw_lock.write_owned().await),
+        }
+    }
+}
+
+// LockTable serializes the transactions that access the same resources.
+// A scoped transaction holds the global lock for reading, while a guarded
+// transaction holds it for writing and excludes every other transaction.
+// The locks are always acquired in the order of global, core and emunets
+// sorted by uuid, so that transactions can not deadlock.
+struct LockTable {
+    global: Arc<RwLock<()>>,
+    core: Arc<RwLock<()>>,
+    emunets: Mutex<HashMap<Uuid, Arc<RwLock<()>>>>,
+}
+
+impl LockTable {
+    fn new() -> Self {
+        Self {
+            global: Arc::new(RwLock::new(())),
+            core: Arc::new(RwLock::new(())),
+            emunets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn emunet_lock(&self, emunet_uuid: &Uuid) -> Arc<RwLock<()>> {
+        let mut emunets = self.emunets.lock().unwrap();
+        // drop the locks that no transaction is holding or waiting for
+        emunets.retain(|_, rw_lock| Arc::strong_count(rw_lock) > 1);
+        emunets
+            .entry(emunet_uuid.clone())
+            .or_insert_with(|| Arc::new(RwLock::new(())))
+            .clone()
+    }
+
+    async fn lock_all(&self) -> LockGuards {
+        let mut guards = LockGuards::default();
+        guards.lock(self.global.clone(), Access::Write).await;
+        guards
+    }
+
+    async fn lock_scope(&self, scope: Scope) -> LockGuards {
+        let mut guards = LockGuards::default();
+        guards.lock(self.global.clone(), Access::Read).await;
+        if let Some(access) = scope.core {
+            guards.lock(self.core.clone(), access).await;
+        }
+        for (emunet_uuid, access) in scope.emunets.into_iter() {
+            let rw_lock = self.emunet_lock(&emunet_uuid);
+            guards.lock(rw_lock, access).await;
+        }
+        guards
+    }
+}
 
 enum ConnectorMessage {
     GetClient,
     ClientFail(u64),
 }
 
-async fn do_connect(db_addr: &str) -> Result<proto::Client, ConnectorError> {
-    let endpoint = tonic::transport::Endpoint::from_str(db_addr)?;
-    Ok(proto::Client::new(endpoint).await?)
-}
 struct ConnectorBackend {
-    db_addr: String,
+    config: StorageConfig,
     client_id: u64,
-    client_opt: Option<proto::Client>,
-    semaphore: Arc<Semaphore>,
+    storage_opt: Option<Arc<dyn Storage>>,
+    lock_table: Arc<LockTable>,
     queue: Queue<ConnectorMessage, ConnectorResponse>,
 }
 
 impl ConnectorBackend {
     async fn new(
-        db_addr: &str,
+        config: StorageConfig,
         queue: Queue<ConnectorMessage, ConnectorResponse>,
     ) -> Result<Self, ConnectorError> {
-        let client = do_connect(db_addr).await?;
+        let storage = storage::open(&config).await?;
         Ok(Self {
-            db_addr: db_addr.to_string(),
+            config,
             client_id: 1,
-            client_opt: Some(client),
-            semaphore: Arc::new(Semaphore::new(1)),
+            storage_opt: Some(storage),
+            lock_table: Arc::new(LockTable::new()),
             queue,
         })
     }
@@ -49,18 +157,18 @@ impl ConnectorBackend {
             match msg {
                 None => break,
                 Some((msg, responder)) => match msg {
-                    ConnectorMessage::GetClient => match self.client_opt {
-                        Some(ref client) => {
+                    ConnectorMessage::GetClient => match self.storage_opt {
+                        Some(ref storage) => {
                             let _ = responder.send(Ok((
-                                client.clone(),
+                                storage.clone(),
                                 self.client_id,
-                                self.semaphore.clone(),
+                                self.lock_table.clone(),
                             )));
                         }
                         None => {
                             let res = tokio::time::timeout(
                                 std::time::Duration::from_millis(500),
-                                do_connect(&self.db_addr),
+                                storage::open(&self.config),
                             )
                             .await;
                             match res {
@@ -70,21 +178,26 @@ impl ConnectorBackend {
                                 Ok(Err(err)) => {
                                     let _ = responder.send(Err(err));
                                 }
-                                Ok(Ok(client)) => {
-                                    self.client_opt = Some(client.clone());
+                                Ok(Ok(storage)) => {
+                                    self.storage_opt = Some(storage.clone());
                                     self.client_id += 1;
                                     let _ = responder.send(Ok((
-                                        client,
+                                        storage,
                                         self.client_id,
-                                        self.semaphore.clone(),
+                                        self.lock_table.clone(),
                                     )));
                                 }
                             }
                         }
                     },
                     ConnectorMessage::ClientFail(client_id) => {
-                        if client_id == self.client_id && self.client_opt.is_some() {
-                            self.client_opt = None
+                        // an embedded datastore is kept open, reopening it
+                        // while the old one is in use would fail
+                        if client_id == self.client_id
+                            && self.storage_opt.is_some()
+                            && self.config.reconnectable()
+                        {
+                            self.storage_opt = None
                         }
                     }
                 },
@@ -94,9 +207,9 @@ impl ConnectorBackend {
 }
 
 pub(crate) struct Client {
-    client: proto::Client,
+    storage: Arc<dyn Storage>,
     client_id: u64,
-    semaphore: Arc<Semaphore>,
+    lock_table: Arc<LockTable>,
     sender: Sender<ConnectorMessage, ConnectorResponse>,
 }
 
@@ -107,23 +220,38 @@ impl Client {
             .send(ConnectorMessage::ClientFail(self.client_id));
     }
 
+    // a transaction that excludes every other transaction
     pub(crate) async fn guarded_tran(&mut self) -> Result<GuardedTransaction, proto::ClientError> {
-        let tran = self.client.transaction().await?;
-        let singleton_guard = self.semaphore.clone().acquire_owned().await.unwrap();
+        let tran = self.storage.transaction().await?;
+        let guards = self.lock_table.lock_all().await;
+        Ok(GuardedTransaction {
+            tran,
+            _guards: guards,
+        })
+    }
+
+    // a transaction that only excludes the transactions accessing the
+    // same resources, it must not touch anything outside of the scope
+    pub(crate) async fn scoped_tran(
+        &mut self,
+        scope: Scope,
+    ) -> Result<GuardedTransaction, proto::ClientError> {
+        let tran = self.storage.transaction().await?;
+        let guards = self.lock_table.lock_scope(scope).await;
         Ok(GuardedTransaction {
             tran,
-            _singleton_guard: singleton_guard,
+            _guards: guards,
         })
     }
 }
 
 pub(crate) struct GuardedTransaction {
-    tran: proto::Transaction,
-    _singleton_guard: OwnedSemaphorePermit,
+    tran: Transaction,
+    _guards: LockGuards,
 }
 
 impl Deref for GuardedTransaction {
-    type Target = proto::Transaction;
+    type Target = Transaction;
 
     fn deref(&self) -> &Self::Target {
         &self.tran
@@ -149,20 +277,18 @@ impl Connector {
             .send_for_response(ConnectorMessage::GetClient)
             .await?;
 
-        resp.map(move |(client, client_id, semaphore)| Client {
-            client: client,
+        resp.map(move |(storage, client_id, lock_table)| Client {
+            storage,
             client_id: client_id,
-            semaphore,
+            lock_table,
             sender: self.sender.clone(),
         })
     }
 }
 
-pub async fn new_connector<S: std::convert::AsRef<str>>(
-    db_addr: S,
-) -> Result<Connector, ConnectorError> {
+pub async fn new_connector(config: StorageConfig) -> Result<Connector, ConnectorError> {
     let (sender, queue) = message_queue::create();
-    let connector_backend = ConnectorBackend::new(db_addr.as_ref(), queue).await?;
+    let connector_backend = ConnectorBackend::new(config, queue).await?;
     let _ = tokio::spawn(connector_backend.backend_task());
     Ok(Connector { sender })
 }
@@ -170,11 +296,8 @@ pub async fn new_connector<S: std::convert::AsRef<str>>(
 pub async fn init(
     connector: &Connector,
     cluster_info: ClusterInfo,
-) -> Result<Result<(), String>, proto::ClientError> {
-    let mut client = connector
-        .connect()
-        .await
-        .map_err(|_| proto::ClientError::ChannelClosed)?;
+) -> Result<Result<(), String>, Error> {
+    let mut client = connector.connect().await?;
     let mut tran = client.guarded_tran().await?;
 
     let res = helpers::create_vertex(&mut tran, super::CORE_INFO_ID.clone()).await?;
@@ -183,6 +306,8 @@ pub async fn init(
             helpers::set_user_map(&mut tran, HashMap::<String, User>::new()).await?;
             helpers::set_cluster_info(&mut tran, cluster_info).await?;
             helpers::set_garbage_servesr(&mut tran, Vec::new()).await?;
+            helpers::set_ticket_queue(&mut tran, TicketQueue::default()).await?;
+            helpers::set_schema_version(&mut tran, super::migration::SCHEMA_VERSION).await?;
 
             let allocator = IdAllocator::new();
             assert!(allocator.remaining() <= (2 as usize).pow(EMUNET_NUM_POWER));
@@ -194,11 +319,8 @@ pub async fn init(
     }
 }
 
-pub async fn init_ok(connector: &Connector) -> Result<bool, proto::ClientError> {
-    let mut client = connector
-        .connect()
-        .await
-        .map_err(|_| proto::ClientError::ChannelClosed)?;
+pub async fn init_ok(connector: &Connector) -> Result<bool, Error> {
+    let mut client = connector.connect().await?;
     let mut tran = client.guarded_tran().await?;
 
     let res =
@@ -27,6 +27,14 @@ impl From<ClientError> for ConnectorError {
     }
 }
 
+impl From<indradb::Error> for ConnectorError {
+    fn from(e: indradb::Error) -> ConnectorError {
+        Self::ConnectionError {
+            reason: format!("can not open the datastore: {}", e),
+        }
+    }
+}
+
 impl From<InvalidUri> for ConnectorError {
     fn from(e: InvalidUri) -> ConnectorError {
         Self::ConnectionError {
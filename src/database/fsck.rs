@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::helpers;
+use super::storage::Transaction;
+use super::Connector;
+use crate::emunet::{Emunet, EmunetState, IdAllocator, EMUNET_NUM_POWER};
+use crate::errors::Error;
+
+// Violation is a broken invariant of the stored state, repaired tells
+// whether the checker has fixed it.
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct Violation {
+    pub(crate) message: String,
+    pub(crate) repaired: bool,
+}
+
+struct Checker {
+    repair: bool,
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    // record a violation that can not be fixed automatically
+    fn report(&mut self, message: String) {
+        self.violations.push(Violation {
+            message,
+            repaired: false,
+        });
+    }
+
+    // record a violation, return whether the caller should fix it
+    fn fix(&mut self, message: String) -> bool {
+        self.violations.push(Violation {
+            message,
+            repaired: self.repair,
+        });
+        self.repair
+    }
+}
+
+// every emunet keeps its own id, and every other id is free
+fn check_id_allocator(
+    checker: &mut Checker,
+    emunets: &Vec<Emunet>,
+    allocator: &IdAllocator,
+) -> bool {
+    let mut in_use: BTreeMap<u8, Vec<Uuid>> = BTreeMap::new();
+    for emunet in emunets.iter() {
+        in_use
+            .entry(emunet.emunet_id())
+            .or_insert(Vec::new())
+            .push(emunet.emunet_uuid());
+    }
+
+    let mut rebuild = false;
+    for (id, owners) in in_use.iter() {
+        if owners.len() > 1 {
+            checker.report(format!(
+                "emunet id {} is shared by emunets {:?}",
+                id, owners
+            ));
+        }
+        if allocator.is_free(*id) {
+            rebuild |= checker.fix(format!(
+                "emunet id {} of emunet {} is free in the id allocator",
+                id, owners[0]
+            ));
+        }
+    }
+    for id in 0..(2 as u32).pow(EMUNET_NUM_POWER) {
+        let id = id as u8;
+        if !allocator.is_free(id) && !in_use.contains_key(&id) {
+            rebuild |= checker.fix(format!(
+                "emunet id {} is neither free nor used by any emunet",
+                id
+            ));
+        }
+    }
+    rebuild
+}
+
+// the devices placed on each server of the emunet match the devices that
+// the server holds, return whether the emunet is modified
+fn check_placement(checker: &mut Checker, emunet: &Emunet) -> bool {
+    let mut placed: HashMap<String, HashSet<u64>> = HashMap::new();
+    for (dev_id, dev) in emunet.devices().iter() {
+        placed
+            .entry(dev.server_name())
+            .or_insert(HashSet::new())
+            .insert(*dev_id);
+    }
+
+    // the background task of a working emunet writes back its own copy
+    let working = match emunet.state() {
+        EmunetState::Working => true,
+        _ => false,
+    };
+    let mut modified = false;
+    for (node_name, server) in emunet.servers().iter() {
+        let devs = placed.remove(node_name).unwrap_or(HashSet::new());
+        if *server.devs() == devs {
+            continue;
+        }
+        let message = format!(
+            "server {} of emunet {} holds devices {:?}, but devices {:?} are placed on it",
+            node_name,
+            emunet.emunet_uuid(),
+            server.devs(),
+            devs
+        );
+        if working {
+            checker.report(format!("{}, the emunet is working", message));
+        } else if checker.fix(message) {
+            server.reset_devs(devs);
+            modified = true;
+        }
+    }
+    for (node_name, devs) in placed.into_iter() {
+        checker.report(format!(
+            "devices {:?} of emunet {} are placed on server {}, which the emunet does not own",
+            devs,
+            emunet.emunet_uuid(),
+            node_name
+        ));
+    }
+    modified
+}
+
+// check the invariants of the stored state, the violations that can be
+// fixed are repaired if repair is set
+pub(crate) async fn check(tran: &mut Transaction, repair: bool) -> Result<Vec<Violation>, Error> {
+    let mut checker = Checker {
+        repair,
+        violations: Vec::new(),
+    };
+
+    // every emunet in the user map has a vertex
+    let user_map = helpers::get_user_map(tran).await?;
+    let mut entries: Vec<(String, String, Uuid)> = user_map
+        .iter()
+        .map(|(user_name, user)| {
+            user.emunet_entries()
+                .into_iter()
+                .map(move |(emunet_name, uuid)| (user_name.clone(), emunet_name, uuid))
+        })
+        .flatten()
+        .collect();
+    entries.sort();
+    let mut user_map_modified = false;
+    let mut emunets = Vec::new();
+    for (user_name, emunet_name, emunet_uuid) in entries {
+        match helpers::get_emunet(tran, emunet_uuid).await? {
+            Some(emunet) => emunets.push(emunet),
+            None => {
+                if checker.fix(format!(
+                    "emunet {} ({}) of user {} has no vertex",
+                    emunet_name, emunet_uuid, user_name
+                )) {
+                    user_map
+                        .get(&user_name)
+                        .unwrap()
+                        .delete_emunet(&emunet_name);
+                    user_map_modified = true;
+                }
+            }
+        }
+    }
+
+    let id_allocator = helpers::get_emunet_id_allocator(tran).await?;
+    let rebuild_allocator = check_id_allocator(&mut checker, &emunets, &id_allocator);
+
+    // a server is either free, owned by one emunet or waiting in the garbage
+    let mut cluster_info = helpers::get_cluster_info(tran).await?;
+    let mut garbage_servers = helpers::get_garbage_servesr(tran).await?;
+    let mut owners: BTreeMap<String, Vec<Uuid>> = BTreeMap::new();
+    for emunet in emunets.iter() {
+        for node_name in emunet.servers().keys() {
+            owners
+                .entry(node_name.clone())
+                .or_insert(Vec::new())
+                .push(emunet.emunet_uuid());
+        }
+    }
+    let mut cluster_modified = false;
+    let mut garbage_modified = false;
+    for (node_name, owner_uuids) in owners.iter() {
+        if owner_uuids.len() > 1 {
+            checker.report(format!(
+                "server {} is owned by emunets {:?}",
+                node_name, owner_uuids
+            ));
+        }
+        let is_free = cluster_info
+            .free_servers()
+            .iter()
+            .any(|s| &s.node_name == node_name);
+        if is_free
+            && checker.fix(format!(
+                "server {} of emunet {} is also free in the cluster",
+                node_name, owner_uuids[0]
+            ))
+        {
+            cluster_info.remove_server(node_name);
+            cluster_modified = true;
+        }
+        if garbage_servers.iter().any(|s| &s.node_name == node_name)
+            && checker.fix(format!(
+                "server {} of emunet {} is also in the garbage",
+                node_name, owner_uuids[0]
+            ))
+        {
+            garbage_servers.retain(|s| &s.node_name != node_name);
+            garbage_modified = true;
+        }
+    }
+    // the garbage servers wait for a manual cleanup before they are reused
+    let garbage_names: Vec<String> = garbage_servers
+        .iter()
+        .map(|s| s.node_name.clone())
+        .collect();
+    for node_name in garbage_names {
+        let is_free = cluster_info
+            .free_servers()
+            .iter()
+            .any(|s| s.node_name == node_name);
+        if is_free
+            && checker.fix(format!(
+                "server {} is both free in the cluster and in the garbage",
+                node_name
+            ))
+        {
+            cluster_info.remove_server(&node_name);
+            cluster_modified = true;
+        }
+    }
+
+    let in_use_ids: HashSet<u8> = emunets.iter().map(|e| e.emunet_id()).collect();
+    let modified_emunets: Vec<Emunet> = emunets
+        .into_iter()
+        .filter(|emunet| check_placement(&mut checker, emunet))
+        .collect();
+
+    if user_map_modified {
+        helpers::set_user_map(tran, user_map).await?;
+    }
+    if rebuild_allocator {
+        helpers::set_emunet_id_allocator(tran, IdAllocator::excluding(&in_use_ids)).await?;
+    }
+    if cluster_modified {
+        helpers::set_cluster_info(tran, cluster_info).await?;
+    }
+    if garbage_modified {
+        helpers::set_garbage_servesr(tran, garbage_servers).await?;
+    }
+    for emunet in modified_emunets.into_iter() {
+        let emunet_uuid = emunet.emunet_uuid();
+        let fut = helpers::set_emunet(tran, &emunet);
+        if !fut.await? {
+            return Err(Error::Conflict(emunet_uuid));
+        }
+    }
+
+    Ok(checker.violations)
+}
+
+// check the stored state while the server is not serving
+pub async fn fsck(connector: &Connector, repair: bool) -> Result<Vec<(String, bool)>, Error> {
+    let mut client = connector.connect().await?;
+    let mut tran = client.guarded_tran().await?;
+
+    let violations = check(&mut tran, repair).await?;
+    Ok(violations
+        .into_iter()
+        .map(|v| (v.message, v.repaired))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    #[tokio::test]
+    async fn id_allocator_and_placement_are_repaired() {
+        let connector = testing::memory_connector().await;
+        let mut client = connector.connect().await.unwrap();
+        let mut tran = client.guarded_tran().await.unwrap();
+        let emunet = testing::seed_emunet(&mut tran, "alice", "line", 4).await;
+        let emunet_uuid = emunet.emunet_uuid();
+        let emunet_id = emunet.emunet_id();
+
+        // the id of the emunet is free while a stray id is taken
+        let stray_id = if emunet_id == 0 { 1 } else { 0 };
+        let allocator = IdAllocator::excluding(&vec![stray_id].into_iter().collect());
+        helpers::set_emunet_id_allocator(&mut tran, allocator)
+            .await
+            .unwrap();
+        // a server forgets the devices placed on it
+        let node_name = emunet
+            .servers()
+            .iter()
+            .find(|(_, server)| server.devs().len() > 0)
+            .map(|(node_name, _)| node_name.clone())
+            .unwrap();
+        let devs = emunet.servers()[&node_name].devs().clone();
+        emunet.servers()[&node_name].reset_devs(HashSet::new());
+        assert!(helpers::set_emunet(&mut tran, &emunet).await.unwrap());
+
+        let violations = check(&mut tran, true).await.unwrap();
+        assert!(violations.iter().all(|v| v.repaired));
+        let messages: Vec<&str> = violations.iter().map(|v| &v.message[..]).collect();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[0],
+            format!(
+                "emunet id {} of emunet {} is free in the id allocator",
+                emunet_id, emunet_uuid
+            )
+        );
+        assert_eq!(
+            messages[1],
+            format!(
+                "emunet id {} is neither free nor used by any emunet",
+                stray_id
+            )
+        );
+        assert!(messages[2].starts_with(&format!(
+            "server {} of emunet {} holds devices {{}}, but devices",
+            node_name, emunet_uuid
+        )));
+
+        let allocator = helpers::get_emunet_id_allocator(&mut tran).await.unwrap();
+        assert!(!allocator.is_free(emunet_id));
+        assert!(allocator.is_free(stray_id));
+        let repaired = helpers::get_emunet(&mut tran, emunet_uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(*repaired.servers()[&node_name].devs(), devs);
+        assert_eq!(check(&mut tran, false).await.unwrap().len(), 0);
+    }
+}
@@ -2,25 +2,30 @@ use std::collections::HashMap;
 use std::future::Future;
 
 use indradb::Type;
-use indradb::{SpecificVertexQuery, VertexQueryExt};
+use indradb::{EdgeKey, EdgeQueryExt, PipeVertexQuery, SpecificEdgeQuery};
+use indradb::{RangeVertexQuery, SpecificVertexQuery, VertexQueryExt};
 use indradb::{Vertex, VertexQuery};
-use indradb_proto::{ClientError, Transaction};
 use uuid::Uuid;
 
-use crate::emunet::{self, ClusterInfo, Emunet, IdAllocator, User, ServerInfo};
+use super::storage::Transaction;
+use crate::emunet::{
+    self, ClusterInfo, Device, DeviceMeta, Emunet, IdAllocator, Job, Link, LinkMeta, ServerInfo,
+    TicketQueue, Usage, User,
+};
+use crate::errors::Error;
 
-pub(crate) async fn create_vertex(tran: &mut Transaction, id: Uuid) -> Result<bool, ClientError> {
+pub(crate) async fn create_vertex(tran: &mut Transaction, id: Uuid) -> Result<bool, Error> {
     let t = Type::new("t").unwrap();
     let v = Vertex::with_id(id, t);
 
-    tran.create_vertex(&v).await
+    Ok(tran.create_vertex(&v).await?)
 }
 
 pub(crate) async fn get_vertex_json_value(
     tran: &mut Transaction,
     vid: Uuid,
     property_name: &str,
-) -> Result<Option<serde_json::Value>, ClientError> {
+) -> Result<Option<serde_json::Value>, Error> {
     let q: VertexQuery = SpecificVertexQuery::single(vid.clone()).into();
     let vertex_list = tran.get_vertices(q).await?;
     if vertex_list.len() == 0 {
@@ -42,7 +47,7 @@ pub(crate) async fn set_vertex_json_value(
     vid: Uuid,
     property_name: &str,
     json: &serde_json::Value,
-) -> Result<bool, ClientError> {
+) -> Result<bool, Error> {
     let q: VertexQuery = SpecificVertexQuery::single(vid).into();
     let vertex_list = tran.get_vertices(q).await?;
     if vertex_list.len() == 0 {
@@ -55,68 +60,66 @@ pub(crate) async fn set_vertex_json_value(
     Ok(true)
 }
 
-pub(crate) async fn delete_vertex(tran: &mut Transaction, vid: Uuid) -> Result<(), ClientError> {
+pub(crate) async fn delete_vertex(tran: &mut Transaction, vid: Uuid) -> Result<(), Error> {
     let q: VertexQuery = SpecificVertexQuery::single(vid).into();
-    tran.delete_vertices(q).await
+    Ok(tran.delete_vertices(q).await?)
 }
 
-pub(crate) async fn get_cluster_info(tran: &mut Transaction) -> Result<ClusterInfo, ClientError> {
+pub(crate) async fn get_cluster_info(tran: &mut Transaction) -> Result<ClusterInfo, Error> {
     let res = get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "cluster_info").await?;
     match res {
-        Some(jv) => Ok(serde_json::from_value(jv).unwrap()),
-        None => panic!("database is not correctly initialized"),
+        Some(jv) => serde_json::from_value(jv).map_err(|e| Error::corrupted("cluster_info", e)),
+        None => Err(Error::NotInitialized),
     }
 }
 
 pub(crate) async fn set_cluster_info(
     tran: &mut Transaction,
     cluster_info: ClusterInfo,
-) -> Result<(), ClientError> {
+) -> Result<(), Error> {
     let jv = serde_json::to_value(cluster_info).unwrap();
     let res = set_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "cluster_info", &jv).await?;
-    if !res {
-        panic!("database is not correctly initialized");
+    match res {
+        true => Ok(()),
+        false => Err(Error::NotInitialized),
     }
-    Ok(())
 }
 
-pub(crate) async fn get_user_map(
-    tran: &mut Transaction,
-) -> Result<HashMap<String, User>, ClientError> {
+pub(crate) async fn get_user_map(tran: &mut Transaction) -> Result<HashMap<String, User>, Error> {
     let res = get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "user_map").await?;
     match res {
-        Some(jv) => Ok(serde_json::from_value(jv).unwrap()),
-        None => panic!("database is not correctly initialized"),
+        Some(jv) => serde_json::from_value(jv).map_err(|e| Error::corrupted("user_map", e)),
+        None => Err(Error::NotInitialized),
     }
 }
 
 pub(crate) async fn set_user_map(
     tran: &mut Transaction,
     user_map: HashMap<String, User>,
-) -> Result<(), ClientError> {
+) -> Result<(), Error> {
     let jv = serde_json::to_value(user_map).unwrap();
     let res = set_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "user_map", &jv).await?;
-    if !res {
-        panic!("database is not correctly initialized");
+    match res {
+        true => Ok(()),
+        false => Err(Error::NotInitialized),
     }
-    Ok(())
 }
 
-pub(crate) async fn get_emunet_id_allocator(
-    tran: &mut Transaction,
-) -> Result<IdAllocator, ClientError> {
+pub(crate) async fn get_emunet_id_allocator(tran: &mut Transaction) -> Result<IdAllocator, Error> {
     let res =
         get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "emunet_id_allocator").await?;
     match res {
-        Some(jv) => Ok(serde_json::from_value(jv).unwrap()),
-        None => panic!("database is not correctly initialized"),
+        Some(jv) => {
+            serde_json::from_value(jv).map_err(|e| Error::corrupted("emunet_id_allocator", e))
+        }
+        None => Err(Error::NotInitialized),
     }
 }
 
 pub(crate) async fn set_emunet_id_allocator(
     tran: &mut Transaction,
     id_allocator: IdAllocator,
-) -> Result<(), ClientError> {
+) -> Result<(), Error> {
     let jv = serde_json::to_value(id_allocator).unwrap();
     let res = set_vertex_json_value(
         tran,
@@ -125,55 +128,542 @@ pub(crate) async fn set_emunet_id_allocator(
         &jv,
     )
     .await?;
-    if !res {
-        panic!("database is not correctly initialized");
+    match res {
+        true => Ok(()),
+        false => Err(Error::NotInitialized),
     }
-    Ok(())
 }
 
 pub(crate) async fn get_emunet(
     tran: &mut Transaction,
     emunet_uuid: Uuid,
-) -> Result<Option<Emunet>, ClientError> {
+) -> Result<Option<Emunet>, Error> {
+    let jv = match get_vertex_json_value(tran, emunet_uuid, emunet::EMUNET_NODE_PROPERTY).await? {
+        None => return Ok(None),
+        Some(jv) => jv,
+    };
+
+    let emunet: Emunet = serde_json::from_value(jv).map_err(|e| Error::corrupted("emunet", e))?;
+    if !emunet.graph_loaded() {
+        let devices = get_devices(tran, device_vertex_query(emunet_uuid.clone())).await?;
+        let links = get_links(tran, device_vertex_query(emunet_uuid)).await?;
+        emunet.load_graph(devices, links);
+    }
+    Ok(Some(emunet))
+}
+
+// the emunet without its devices and links
+pub(crate) async fn get_emunet_header(
+    tran: &mut Transaction,
+    emunet_uuid: Uuid,
+) -> Result<Option<Emunet>, Error> {
     let jv = match get_vertex_json_value(tran, emunet_uuid, emunet::EMUNET_NODE_PROPERTY).await? {
         None => return Ok(None),
         Some(jv) => jv,
     };
 
-    let emunet: Emunet = serde_json::from_value(jv).expect("FATAL: this should not happen");
+    let emunet: Emunet = serde_json::from_value(jv).map_err(|e| Error::corrupted("emunet", e))?;
     Ok(Some(emunet))
 }
 
+fn device_type() -> Type {
+    Type::new(emunet::DEVICE_NODE_PROPERTY).unwrap()
+}
+
+fn link_type() -> Type {
+    Type::new(emunet::LINK_EDGE_PROPERTY).unwrap()
+}
+
+fn node_type() -> Type {
+    Type::new(emunet::K8S_NODE_PROPERTY).unwrap()
+}
+
+fn placement_type() -> Type {
+    Type::new(emunet::PLACEMENT_EDGE_PROPERTY).unwrap()
+}
+
+// the device vertices of an emunet, they are connected to the emunet vertex
+// by edges of the device type
+fn device_vertex_query(emunet_uuid: Uuid) -> PipeVertexQuery {
+    SpecificVertexQuery::single(emunet_uuid)
+        .outbound()
+        .t(device_type())
+        .inbound()
+}
+
+// the vertices of the k8s nodes that an emunet owns, each of them is linked
+// to the devices placed on the node by edges of the placement type
+fn node_vertex_query(emunet_uuid: Uuid) -> PipeVertexQuery {
+    SpecificVertexQuery::single(emunet_uuid)
+        .outbound()
+        .t(node_type())
+        .inbound()
+}
+
+async fn get_devices(
+    tran: &mut Transaction,
+    q: PipeVertexQuery,
+) -> Result<Vec<(Uuid, Device<DeviceMeta, LinkMeta>)>, Error> {
+    let property_list = tran
+        .get_vertex_properties(q.property(emunet::DEVICE_NODE_PROPERTY))
+        .await?;
+    property_list
+        .into_iter()
+        .map(|p| {
+            let dev = serde_json::from_value(p.value).map_err(|e| Error::corrupted("device", e))?;
+            Ok((p.id, dev))
+        })
+        .collect()
+}
+
+// the links going out of the queried device vertices
+async fn get_links(
+    tran: &mut Transaction,
+    q: impl VertexQueryExt,
+) -> Result<Vec<Link<LinkMeta>>, Error> {
+    let property_list = tran
+        .get_edge_properties(
+            q.outbound()
+                .t(link_type())
+                .property(emunet::LINK_EDGE_PROPERTY),
+        )
+        .await?;
+    property_list
+        .into_iter()
+        .map(|p| serde_json::from_value(p.value).map_err(|e| Error::corrupted("link", e)))
+        .collect()
+}
+
+// the ids of the devices placed on the k8s node stored on vertex node_vid,
+// they are kept on the placement edges
+pub(crate) async fn get_devices_on_node(
+    tran: &mut Transaction,
+    node_vid: Uuid,
+) -> Result<Vec<u64>, Error> {
+    let q = SpecificVertexQuery::single(node_vid)
+        .outbound()
+        .t(placement_type())
+        .property(emunet::PLACEMENT_EDGE_PROPERTY);
+    let mut res: Vec<u64> = tran
+        .get_edge_properties(q)
+        .await?
+        .into_iter()
+        .map(|p| serde_json::from_value(p.value).map_err(|e| Error::corrupted("placement", e)))
+        .collect::<Result<_, _>>()?;
+    res.sort();
+    Ok(res)
+}
+
+// the device stored on vertex dev_vid together with its links
+pub(crate) async fn get_device(
+    tran: &mut Transaction,
+    dev_vid: Uuid,
+) -> Result<Option<Device<DeviceMeta, LinkMeta>>, Error> {
+    let dev: Device<DeviceMeta, LinkMeta> =
+        match get_vertex_json_value(tran, dev_vid.clone(), emunet::DEVICE_NODE_PROPERTY).await? {
+            None => return Ok(None),
+            Some(jv) => serde_json::from_value(jv).map_err(|e| Error::corrupted("device", e))?,
+        };
+    for link in get_links(tran, SpecificVertexQuery::single(dev_vid)).await? {
+        dev.add_link(link);
+    }
+    Ok(Some(dev))
+}
+
+// the ids of the devices linked to the device stored on vertex dev_vid
+pub(crate) async fn get_neighbors(
+    tran: &mut Transaction,
+    dev_vid: Uuid,
+) -> Result<Vec<u64>, Error> {
+    let q = SpecificVertexQuery::single(dev_vid)
+        .outbound()
+        .t(link_type())
+        .inbound();
+    let mut res: Vec<u64> = get_devices(tran, q)
+        .await?
+        .into_iter()
+        .map(|(_, dev)| dev.id())
+        .collect();
+    res.sort();
+    Ok(res)
+}
+
+// the number of links of an emunet, every link is stored as two directed
+// edges, which are counted without loading the devices
+async fn count_links(tran: &mut Transaction, emunet_uuid: Uuid) -> Result<u64, Error> {
+    let q = device_vertex_query(emunet_uuid).outbound().t(link_type());
+    let edges = tran.get_edges(q.into()).await?;
+    Ok(edges.len() as u64 / 2)
+}
+
+// sum up the resources held by the emunets of a user, only the headers of
+// the emunets are loaded
+pub(crate) async fn get_usage(
+    tran: &mut Transaction,
+    emunet_uuids: Vec<Uuid>,
+) -> Result<Usage, Error> {
+    let mut usage = Usage::default();
+    for emunet_uuid in emunet_uuids {
+        let emunet = get_emunet_header(tran, emunet_uuid)
+            .await?
+            .ok_or(Error::EmunetNotFound(emunet_uuid))?;
+        usage.emunets += 1;
+        usage.capacity += emunet.max_capacity();
+        // emunets stored in the old format carry their links in the header
+        usage.links += if emunet.graph_loaded() {
+            emunet.link_count()
+        } else {
+            count_links(tran, emunet_uuid).await?
+        };
+    }
+    Ok(usage)
+}
+
+// the servers of every leased emunet together with the lease expiry,
+// these servers return to the cluster once the lease expires
+pub(crate) async fn get_leased_servers(
+    tran: &mut Transaction,
+) -> Result<Vec<(u64, ServerInfo)>, Error> {
+    let user_map = get_user_map(tran).await?;
+    let emunet_uuids: Vec<Uuid> = user_map
+        .values()
+        .map(|user| user.emunet_uuids())
+        .flatten()
+        .collect();
+    drop(user_map);
+
+    let mut res = Vec::new();
+    for emunet_uuid in emunet_uuids {
+        let emunet = get_emunet_header(tran, emunet_uuid)
+            .await?
+            .ok_or(Error::EmunetNotFound(emunet_uuid))?;
+        if let Some(lease_expiry) = emunet.lease_expiry() {
+            for server in emunet.servers().values() {
+                res.push((lease_expiry, server.server_info().clone()));
+            }
+        }
+    }
+    Ok(res)
+}
+
+// the capacity of the whole cluster, counting the free servers and the
+// servers held by every emunet
+pub(crate) async fn get_total_capacity(tran: &mut Transaction) -> Result<u64, Error> {
+    let cluster_info = get_cluster_info(tran).await?;
+    let mut total: u64 = cluster_info
+        .free_servers()
+        .iter()
+        .map(|server| server.max_capacity)
+        .sum();
+    drop(cluster_info);
+
+    let user_map = get_user_map(tran).await?;
+    let emunet_uuids: Vec<Uuid> = user_map
+        .values()
+        .map(|user| user.emunet_uuids())
+        .flatten()
+        .collect();
+    drop(user_map);
+
+    for emunet_uuid in emunet_uuids {
+        let emunet = get_emunet_header(tran, emunet_uuid)
+            .await?
+            .ok_or(Error::EmunetNotFound(emunet_uuid))?;
+        total += emunet.max_capacity();
+    }
+    Ok(total)
+}
+
+// EmunetWrite is an emunet serialized for storing, it is taken before a
+// transaction is acquired so that the emunet is not held across awaits
+pub(crate) struct EmunetWrite {
+    emunet_uuid: Uuid,
+    expected_revision: u64,
+    jv: serde_json::Value,
+    devices: Vec<(Uuid, serde_json::Value)>,
+    links: Vec<(EdgeKey, serde_json::Value)>,
+    nodes: Vec<(Uuid, serde_json::Value)>,
+    placements: Vec<(EdgeKey, serde_json::Value)>,
+}
+
+impl EmunetWrite {
+    pub(crate) fn new(emunet: &Emunet) -> Self {
+        let expected_revision = emunet.bump_revision();
+        Self::with_expected_revision(emunet, expected_revision)
+    }
+
+    // an emunet restored from an archive keeps its archived revision, the
+    // archived header has to be stored before the write
+    pub(crate) fn restore(emunet: &Emunet) -> Self {
+        Self::with_expected_revision(emunet, emunet.revision())
+    }
+
+    fn with_expected_revision(emunet: &Emunet, expected_revision: u64) -> Self {
+        let device_vertices = emunet.assign_device_vertices();
+        let node_vertices = emunet.assign_node_vertices();
+        let jv = serde_json::to_value(emunet).unwrap();
+        let emunet_uuid = emunet.emunet_uuid();
+
+        let devices: Vec<(Uuid, serde_json::Value)> = emunet
+            .devices()
+            .iter()
+            .map(|(dev_id, dev)| {
+                (
+                    device_vertices.get(dev_id).unwrap().clone(),
+                    serde_json::to_value(dev).unwrap(),
+                )
+            })
+            .collect();
+        let links: Vec<(EdgeKey, serde_json::Value)> = emunet
+            .devices()
+            .values()
+            .map(|dev| {
+                dev.links()
+                    .iter()
+                    .map(|link| {
+                        let (s, d) = link.link_id();
+                        let key = EdgeKey::new(
+                            device_vertices.get(&s).unwrap().clone(),
+                            link_type(),
+                            device_vertices.get(&d).unwrap().clone(),
+                        );
+                        (key, serde_json::to_value(link).unwrap())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .flatten()
+            .collect();
+        let nodes: Vec<(Uuid, serde_json::Value)> = node_vertices
+            .iter()
+            .map(|(node_name, vid)| (vid.clone(), serde_json::to_value(node_name).unwrap()))
+            .collect();
+        let placements: Vec<(EdgeKey, serde_json::Value)> = emunet
+            .devices()
+            .iter()
+            .map(|(dev_id, dev)| {
+                let key = EdgeKey::new(
+                    node_vertices.get(&dev.server_name()).unwrap().clone(),
+                    placement_type(),
+                    device_vertices.get(dev_id).unwrap().clone(),
+                );
+                (key, serde_json::to_value(dev_id).unwrap())
+            })
+            .collect();
+
+        Self {
+            emunet_uuid,
+            expected_revision,
+            jv,
+            devices,
+            links,
+            nodes,
+            placements,
+        }
+    }
+
+    // return false if the emunet vertex does not exist or the stored emunet
+    // has been modified since the emunet was read
+    pub(crate) async fn store(self, tran: &mut Transaction) -> Result<bool, Error> {
+        // compare and set, a newly created emunet has no stored revision
+        let stored =
+            get_vertex_json_value(tran, self.emunet_uuid, emunet::EMUNET_NODE_PROPERTY).await?;
+        let stored_revision = stored
+            .as_ref()
+            .and_then(|jv| jv.get("revision"))
+            .and_then(|jv| jv.as_u64())
+            .unwrap_or(0);
+        if stored_revision != self.expected_revision {
+            return Ok(false);
+        }
+
+        let res = set_vertex_json_value(
+            tran,
+            self.emunet_uuid,
+            emunet::EMUNET_NODE_PROPERTY,
+            &self.jv,
+        )
+        .await?;
+        if res {
+            set_emunet_graph(
+                tran,
+                self.emunet_uuid,
+                self.devices,
+                self.links,
+                self.nodes,
+                self.placements,
+            )
+            .await?;
+        }
+
+        Ok(res)
+    }
+}
+
+// store the emunet, return false if the emunet vertex does not exist or
+// the stored emunet has been modified since the emunet was read
 pub(crate) fn set_emunet<'a>(
     tran: &'a mut Transaction,
     emunet: &Emunet,
-) -> impl Future<Output = Result<bool, ClientError>> + Send + 'a {
-    let jv = serde_json::to_value(emunet).unwrap();
-    let emunet_uuid = emunet.emunet_uuid();
+) -> impl Future<Output = Result<bool, Error>> + Send + 'a {
+    EmunetWrite::new(emunet).store(tran)
+}
 
-    async move {
-        let res =
-            set_vertex_json_value(tran, emunet_uuid, emunet::EMUNET_NODE_PROPERTY, &jv).await?;
+// store the devices and links of an emunet together with the placement of
+// the devices, only the vertices and edges that differ from the stored ones
+// are written
+async fn set_emunet_graph(
+    tran: &mut Transaction,
+    emunet_uuid: Uuid,
+    devices: Vec<(Uuid, serde_json::Value)>,
+    links: Vec<(EdgeKey, serde_json::Value)>,
+    nodes: Vec<(Uuid, serde_json::Value)>,
+    placements: Vec<(EdgeKey, serde_json::Value)>,
+) -> Result<(), Error> {
+    let mut stored_devices: HashMap<Uuid, serde_json::Value> = tran
+        .get_vertex_properties(
+            device_vertex_query(emunet_uuid.clone()).property(emunet::DEVICE_NODE_PROPERTY),
+        )
+        .await?
+        .into_iter()
+        .map(|p| (p.id, p.value))
+        .collect();
+    let mut stored_links: HashMap<EdgeKey, serde_json::Value> = tran
+        .get_edge_properties(
+            device_vertex_query(emunet_uuid.clone())
+                .outbound()
+                .t(link_type())
+                .property(emunet::LINK_EDGE_PROPERTY),
+        )
+        .await?
+        .into_iter()
+        .map(|p| (p.key, p.value))
+        .collect();
 
-        Ok(res)
+    for (vid, jv) in devices {
+        match stored_devices.remove(&vid) {
+            Some(stored) if stored == jv => continue,
+            Some(_) => {}
+            None => {
+                tran.create_vertex(&Vertex::with_id(vid.clone(), device_type()))
+                    .await?;
+                tran.create_edge(&EdgeKey::new(
+                    emunet_uuid.clone(),
+                    device_type(),
+                    vid.clone(),
+                ))
+                .await?;
+            }
+        }
+        let q = SpecificVertexQuery::single(vid).property(emunet::DEVICE_NODE_PROPERTY);
+        tran.set_vertex_properties(q, &jv).await?;
+    }
+
+    for (key, jv) in links {
+        match stored_links.remove(&key) {
+            Some(stored) if stored == jv => continue,
+            Some(_) => {}
+            None => {
+                tran.create_edge(&key).await?;
+            }
+        }
+        let q = SpecificEdgeQuery::single(key).property(emunet::LINK_EDGE_PROPERTY);
+        tran.set_edge_properties(q, &jv).await?;
     }
+
+    let mut stored_nodes: HashMap<Uuid, serde_json::Value> = tran
+        .get_vertex_properties(
+            node_vertex_query(emunet_uuid.clone()).property(emunet::K8S_NODE_PROPERTY),
+        )
+        .await?
+        .into_iter()
+        .map(|p| (p.id, p.value))
+        .collect();
+    let mut stored_placements: HashMap<EdgeKey, serde_json::Value> = tran
+        .get_edge_properties(
+            node_vertex_query(emunet_uuid.clone())
+                .outbound()
+                .t(placement_type())
+                .property(emunet::PLACEMENT_EDGE_PROPERTY),
+        )
+        .await?
+        .into_iter()
+        .map(|p| (p.key, p.value))
+        .collect();
+
+    for (vid, jv) in nodes {
+        match stored_nodes.remove(&vid) {
+            Some(stored) if stored == jv => continue,
+            Some(_) => {}
+            None => {
+                tran.create_vertex(&Vertex::with_id(vid.clone(), node_type()))
+                    .await?;
+                tran.create_edge(&EdgeKey::new(emunet_uuid.clone(), node_type(), vid.clone()))
+                    .await?;
+            }
+        }
+        let q = SpecificVertexQuery::single(vid).property(emunet::K8S_NODE_PROPERTY);
+        tran.set_vertex_properties(q, &jv).await?;
+    }
+
+    for (key, jv) in placements {
+        match stored_placements.remove(&key) {
+            Some(stored) if stored == jv => continue,
+            Some(_) => {}
+            None => {
+                tran.create_edge(&key).await?;
+            }
+        }
+        let q = SpecificEdgeQuery::single(key).property(emunet::PLACEMENT_EDGE_PROPERTY);
+        tran.set_edge_properties(q, &jv).await?;
+    }
+
+    // the links and placements of the removed devices are deleted together
+    // with the vertices
+    let stale_edges: Vec<EdgeKey> = stored_links
+        .into_iter()
+        .chain(stored_placements.into_iter())
+        .map(|(key, _)| key)
+        .collect();
+    if stale_edges.len() > 0 {
+        tran.delete_edges(SpecificEdgeQuery::new(stale_edges).into())
+            .await?;
+    }
+    let stale_vertices: Vec<Uuid> = stored_devices
+        .into_iter()
+        .chain(stored_nodes.into_iter())
+        .map(|(vid, _)| vid)
+        .collect();
+    if stale_vertices.len() > 0 {
+        tran.delete_vertices(SpecificVertexQuery::new(stale_vertices).into())
+            .await?;
+    }
+
+    Ok(())
 }
 
-pub(crate) async fn get_garbage_servesr(
-    tran: &mut Transaction,
-) -> Result<Vec<ServerInfo>, ClientError> {
+// delete an emunet together with the vertices of its devices and k8s nodes,
+// indradb deletes the edges along with the vertices
+pub(crate) async fn delete_emunet(tran: &mut Transaction, emunet_uuid: Uuid) -> Result<(), Error> {
+    tran.delete_vertices(device_vertex_query(emunet_uuid.clone()).into())
+        .await?;
+    tran.delete_vertices(node_vertex_query(emunet_uuid.clone()).into())
+        .await?;
+    delete_vertex(tran, emunet_uuid).await
+}
+
+pub(crate) async fn get_garbage_servesr(tran: &mut Transaction) -> Result<Vec<ServerInfo>, Error> {
     let res =
         get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "garbage_servers").await?;
     match res {
-        Some(jv) => Ok(serde_json::from_value(jv).unwrap()),
-        None => panic!("database is not correctly initialized"),
+        Some(jv) => serde_json::from_value(jv).map_err(|e| Error::corrupted("garbage_servers", e)),
+        None => Err(Error::NotInitialized),
     }
 }
 
 pub(crate) async fn set_garbage_servesr(
     tran: &mut Transaction,
     garbage_servers: Vec<ServerInfo>,
-) -> Result<(), ClientError> {
+) -> Result<(), Error> {
     let jv = serde_json::to_value(garbage_servers).unwrap();
     let res = set_vertex_json_value(
         tran,
@@ -182,8 +672,124 @@ pub(crate) async fn set_garbage_servesr(
         &jv,
     )
     .await?;
-    if !res {
-        panic!("database is not correctly initialized");
+    match res {
+        true => Ok(()),
+        false => Err(Error::NotInitialized),
+    }
+}
+
+// databases created before the ticket queue was introduced have no queue
+pub(crate) async fn get_ticket_queue(tran: &mut Transaction) -> Result<TicketQueue, Error> {
+    let res = get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "ticket_queue").await?;
+    match res {
+        Some(jv) => serde_json::from_value(jv).map_err(|e| Error::corrupted("ticket_queue", e)),
+        None => Ok(TicketQueue::default()),
+    }
+}
+
+pub(crate) async fn set_ticket_queue(
+    tran: &mut Transaction,
+    ticket_queue: TicketQueue,
+) -> Result<(), Error> {
+    let jv = serde_json::to_value(ticket_queue).unwrap();
+    let res = set_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "ticket_queue", &jv).await?;
+    match res {
+        true => Ok(()),
+        false => Err(Error::NotInitialized),
+    }
+}
+
+// databases created before the schema was versioned have no version
+pub(crate) async fn get_schema_version(tran: &mut Transaction) -> Result<u64, Error> {
+    let res = get_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "schema_version").await?;
+    match res {
+        Some(jv) => serde_json::from_value(jv).map_err(|e| Error::corrupted("schema_version", e)),
+        None => Ok(0),
+    }
+}
+
+pub(crate) async fn set_schema_version(
+    tran: &mut Transaction,
+    schema_version: u64,
+) -> Result<(), Error> {
+    let jv = serde_json::to_value(schema_version).unwrap();
+    let res =
+        set_vertex_json_value(tran, super::CORE_INFO_ID.clone(), "schema_version", &jv).await?;
+    match res {
+        true => Ok(()),
+        false => Err(Error::NotInitialized),
+    }
+}
+
+pub(crate) async fn get_job(tran: &mut Transaction, job_uuid: Uuid) -> Result<Option<Job>, Error> {
+    let jv = match get_vertex_json_value(tran, job_uuid, emunet::JOB_NODE_PROPERTY).await? {
+        None => return Ok(None),
+        Some(jv) => jv,
+    };
+
+    let job: Job = serde_json::from_value(jv).map_err(|e| Error::corrupted("job", e))?;
+    Ok(Some(job))
+}
+
+// every stored job, only the migration scans the whole database for them
+pub(crate) async fn get_all_jobs(tran: &mut Transaction) -> Result<Vec<Job>, Error> {
+    let q = RangeVertexQuery::new(u32::MAX).property(emunet::JOB_NODE_PROPERTY);
+    let props = tran.get_vertex_properties(q).await?;
+    props
+        .into_iter()
+        .map(|prop| serde_json::from_value(prop.value).map_err(|e| Error::corrupted("job", e)))
+        .collect()
+}
+
+fn job_type() -> Type {
+    Type::new(emunet::JOB_NODE_PROPERTY).unwrap()
+}
+
+// link a job to the emunet it works on, return false if either vertex
+// does not exist
+pub(crate) async fn link_job(
+    tran: &mut Transaction,
+    emunet_uuid: Uuid,
+    job_uuid: Uuid,
+) -> Result<bool, Error> {
+    let key = EdgeKey::new(emunet_uuid, job_type(), job_uuid);
+    Ok(tran.create_edge(&key).await?)
+}
+
+// delete the jobs linked to an emunet except the job kept_uuid
+pub(crate) async fn delete_jobs(
+    tran: &mut Transaction,
+    emunet_uuid: Uuid,
+    kept_uuid: Uuid,
+) -> Result<(), Error> {
+    let q = SpecificVertexQuery::single(emunet_uuid)
+        .outbound()
+        .t(job_type())
+        .inbound();
+    let job_uuids: Vec<Uuid> = tran
+        .get_vertices(q.into())
+        .await?
+        .into_iter()
+        .map(|v| v.id)
+        .filter(|job_uuid| job_uuid != &kept_uuid)
+        .collect();
+    if job_uuids.len() > 0 {
+        tran.delete_vertices(SpecificVertexQuery::new(job_uuids).into())
+            .await?;
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+pub(crate) fn set_job<'a>(
+    tran: &'a mut Transaction,
+    job: &Job,
+) -> impl Future<Output = Result<bool, Error>> + Send + 'a {
+    let jv = serde_json::to_value(job).unwrap();
+    let job_uuid = job.job_uuid();
+
+    async move {
+        let res = set_vertex_json_value(tran, job_uuid, emunet::JOB_NODE_PROPERTY, &jv).await?;
+
+        Ok(res)
+    }
+}
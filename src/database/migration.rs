@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::helpers;
+use super::storage::Transaction;
+use super::Connector;
+use crate::emunet::{self, JobKind, ServerInfo, TicketQueue};
+use crate::errors::Error;
+
+// the layout version of the stored records, it is bumped together with a
+// new migration step whenever a record changes in a way that the serde
+// defaults can not absorb
+pub(crate) static SCHEMA_VERSION: u64 = 5;
+
+// Step upgrades the stored records from schema version `version - 1` to
+// `version`.
+struct Step {
+    version: u64,
+    description: &'static str,
+    run: for<'a> fn(&'a mut Transaction) -> BoxFuture<'a, Result<(), Error>>,
+}
+
+static STEPS: [Step; 5] = [
+    Step {
+        version: 1,
+        description: "create the garbage servers and the ticket queue on the core vertex",
+        run: add_core_records,
+    },
+    Step {
+        version: 2,
+        description: "move the devices and links of the emunets into their own vertices and edges",
+        run: split_emunet_graphs,
+    },
+    Step {
+        version: 3,
+        description: "replace the bearer tokens of the users with their hashes",
+        run: hash_user_tokens,
+    },
+    Step {
+        version: 4,
+        description: "link the devices to the vertices of the k8s nodes they are placed on",
+        run: link_node_vertices,
+    },
+    Step {
+        version: 5,
+        description: "link the jobs to their emunets and delete the jobs of the deleted emunets",
+        run: link_jobs,
+    },
+];
+
+fn add_core_records(tran: &mut Transaction) -> BoxFuture<'_, Result<(), Error>> {
+    Box::pin(async move {
+        let core_id = super::CORE_INFO_ID.clone();
+        if helpers::get_vertex_json_value(tran, core_id, "garbage_servers")
+            .await?
+            .is_none()
+        {
+            helpers::set_garbage_servesr(tran, Vec::<ServerInfo>::new()).await?;
+        }
+        if helpers::get_vertex_json_value(tran, core_id, "ticket_queue")
+            .await?
+            .is_none()
+        {
+            helpers::set_ticket_queue(tran, TicketQueue::default()).await?;
+        }
+        Ok(())
+    })
+}
+
+fn split_emunet_graphs(tran: &mut Transaction) -> BoxFuture<'_, Result<(), Error>> {
+    Box::pin(async move {
+        let emunet_uuids: Vec<uuid::Uuid> = helpers::get_user_map(tran)
+            .await?
+            .values()
+            .map(|user| user.emunet_uuids())
+            .flatten()
+            .collect();
+        for emunet_uuid in emunet_uuids {
+            // the emunets in the old format carry their devices in the vertex
+            let old_format =
+                helpers::get_vertex_json_value(tran, emunet_uuid, emunet::EMUNET_NODE_PROPERTY)
+                    .await?
+                    .and_then(|jv| jv.get("devices").map(|devs| devs.is_object()))
+                    .unwrap_or(false);
+            if !old_format {
+                continue;
+            }
+            let emunet = helpers::get_emunet(tran, emunet_uuid)
+                .await?
+                .ok_or(Error::EmunetNotFound(emunet_uuid))?;
+            let fut = helpers::set_emunet(tran, &emunet);
+            if !fut.await? {
+                return Err(Error::Conflict(emunet_uuid));
+            }
+        }
+        Ok(())
+    })
+}
+
+fn hash_user_tokens(tran: &mut Transaction) -> BoxFuture<'_, Result<(), Error>> {
+    Box::pin(async move {
+        let mut user_map = helpers::get_user_map(tran).await?;
+        for user in user_map.values_mut() {
+            user.hash_plain_token();
+        }
+        helpers::set_user_map(tran, user_map).await
+    })
+}
+
+fn link_node_vertices(tran: &mut Transaction) -> BoxFuture<'_, Result<(), Error>> {
+    Box::pin(async move {
+        let emunet_uuids: Vec<uuid::Uuid> = helpers::get_user_map(tran)
+            .await?
+            .values()
+            .map(|user| user.emunet_uuids())
+            .flatten()
+            .collect();
+        for emunet_uuid in emunet_uuids {
+            // storing the emunet again creates the missing node vertices
+            let emunet = helpers::get_emunet(tran, emunet_uuid)
+                .await?
+                .ok_or(Error::EmunetNotFound(emunet_uuid))?;
+            let fut = helpers::set_emunet(tran, &emunet);
+            if !fut.await? {
+                return Err(Error::Conflict(emunet_uuid));
+            }
+        }
+        Ok(())
+    })
+}
+
+fn link_jobs(tran: &mut Transaction) -> BoxFuture<'_, Result<(), Error>> {
+    Box::pin(async move {
+        let emunet_uuids: HashSet<uuid::Uuid> = helpers::get_user_map(tran)
+            .await?
+            .values()
+            .map(|user| user.emunet_uuids())
+            .flatten()
+            .collect();
+        for job in helpers::get_all_jobs(tran).await? {
+            if emunet_uuids.contains(&job.emunet_uuid()) {
+                helpers::link_job(tran, job.emunet_uuid(), job.job_uuid()).await?;
+                continue;
+            }
+            // the deletion job outlives its emunet to report the result
+            match job.kind() {
+                JobKind::Delete => {}
+                _ => helpers::delete_vertex(tran, job.job_uuid()).await?,
+            }
+        }
+        Ok(())
+    })
+}
+
+// MigrationReport lists the steps that bring the stored records from
+// schema_version up to target_version.
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct MigrationReport {
+    pub(crate) schema_version: u64,
+    pub(crate) target_version: u64,
+    pub(crate) steps: Vec<String>,
+    pub(crate) applied: bool,
+}
+
+// run the pending migration steps one by one, the schema version is
+// stored after every step so that an interrupted migration resumes from
+// the failed step
+pub(crate) async fn run_migration(
+    tran: &mut Transaction,
+    dry_run: bool,
+) -> Result<Result<MigrationReport, String>, Error> {
+    let schema_version = helpers::get_schema_version(tran).await?;
+    if schema_version > SCHEMA_VERSION {
+        return Ok(Err(format!(
+            "the database has schema version {}, which is newer than version {} of this server",
+            schema_version, SCHEMA_VERSION
+        )));
+    }
+
+    let pending: Vec<&Step> = STEPS
+        .iter()
+        .filter(|step| step.version > schema_version)
+        .collect();
+    if !dry_run {
+        for step in pending.iter() {
+            (step.run)(tran).await?;
+            helpers::set_schema_version(tran, step.version).await?;
+        }
+    }
+
+    Ok(Ok(MigrationReport {
+        schema_version,
+        target_version: SCHEMA_VERSION,
+        steps: pending
+            .iter()
+            .map(|step| format!("{}: {}", step.version, step.description))
+            .collect(),
+        applied: !dry_run,
+    }))
+}
+
+// upgrade the stored records when the server starts, return the
+// descriptions of the applied steps
+pub async fn migrate(connector: &Connector) -> Result<Result<Vec<String>, String>, Error> {
+    let mut client = connector.connect().await?;
+    let mut tran = client.guarded_tran().await?;
+
+    let res = run_migration(&mut tran, false).await?;
+    Ok(res.map(|report| report.steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    #[tokio::test]
+    async fn old_format_emunet_is_split() {
+        let connector = testing::memory_connector().await;
+        let mut client = connector.connect().await.unwrap();
+        let mut tran = client.guarded_tran().await.unwrap();
+        let emunet = testing::seed_emunet(&mut tran, "alice", "line", 3).await;
+        let emunet_uuid = emunet.emunet_uuid();
+
+        // before schema version 2 the devices and their links are kept in
+        // the emunet vertex
+        let mut jv = serde_json::to_value(&emunet).unwrap();
+        let mut devices = serde_json::Map::new();
+        let mut links = Vec::new();
+        for (dev_id, dev) in emunet.devices().iter() {
+            let mut dev_jv = serde_json::to_value(dev).unwrap();
+            dev_jv["links"] = serde_json::to_value(&*dev.links()).unwrap();
+            devices.insert(dev_id.to_string(), dev_jv);
+            for link in dev.links().iter() {
+                if link.link_id().0 < link.link_id().1 {
+                    links.push(link.link_id());
+                }
+            }
+        }
+        jv["devices"] = serde_json::Value::Object(devices);
+        jv["links"] = serde_json::to_value(links).unwrap();
+        jv.as_object_mut().unwrap().remove("device_vertices");
+        jv.as_object_mut().unwrap().remove("node_vertices");
+        helpers::delete_emunet(&mut tran, emunet_uuid)
+            .await
+            .unwrap();
+        assert!(helpers::create_vertex(&mut tran, emunet_uuid)
+            .await
+            .unwrap());
+        assert!(helpers::set_vertex_json_value(
+            &mut tran,
+            emunet_uuid,
+            emunet::EMUNET_NODE_PROPERTY,
+            &jv
+        )
+        .await
+        .unwrap());
+        helpers::set_schema_version(&mut tran, 1).await.unwrap();
+
+        let report = run_migration(&mut tran, false).await.unwrap().unwrap();
+        assert_eq!(report.schema_version, 1);
+        assert_eq!(report.steps.len(), STEPS.len() - 1);
+        assert_eq!(
+            helpers::get_schema_version(&mut tran).await.unwrap(),
+            SCHEMA_VERSION
+        );
+
+        let stored =
+            helpers::get_vertex_json_value(&mut tran, emunet_uuid, emunet::EMUNET_NODE_PROPERTY)
+                .await
+                .unwrap()
+                .unwrap();
+        assert!(stored.get("devices").is_none());
+        let migrated = helpers::get_emunet_header(&mut tran, emunet_uuid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(migrated.devices().len(), 0);
+        assert_eq!(migrated.stored_dev_idxs(), vec![0, 1, 2]);
+
+        let dev_vid = migrated.device_vertex(1).unwrap();
+        let dev = helpers::get_device(&mut tran, dev_vid)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(dev.links().len(), 2);
+        assert_eq!(
+            helpers::get_neighbors(&mut tran, dev_vid).await.unwrap(),
+            vec![0, 2]
+        );
+        let placement: Vec<(String, Vec<u64>)> = migrated
+            .servers()
+            .iter()
+            .map(|(node_name, server)| {
+                let mut devs: Vec<u64> = server.devs().iter().map(|id| *id).collect();
+                devs.sort();
+                (node_name.clone(), devs)
+            })
+            .collect();
+        for (node_name, devs) in placement {
+            let node_vid = migrated.node_vertex(&node_name).unwrap();
+            assert_eq!(
+                helpers::get_devices_on_node(&mut tran, node_vid)
+                    .await
+                    .unwrap(),
+                devs
+            );
+        }
+    }
+}
@@ -5,12 +5,22 @@ lazy_static! {
     static ref CORE_INFO_ID: uuid::Uuid = uuid::Uuid::from_bytes(BYTES_SEED);
 }
 
+pub(crate) mod archive;
 mod client;
+pub(crate) mod fsck;
 mod message_queue;
+mod storage;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub(crate) mod helpers;
+pub(crate) mod migration;
 pub(crate) use client::Client;
 pub(crate) use client::GuardedTransaction;
+pub(crate) use client::Scope;
 
 pub mod errors;
 pub use client::{init, init_ok, new_connector, Connector};
+pub use fsck::fsck;
+pub use migration::migrate;
+pub use storage::StorageConfig;
@@ -0,0 +1,329 @@
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{self, BoxFuture};
+use indradb::{Datastore, MemoryDatastore, RocksdbDatastore, SledDatastore};
+use indradb::{Edge, EdgeKey, EdgeProperty, EdgePropertyQuery, EdgeQuery};
+use indradb::{Vertex, VertexProperty, VertexPropertyQuery, VertexQuery};
+use indradb_proto as proto;
+use indradb_proto::ClientError;
+
+use super::errors::ConnectorError;
+
+// StorageConfig selects where the mocknet server keeps its data.
+#[derive(Clone, Debug)]
+pub enum StorageConfig {
+    // a separately launched indradb gRPC server at the address
+    Remote(String),
+    // indradb datastores embedded in the mocknet server, the rocksdb and
+    // sled datastores are kept in the directory
+    Rocksdb(String),
+    Sled(String),
+    // everything is lost when the server exits, mainly used by tests
+    Memory,
+}
+
+impl StorageConfig {
+    // only the gRPC connection can be re-established after a failure
+    pub(crate) fn reconnectable(&self) -> bool {
+        match self {
+            StorageConfig::Remote(_) => true,
+            _ => false,
+        }
+    }
+}
+
+// StorageTransaction is the subset of the indradb transaction interface
+// that database::helpers relies on.
+pub(crate) trait StorageTransaction: Send {
+    fn create_vertex<'a>(&'a mut self, v: &'a Vertex) -> BoxFuture<'a, Result<bool, ClientError>>;
+
+    fn get_vertices(&mut self, q: VertexQuery) -> BoxFuture<'_, Result<Vec<Vertex>, ClientError>>;
+
+    fn delete_vertices(&mut self, q: VertexQuery) -> BoxFuture<'_, Result<(), ClientError>>;
+
+    fn get_vertex_properties(
+        &mut self,
+        q: VertexPropertyQuery,
+    ) -> BoxFuture<'_, Result<Vec<VertexProperty>, ClientError>>;
+
+    fn set_vertex_properties<'a>(
+        &'a mut self,
+        q: VertexPropertyQuery,
+        value: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<(), ClientError>>;
+
+    fn create_edge<'a>(&'a mut self, key: &'a EdgeKey) -> BoxFuture<'a, Result<bool, ClientError>>;
+
+    fn get_edges(&mut self, q: EdgeQuery) -> BoxFuture<'_, Result<Vec<Edge>, ClientError>>;
+
+    fn delete_edges(&mut self, q: EdgeQuery) -> BoxFuture<'_, Result<(), ClientError>>;
+
+    fn get_edge_properties(
+        &mut self,
+        q: EdgePropertyQuery,
+    ) -> BoxFuture<'_, Result<Vec<EdgeProperty>, ClientError>>;
+
+    fn set_edge_properties<'a>(
+        &'a mut self,
+        q: EdgePropertyQuery,
+        value: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<(), ClientError>>;
+}
+
+// Transaction is what database::helpers operates on, whichever storage
+// backend it comes from
+pub(crate) struct Transaction {
+    inner: Box<dyn StorageTransaction>,
+}
+
+impl Deref for Transaction {
+    type Target = dyn StorageTransaction;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
+    }
+}
+
+impl DerefMut for Transaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.as_mut()
+    }
+}
+
+// Storage hands out the transactions of a storage backend.
+pub(crate) trait Storage: Send + Sync {
+    fn transaction(&self) -> BoxFuture<'_, Result<Transaction, ClientError>>;
+}
+
+impl StorageTransaction for proto::Transaction {
+    fn create_vertex<'a>(&'a mut self, v: &'a Vertex) -> BoxFuture<'a, Result<bool, ClientError>> {
+        Box::pin(proto::Transaction::create_vertex(self, v))
+    }
+
+    fn get_vertices(&mut self, q: VertexQuery) -> BoxFuture<'_, Result<Vec<Vertex>, ClientError>> {
+        Box::pin(proto::Transaction::get_vertices(self, q))
+    }
+
+    fn delete_vertices(&mut self, q: VertexQuery) -> BoxFuture<'_, Result<(), ClientError>> {
+        Box::pin(proto::Transaction::delete_vertices(self, q))
+    }
+
+    fn get_vertex_properties(
+        &mut self,
+        q: VertexPropertyQuery,
+    ) -> BoxFuture<'_, Result<Vec<VertexProperty>, ClientError>> {
+        Box::pin(proto::Transaction::get_vertex_properties(self, q))
+    }
+
+    fn set_vertex_properties<'a>(
+        &'a mut self,
+        q: VertexPropertyQuery,
+        value: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<(), ClientError>> {
+        Box::pin(proto::Transaction::set_vertex_properties(self, q, value))
+    }
+
+    fn create_edge<'a>(&'a mut self, key: &'a EdgeKey) -> BoxFuture<'a, Result<bool, ClientError>> {
+        Box::pin(proto::Transaction::create_edge(self, key))
+    }
+
+    fn get_edges(&mut self, q: EdgeQuery) -> BoxFuture<'_, Result<Vec<Edge>, ClientError>> {
+        Box::pin(proto::Transaction::get_edges(self, q))
+    }
+
+    fn delete_edges(&mut self, q: EdgeQuery) -> BoxFuture<'_, Result<(), ClientError>> {
+        Box::pin(proto::Transaction::delete_edges(self, q))
+    }
+
+    fn get_edge_properties(
+        &mut self,
+        q: EdgePropertyQuery,
+    ) -> BoxFuture<'_, Result<Vec<EdgeProperty>, ClientError>> {
+        Box::pin(proto::Transaction::get_edge_properties(self, q))
+    }
+
+    fn set_edge_properties<'a>(
+        &'a mut self,
+        q: EdgePropertyQuery,
+        value: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<(), ClientError>> {
+        Box::pin(proto::Transaction::set_edge_properties(self, q, value))
+    }
+}
+
+// the storage behind an indradb gRPC server
+struct RemoteStorage {
+    client: Mutex<proto::Client>,
+}
+
+impl Storage for RemoteStorage {
+    fn transaction(&self) -> BoxFuture<'_, Result<Transaction, ClientError>> {
+        let mut client = self.client.lock().unwrap().clone();
+        Box::pin(async move {
+            let tran = client.transaction().await?;
+            Ok(Transaction {
+                inner: Box::new(tran),
+            })
+        })
+    }
+}
+
+fn datastore_error(e: indradb::Error) -> ClientError {
+    tonic::Status::internal(format!("{}", e)).into()
+}
+
+// the embedded datastores answer immediately, the operations are not
+// offloaded because every one of them touches only a few keys
+struct EmbeddedTransaction<T>(T);
+
+impl<T: indradb::Transaction + Send> StorageTransaction for EmbeddedTransaction<T> {
+    fn create_vertex<'a>(&'a mut self, v: &'a Vertex) -> BoxFuture<'a, Result<bool, ClientError>> {
+        Box::pin(future::ready(
+            self.0.create_vertex(v).map_err(datastore_error),
+        ))
+    }
+
+    fn get_vertices(&mut self, q: VertexQuery) -> BoxFuture<'_, Result<Vec<Vertex>, ClientError>> {
+        Box::pin(future::ready(
+            self.0.get_vertices(q).map_err(datastore_error),
+        ))
+    }
+
+    fn delete_vertices(&mut self, q: VertexQuery) -> BoxFuture<'_, Result<(), ClientError>> {
+        Box::pin(future::ready(
+            self.0.delete_vertices(q).map_err(datastore_error),
+        ))
+    }
+
+    fn get_vertex_properties(
+        &mut self,
+        q: VertexPropertyQuery,
+    ) -> BoxFuture<'_, Result<Vec<VertexProperty>, ClientError>> {
+        Box::pin(future::ready(
+            self.0.get_vertex_properties(q).map_err(datastore_error),
+        ))
+    }
+
+    fn set_vertex_properties<'a>(
+        &'a mut self,
+        q: VertexPropertyQuery,
+        value: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<(), ClientError>> {
+        Box::pin(future::ready(
+            self.0
+                .set_vertex_properties(q, value)
+                .map_err(datastore_error),
+        ))
+    }
+
+    fn create_edge<'a>(&'a mut self, key: &'a EdgeKey) -> BoxFuture<'a, Result<bool, ClientError>> {
+        Box::pin(future::ready(
+            self.0.create_edge(key).map_err(datastore_error),
+        ))
+    }
+
+    fn get_edges(&mut self, q: EdgeQuery) -> BoxFuture<'_, Result<Vec<Edge>, ClientError>> {
+        Box::pin(future::ready(self.0.get_edges(q).map_err(datastore_error)))
+    }
+
+    fn delete_edges(&mut self, q: EdgeQuery) -> BoxFuture<'_, Result<(), ClientError>> {
+        Box::pin(future::ready(
+            self.0.delete_edges(q).map_err(datastore_error),
+        ))
+    }
+
+    fn get_edge_properties(
+        &mut self,
+        q: EdgePropertyQuery,
+    ) -> BoxFuture<'_, Result<Vec<EdgeProperty>, ClientError>> {
+        Box::pin(future::ready(
+            self.0.get_edge_properties(q).map_err(datastore_error),
+        ))
+    }
+
+    fn set_edge_properties<'a>(
+        &'a mut self,
+        q: EdgePropertyQuery,
+        value: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<(), ClientError>> {
+        Box::pin(future::ready(
+            self.0
+                .set_edge_properties(q, value)
+                .map_err(datastore_error),
+        ))
+    }
+}
+
+// the storage behind an indradb datastore running in the mocknet server
+struct EmbeddedStorage<D> {
+    datastore: D,
+}
+
+impl<D> Storage for EmbeddedStorage<D>
+where
+    D: Datastore + Send + Sync,
+    D::Trans: Send + 'static,
+{
+    fn transaction(&self) -> BoxFuture<'_, Result<Transaction, ClientError>> {
+        let res = self
+            .datastore
+            .transaction()
+            .map(|tran| Transaction {
+                inner: Box::new(EmbeddedTransaction(tran)),
+            })
+            .map_err(datastore_error);
+        Box::pin(future::ready(res))
+    }
+}
+
+pub(crate) async fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>, ConnectorError> {
+    let storage: Arc<dyn Storage> = match config {
+        StorageConfig::Remote(db_addr) => {
+            let endpoint = tonic::transport::Endpoint::from_str(db_addr)?;
+            let client = proto::Client::new(endpoint).await?;
+            Arc::new(RemoteStorage {
+                client: Mutex::new(client),
+            })
+        }
+        StorageConfig::Rocksdb(path) => Arc::new(EmbeddedStorage {
+            datastore: RocksdbDatastore::new(path, None)?,
+        }),
+        StorageConfig::Sled(path) => Arc::new(EmbeddedStorage {
+            datastore: SledDatastore::new(path)?,
+        }),
+        StorageConfig::Memory => Arc::new(EmbeddedStorage {
+            datastore: MemoryDatastore::default(),
+        }),
+    };
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::StorageConfig;
+    use crate::database::testing::cluster_info;
+    use crate::database::{helpers, init, init_ok, new_connector};
+    use crate::emunet::User;
+
+    #[tokio::test]
+    async fn memory_storage_round_trip() {
+        let connector = new_connector(StorageConfig::Memory).await.unwrap();
+        assert_eq!(init_ok(&connector).await.unwrap(), false);
+        assert!(init(&connector, cluster_info()).await.unwrap().is_ok());
+        assert_eq!(init_ok(&connector).await.unwrap(), true);
+        assert!(init(&connector, cluster_info()).await.unwrap().is_err());
+
+        let mut client = connector.connect().await.unwrap();
+        let mut tran = client.guarded_tran().await.unwrap();
+        let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut tran).await.unwrap();
+        assert!(user_map.is_empty());
+        user_map.insert("alice".to_string(), User::new("alice", "token"));
+        helpers::set_user_map(&mut tran, user_map).await.unwrap();
+        let user_map = helpers::get_user_map(&mut tran).await.unwrap();
+        assert!(user_map.contains_key("alice"));
+    }
+}
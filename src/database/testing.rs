@@ -0,0 +1,85 @@
+// fixtures for the tests that run on the in-memory storage
+
+use super::storage::Transaction;
+use super::{helpers, init, new_connector, Connector, StorageConfig};
+use crate::algo::UndirectedGraph;
+use crate::emunet::{ClusterConfig, ClusterInfo, Emunet, EmunetState};
+use crate::emunet::{InputDevice, InputLink, User};
+
+pub(crate) fn cluster_info() -> ClusterInfo {
+    let config: ClusterConfig =
+        serde_json::from_str(include_str!("../../deploy/cluster_config_template.json")).unwrap();
+    ClusterInfo::try_new(config).unwrap()
+}
+
+// a connector to an in-memory database initialized with the template cluster
+pub(crate) async fn memory_connector() -> Connector {
+    let connector = new_connector(StorageConfig::Memory).await.unwrap();
+    assert!(init(&connector, cluster_info()).await.unwrap().is_ok());
+    connector
+}
+
+// store a normal emunet whose devices form a line, the emunet takes its id
+// and servers the way a created emunet does
+pub(crate) async fn seed_emunet(
+    tran: &mut Transaction,
+    user_name: &str,
+    emunet_name: &str,
+    dev_num: u64,
+) -> Emunet {
+    let mut user_map = helpers::get_user_map(tran).await.unwrap();
+    let user = user_map
+        .entry(user_name.to_string())
+        .or_insert_with(|| User::new(user_name, "token"));
+    let emunet_uuid = user.register_emunet(emunet_name).unwrap();
+    let mut id_allocator = helpers::get_emunet_id_allocator(tran).await.unwrap();
+    let emunet_id = id_allocator.alloc().unwrap();
+    let mut cluster_info = helpers::get_cluster_info(tran).await.unwrap();
+    let servers = cluster_info
+        .allocate_servers(dev_num, None, &Vec::new())
+        .unwrap();
+    assert!(helpers::create_vertex(tran, emunet_uuid.clone())
+        .await
+        .unwrap());
+
+    let emunet = Emunet::new(
+        emunet_id,
+        emunet_name.to_string(),
+        emunet_uuid,
+        user_name.to_string(),
+        cluster_info.api_server_addr().into(),
+        cluster_info.emunet_access_info().clone(),
+        servers,
+    );
+    let graph = UndirectedGraph::new(
+        (0..dev_num)
+            .map(|id| {
+                let dev = InputDevice {
+                    id,
+                    description: String::new(),
+                };
+                (id, dev)
+            })
+            .collect(),
+        (1..dev_num)
+            .map(|id| {
+                let link = InputLink {
+                    edge_id: (id - 1, id),
+                    description: String::new(),
+                };
+                ((id - 1, id), link)
+            })
+            .collect(),
+    )
+    .unwrap();
+    emunet.build_emunet_graph(&graph);
+    emunet.set_state(EmunetState::Normal);
+    assert!(helpers::set_emunet(tran, &emunet).await.unwrap());
+
+    helpers::set_user_map(tran, user_map).await.unwrap();
+    helpers::set_cluster_info(tran, cluster_info).await.unwrap();
+    helpers::set_emunet_id_allocator(tran, id_allocator)
+        .await
+        .unwrap();
+    emunet
+}
@@ -2,20 +2,21 @@ use std::cmp::Ord;
 use std::collections::HashSet;
 use std::{cell::RefCell, collections::HashMap};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use super::graph_io_format::{InputDevice, InputLink};
 use crate::algo::*;
 use crate::emunet::EMUNET_NUM_POWER;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub(crate) struct EmunetAccessInfo {
     pub(crate) login_server_addr: String,
     pub(crate) login_server_user: String,
     pub(crate) login_server_pwd: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub(crate) struct ServerInfo {
     pub(crate) node_name: String,
     pub(crate) max_capacity: u64,
@@ -28,11 +29,56 @@ pub struct ClusterConfig {
     k8s_nodes: Vec<ServerInfo>,
 }
 
+// capacity booked by a user for a future time window, the emunet is
+// created when the window starts and its lease expires when the window ends
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub(crate) struct Reservation {
+    pub(crate) reservation_uuid: uuid::Uuid,
+    pub(crate) user: String,
+    pub(crate) emunet: String,
+    pub(crate) capacity: u64,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    // why the last attempt to create the emunet failed, the creation is
+    // retried until the window ends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_error: Option<String>,
+}
+
+impl Reservation {
+    fn overlaps(&self, other: &Reservation) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ClusterInfo {
     api_server_addr: String,
     access_info: EmunetAccessInfo,
     servers: Vec<ServerInfo>,
+    #[serde(default)]
+    reservations: Vec<Reservation>,
+}
+
+// whether the demands can be served by the servers, each demand takes
+// whole servers in the same way as ClusterInfo::allocate_servers
+fn servers_fit(mut servers: Vec<u64>, mut demands: Vec<u64>) -> bool {
+    servers.sort_by(|a, b| b.cmp(a));
+    demands.sort_by(|a, b| b.cmp(a));
+
+    for demand in demands {
+        let mut target = 0;
+        let mut index = 0;
+        while target < demand && index < servers.len() {
+            target += servers[index];
+            index += 1;
+        }
+        if target < demand {
+            return false;
+        }
+        servers.drain(0..index);
+    }
+    true
 }
 
 impl ClusterInfo {
@@ -64,6 +110,7 @@ impl ClusterInfo {
             api_server_addr: config.api_server_addr,
             access_info: config.access_info,
             servers: Vec::new(),
+            reservations: Vec::new(),
         };
 
         for node_info in config.k8s_nodes {
@@ -97,7 +144,15 @@ impl ClusterInfo {
         self.servers
     }
 
-    pub(crate) fn allocate_servers(&mut self, quantity: u64) -> Result<Vec<ContainerServer>, u64> {
+    // allocate servers for an emunet whose lease expires at lease_expiry,
+    // returning holds the servers of the other leased emunets together
+    // with their lease expiry.
+    pub(crate) fn allocate_servers(
+        &mut self,
+        quantity: u64,
+        lease_expiry: Option<u64>,
+        returning: &Vec<(u64, ServerInfo)>,
+    ) -> Result<Vec<ContainerServer>, String> {
         let mut target = 0;
 
         self.servers
@@ -109,18 +164,128 @@ impl ClusterInfo {
             index += 1;
         }
 
-        if target >= quantity {
-            let res: Vec<_> = self
-                .servers
-                .drain(0..index)
-                .map(|server_info| ContainerServer {
-                    server_info,
-                    devs: RefCell::new(HashSet::new()),
-                })
-                .collect();
-            Ok(res)
+        if target < quantity {
+            return Err(format!(
+                "not enough capacity at backend, remaining capacity: {}",
+                target
+            ));
+        }
+
+        // the reserved capacity must stay free during the reserved windows
+        let mut returning = returning.clone();
+        if let Some(lease_expiry) = lease_expiry {
+            for server in self.servers[0..index].iter() {
+                returning.push((lease_expiry, server.clone()));
+            }
+        }
+        if let Err(conflicts) = self.check_reservations(&self.servers[index..], &returning, None) {
+            return Err(format!(
+                "the capacity is reserved by {}",
+                conflicts
+                    .iter()
+                    .map(|r| format!("{} ({}-{})", r.reservation_uuid, r.start, r.end))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        let res: Vec<_> = self
+            .servers
+            .drain(0..index)
+            .map(|server_info| ContainerServer {
+                server_info,
+                devs: RefCell::new(HashSet::new()),
+            })
+            .collect();
+        Ok(res)
+    }
+}
+
+impl ClusterInfo {
+    // check that every reservation, together with the extra one, can be
+    // satisfied by the free servers and the servers returned before the
+    // reservation starts, return the reservations that can not be satisfied
+    pub(crate) fn check_reservations(
+        &self,
+        free: &[ServerInfo],
+        returning: &Vec<(u64, ServerInfo)>,
+        extra: Option<&Reservation>,
+    ) -> Result<(), Vec<Reservation>> {
+        let reservations: Vec<&Reservation> = self.reservations.iter().chain(extra).collect();
+
+        let conflicts: Vec<Reservation> = reservations
+            .iter()
+            .filter(|r| {
+                let servers = free
+                    .iter()
+                    .map(|s| s.max_capacity)
+                    .chain(
+                        returning
+                            .iter()
+                            .filter(|(expiry, _)| *expiry <= r.start)
+                            .map(|(_, s)| s.max_capacity),
+                    )
+                    .collect();
+                let demands = reservations
+                    .iter()
+                    .filter(|other| other.overlaps(r))
+                    .map(|other| other.capacity)
+                    .collect();
+                !servers_fit(servers, demands)
+            })
+            .map(|r| (*r).clone())
+            .collect();
+
+        if conflicts.len() == 0 {
+            Ok(())
         } else {
-            Err(target)
+            Err(conflicts)
+        }
+    }
+
+    pub(crate) fn free_servers(&self) -> &[ServerInfo] {
+        &self.servers[..]
+    }
+
+    // take a server out of the free servers
+    pub(crate) fn remove_server(&mut self, node_name: &str) -> Option<ServerInfo> {
+        let index = self.servers.iter().position(|s| s.node_name == node_name)?;
+        Some(self.servers.remove(index))
+    }
+
+    pub(crate) fn reservations(&self) -> &Vec<Reservation> {
+        &self.reservations
+    }
+
+    // add the reservation if it does not conflict with the others
+    pub(crate) fn add_reservation(
+        &mut self,
+        reservation: Reservation,
+        returning: &Vec<(u64, ServerInfo)>,
+    ) -> Result<(), Vec<Reservation>> {
+        self.check_reservations(&self.servers[..], returning, Some(&reservation))?;
+        self.reservations.push(reservation);
+        Ok(())
+    }
+
+    pub(crate) fn remove_reservation(
+        &mut self,
+        reservation_uuid: &uuid::Uuid,
+    ) -> Option<Reservation> {
+        let index = self
+            .reservations
+            .iter()
+            .position(|r| r.reservation_uuid == *reservation_uuid)?;
+        Some(self.reservations.remove(index))
+    }
+
+    pub(crate) fn set_reservation_error(&mut self, reservation_uuid: &uuid::Uuid, err: String) {
+        if let Some(r) = self
+            .reservations
+            .iter_mut()
+            .find(|r| r.reservation_uuid == *reservation_uuid)
+        {
+            r.last_error = Some(err);
         }
     }
 }
@@ -138,9 +303,21 @@ impl ClusterInfo {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) struct ContainerServer {
     server_info: ServerInfo,
+    #[serde(serialize_with = "serialize_sorted")]
     devs: RefCell<HashSet<u64>>,
 }
 
+// the devices are serialized in order, so that storing or exporting the
+// same server twice produces the same json value
+fn serialize_sorted<S: serde::Serializer>(
+    devs: &RefCell<HashSet<u64>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut devs: Vec<u64> = devs.borrow().iter().map(|id| *id).collect();
+    devs.sort();
+    devs.serialize(serializer)
+}
+
 impl ContainerServer {
     pub(crate) fn server_info(&self) -> &ServerInfo {
         return &self.server_info;
@@ -149,6 +326,10 @@ impl ContainerServer {
     pub(crate) fn devs(&self) -> std::cell::Ref<HashSet<u64>> {
         self.devs.borrow()
     }
+
+    pub(crate) fn reset_devs(&self, devs: HashSet<u64>) {
+        *self.devs.borrow_mut() = devs;
+    }
 }
 
 impl PartitionBin for ContainerServer {
@@ -245,4 +426,15 @@ impl IdAllocator {
     pub(crate) fn remaining(&self) -> usize {
         self.ids.len()
     }
+
+    pub(crate) fn is_free(&self, id: u8) -> bool {
+        self.ids.contains(&id)
+    }
+
+    // the allocator in which every id except the in_use ones is free
+    pub(crate) fn excluding(in_use: &HashSet<u8>) -> Self {
+        let mut allocator = Self::new();
+        allocator.ids.retain(|id| !in_use.contains(id));
+        allocator
+    }
 }
@@ -54,14 +54,21 @@ impl<L> Hash for Link<L> {
     }
 }
 
+// the links of a device are stored as edges between the device vertices,
+// they are only deserialized from emunets stored in the old format
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Device<D, L> {
     id: u64,
     server_name: String,
+    #[serde(default = "no_links", skip_serializing)]
     links: RefCell<HashSet<Link<L>>>,
     meta: D,
 }
 
+fn no_links<L>() -> RefCell<HashSet<Link<L>>> {
+    RefCell::new(HashSet::new())
+}
+
 impl<D, L> Device<D, L> {
     pub(crate) fn new(id: u64, server_name: String, meta: D) -> Self {
         Self {
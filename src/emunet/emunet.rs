@@ -43,10 +43,22 @@ pub(crate) struct Emunet {
     state: RefCell<EmunetState>,
     dev_count: Cell<u64>,
     servers: RefCell<HashMap<String, ContainerServer>>,
+    // devices and links are stored as vertices and edges of their own,
+    // they are only deserialized from emunets stored in the old format
+    #[serde(default, skip_serializing)]
     devices: RefCell<HashMap<u64, Device<DeviceMeta, LinkMeta>>>,
+    #[serde(default, skip_serializing)]
     links: RefCell<HashSet<(u64, u64)>>,
+    #[serde(default)]
+    device_vertices: RefCell<HashMap<u64, Uuid>>,
+    #[serde(default)]
+    node_vertices: RefCell<HashMap<String, Uuid>>,
     subnet_allocator: RefCell<SubnetAllocator>,
     version_num: Cell<u64>,
+    #[serde(default)]
+    revision: Cell<u64>,
+    #[serde(default)]
+    lease_expiry: Cell<Option<u64>>,
 }
 
 impl Emunet {
@@ -87,8 +99,12 @@ impl Emunet {
             servers: RefCell::new(hm),
             devices: RefCell::new(HashMap::new()),
             links: RefCell::new(HashSet::new()),
+            device_vertices: RefCell::new(HashMap::new()),
+            node_vertices: RefCell::new(HashMap::new()),
             subnet_allocator: RefCell::new(allocator),
             version_num: Cell::new(0),
+            revision: Cell::new(0),
+            lease_expiry: Cell::new(None),
         }
     }
 }
@@ -119,6 +135,10 @@ impl Emunet {
         self.dev_count.get()
     }
 
+    pub(crate) fn link_count(&self) -> u64 {
+        self.links.borrow().len() as u64
+    }
+
     pub(crate) fn servers(&self) -> std::cell::Ref<HashMap<String, ContainerServer>> {
         self.servers.borrow()
     }
@@ -136,6 +156,32 @@ impl Emunet {
     }
 }
 
+// the version of the emunet increases by one whenever its topology is
+// built, the revision increases by one whenever the emunet is stored, a
+// write only succeeds if the stored revision has not changed since the
+// emunet was read
+impl Emunet {
+    pub(crate) fn version_num(&self) -> u64 {
+        self.version_num.get()
+    }
+
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision.get()
+    }
+
+    // move to the next revision and return the revision being replaced
+    pub(crate) fn bump_revision(&self) -> u64 {
+        let revision = self.revision.get();
+        self.revision.set(revision + 1);
+        revision
+    }
+
+    // take back the revision of a write that has failed
+    pub(crate) fn reset_revision(&self, revision: u64) {
+        self.revision.set(revision);
+    }
+}
+
 impl Emunet {
     // modifying the state of the EmuNet
     pub(crate) fn state(&self) -> EmunetState {
@@ -147,6 +193,166 @@ impl Emunet {
     }
 }
 
+// seconds since the unix epoch
+pub(crate) fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("FATAL: system time is earlier than the unix epoch")
+        .as_secs()
+}
+
+// the expiry of a lease starting now, None if the lease is longer than
+// MAX_LEASE_SECS
+pub(crate) fn lease_expiry(lease_secs: u64) -> Option<u64> {
+    if lease_secs > super::MAX_LEASE_SECS {
+        return None;
+    }
+    unix_time().checked_add(lease_secs)
+}
+
+// the stages of a lease that the lease warning tells apart
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum LeaseStage {
+    Running,
+    Expiring,
+    Expired,
+}
+
+// an emunet without a lease never expires
+impl Emunet {
+    pub(crate) fn lease_expiry(&self) -> Option<u64> {
+        self.lease_expiry.get()
+    }
+
+    pub(crate) fn set_lease_expiry(&self, lease_expiry: Option<u64>) {
+        self.lease_expiry.set(lease_expiry);
+    }
+
+    pub(crate) fn is_lease_expired(&self, now: u64) -> bool {
+        self.lease_expiry.get().map(|e| e <= now).unwrap_or(false)
+    }
+
+    // the lease is expiring in its last LEASE_WARNING_SECS seconds
+    pub(crate) fn lease_stage(&self, now: u64) -> LeaseStage {
+        match self.lease_expiry.get() {
+            Some(expiry) if expiry <= now => LeaseStage::Expired,
+            Some(expiry) if expiry <= now + super::LEASE_WARNING_SECS => LeaseStage::Expiring,
+            _ => LeaseStage::Running,
+        }
+    }
+
+    // warn the user once the lease is expiring
+    pub(crate) fn lease_warning(&self, now: u64) -> Option<String> {
+        let expiry = self.lease_expiry.get()?;
+        match self.lease_stage(now) {
+            LeaseStage::Expired => Some(format!(
+                "lease expired {} seconds ago, the emunet is being deleted",
+                now - expiry
+            )),
+            LeaseStage::Expiring => Some(format!(
+                "lease expires in {} seconds, the emunet will be deleted unless the lease is renewed",
+                expiry - now
+            )),
+            LeaseStage::Running => None,
+        }
+    }
+}
+
+// the devices are stored on vertices of their own and the directed links
+// are stored as edges between the device vertices
+impl Emunet {
+    pub(crate) fn devices(&self) -> std::cell::Ref<HashMap<u64, Device<DeviceMeta, LinkMeta>>> {
+        self.devices.borrow()
+    }
+
+    pub(crate) fn device_vertex(&self, dev_id: u64) -> Option<Uuid> {
+        self.device_vertices
+            .borrow()
+            .get(&dev_id)
+            .map(|vid| vid.clone())
+    }
+
+    // the ids of the devices that have been stored on their own vertices
+    pub(crate) fn stored_dev_idxs(&self) -> Vec<u64> {
+        let mut dev_idxs: Vec<u64> = self.device_vertices.borrow().keys().map(|id| *id).collect();
+        dev_idxs.sort();
+        dev_idxs
+    }
+
+    // emunets stored in the old format carry the devices in their own json value
+    pub(crate) fn graph_loaded(&self) -> bool {
+        self.dev_count.get() == 0 || self.devices.borrow().len() > 0
+    }
+
+    // assign a vertex to every new device and forget the vertices of the
+    // removed devices, return the vertex ids of all the devices
+    pub(crate) fn assign_device_vertices(&self) -> HashMap<u64, Uuid> {
+        let devices = self.devices.borrow();
+        let mut device_vertices = self.device_vertices.borrow_mut();
+        device_vertices.retain(|dev_id, _| devices.contains_key(dev_id));
+        for dev_id in devices.keys() {
+            device_vertices
+                .entry(*dev_id)
+                .or_insert_with(indradb::util::generate_uuid_v1);
+        }
+        device_vertices.clone()
+    }
+
+    // the vertex linking the devices placed on a k8s node
+    pub(crate) fn node_vertex(&self, node_name: &str) -> Option<Uuid> {
+        self.node_vertices
+            .borrow()
+            .get(node_name)
+            .map(|vid| vid.clone())
+    }
+
+    // assign a vertex to every server of the emunet and to every server that
+    // a device is placed on, forget the vertices of the other servers and
+    // return the vertex ids of the remaining ones
+    pub(crate) fn assign_node_vertices(&self) -> HashMap<String, Uuid> {
+        let servers = self.servers.borrow();
+        let devices = self.devices.borrow();
+        let node_names: HashSet<String> = servers
+            .keys()
+            .cloned()
+            .chain(devices.values().map(|dev| dev.server_name()))
+            .collect();
+        let mut node_vertices = self.node_vertices.borrow_mut();
+        node_vertices.retain(|node_name, _| node_names.contains(node_name));
+        for node_name in node_names {
+            node_vertices
+                .entry(node_name)
+                .or_insert_with(indradb::util::generate_uuid_v1);
+        }
+        node_vertices.clone()
+    }
+
+    pub(crate) fn load_graph(
+        &self,
+        devices: Vec<(Uuid, Device<DeviceMeta, LinkMeta>)>,
+        links: Vec<Link<LinkMeta>>,
+    ) {
+        let mut devices_mut = self.devices.borrow_mut();
+        let mut device_vertices = self.device_vertices.borrow_mut();
+        for (vid, dev) in devices {
+            device_vertices.insert(dev.id(), vid);
+            devices_mut.insert(dev.id(), dev);
+        }
+
+        let mut links_mut = self.links.borrow_mut();
+        for link in links {
+            let (s, d) = link.link_id();
+            if s < d {
+                links_mut.insert((s, d));
+            }
+            devices_mut
+                .get(&s)
+                .expect("FATAL: this should not happen")
+                .add_link(link);
+        }
+    }
+}
+
 impl Emunet {
     pub(crate) fn build_emunet_graph(
         &self,
@@ -296,6 +502,12 @@ impl Emunet {
             .map(|dev| dev.meta().pod_name().to_string())
     }
 
+    pub(crate) fn dev_idxs(&self) -> Vec<u64> {
+        let mut dev_idxs: Vec<u64> = self.devices.borrow().keys().map(|id| *id).collect();
+        dev_idxs.sort();
+        dev_idxs
+    }
+
     pub(crate) fn update_device_login_info(&self, device_infos: &Vec<k8s_api::DeviceInfo>) {
         let mut podname_map = HashMap::new();
         let devices_borrow = self.devices.borrow();
@@ -374,8 +586,33 @@ impl Emunet {
         server_map.into_iter().map(|(_, cs)| cs).collect()
     }
 
-    // this is only called by restful::route_command module,
-    // and we automatically inherit the three preconditions
+    // a path for release_route_command has at least two devices of this
+    // emunet, every two consecutive ones being linked
+    pub(crate) fn check_path(&self, path: &[u64]) -> Result<(), String> {
+        if path.len() < 2 {
+            return Err("a path needs at least two devices".to_string());
+        }
+        let devices_ref = self.devices.borrow();
+        for dev_id in path.iter() {
+            if !devices_ref.contains_key(dev_id) {
+                return Err(format!("device {} does not exist", dev_id));
+            }
+        }
+        for hop in path.windows(2) {
+            let forward = devices_ref[&hop[0]].links().contains(&(hop[0], hop[1]));
+            let backward = devices_ref[&hop[1]].links().contains(&(hop[1], hop[0]));
+            if !(forward && backward) {
+                return Err(format!(
+                    "device {} and device {} are not linked",
+                    hop[0], hop[1]
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // this is only called by restful::route_command module, which checks
+    // the path with check_path first
     pub(crate) fn release_route_command(
         &self,
         path: &[u64],
@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 // LinkInfo represents an undirected edge connecting one node to another
 // LinkInfo is deserialized from the incoming HTTP message
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
 #[allow(dead_code)]
 pub(crate) struct InputLink<T> {
     pub(crate) edge_id: (u64, u64),
@@ -18,7 +19,7 @@ impl<T> InputLink<T> {
 }
 
 // DeviceInfo is deserialized from the incoming HTTP message
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
 #[allow(dead_code)]
 pub(crate) struct InputDevice<T> {
     pub(crate) id: u64,
@@ -31,14 +32,14 @@ impl<T> InputDevice<T> {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub(crate) struct InnerLink {
     pub(crate) dest_dev_id: u64,
     pub(crate) intf_name: String,
     pub(crate) ip: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub(crate) struct OutputDevice {
     pub(crate) id: u64,
     pub(crate) k8s_node_name: String,
@@ -49,7 +50,7 @@ pub(crate) struct OutputDevice {
     pub(crate) links: Vec<InnerLink>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub(crate) struct OutputLink {
     pub(crate) link_id: (u64, u64),
     pub(crate) details: HashMap<u64, InnerLink>,
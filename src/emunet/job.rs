@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub(crate) enum JobKind {
+    Init,
+    Update,
+    Delete,
+}
+
+impl std::convert::From<JobKind> for String {
+    fn from(k: JobKind) -> String {
+        match k {
+            JobKind::Init => "init".to_string(),
+            JobKind::Update => "update".to_string(),
+            JobKind::Delete => "delete".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub(crate) enum JobState {
+    Running,
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+impl std::convert::From<JobState> for String {
+    fn from(s: JobState) -> String {
+        match s {
+            JobState::Running => "running".to_string(),
+            JobState::Succeeded => "succeeded".to_string(),
+            JobState::Failed(inner) => format!("failed: {}", inner),
+            JobState::Cancelled => "cancelled".to_string(),
+        }
+    }
+}
+
+// Job records a long-running operation on an emunet, it is stored
+// on its own vertex and is only modified by the background task
+// that carries out the operation.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Job {
+    job_uuid: Uuid,
+    kind: JobKind,
+    emunet_uuid: Uuid,
+    user_name: String,
+    state: RefCell<JobState>,
+    logs: RefCell<Vec<String>>,
+    result: RefCell<Option<String>>,
+}
+
+impl Job {
+    pub(crate) fn new(job_uuid: Uuid, kind: JobKind, emunet_uuid: Uuid, user_name: String) -> Self {
+        Self {
+            job_uuid,
+            kind,
+            emunet_uuid,
+            user_name,
+            state: RefCell::new(JobState::Running),
+            logs: RefCell::new(Vec::new()),
+            result: RefCell::new(None),
+        }
+    }
+
+    pub(crate) fn job_uuid(&self) -> Uuid {
+        self.job_uuid.clone()
+    }
+
+    pub(crate) fn kind(&self) -> JobKind {
+        self.kind
+    }
+
+    pub(crate) fn emunet_uuid(&self) -> Uuid {
+        self.emunet_uuid.clone()
+    }
+
+    pub(crate) fn user_name(&self) -> &str {
+        &self.user_name
+    }
+
+    pub(crate) fn state(&self) -> JobState {
+        self.state.borrow().clone()
+    }
+
+    pub(crate) fn logs(&self) -> Vec<String> {
+        self.logs.borrow().clone()
+    }
+
+    pub(crate) fn result(&self) -> Option<String> {
+        self.result.borrow().clone()
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        match *self.state.borrow() {
+            JobState::Running => true,
+            _ => false,
+        }
+    }
+}
+
+impl Job {
+    pub(crate) fn log<S: std::convert::Into<String>>(&self, line: S) {
+        self.logs.borrow_mut().push(line.into());
+    }
+
+    pub(crate) fn succeed<S: std::convert::Into<String>>(&self, result: S) {
+        *self.result.borrow_mut() = Some(result.into());
+        *self.state.borrow_mut() = JobState::Succeeded;
+    }
+
+    pub(crate) fn fail<S: std::convert::Into<String>>(&self, err: S) {
+        *self.state.borrow_mut() = JobState::Failed(err.into());
+    }
+
+    pub(crate) fn cancel(&self) {
+        *self.state.borrow_mut() = JobState::Cancelled;
+    }
+}
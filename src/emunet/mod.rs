@@ -3,17 +3,30 @@ mod device;
 mod device_metadata;
 mod emunet;
 mod graph_io_format;
+mod job;
+mod ticket;
 mod user;
 mod utils;
 
 pub use cluster::ClusterConfig;
 pub use cluster::ClusterInfo;
 
-pub(crate) use cluster::{EmunetAccessInfo, IdAllocator, ServerInfo};
+pub(crate) use cluster::{EmunetAccessInfo, IdAllocator, Reservation, ServerInfo};
+pub(crate) use device::{Device, Link};
+pub(crate) use device_metadata::{DeviceMeta, LinkMeta};
 pub(crate) use emunet::*;
 pub(crate) use graph_io_format::{InputDevice, InputLink, OutputDevice, OutputLink};
-pub(crate) use user::{User, Retired};
+pub(crate) use job::{Job, JobKind};
+pub(crate) use ticket::{Ticket, TicketQueue, TicketState};
+pub(crate) use user::{constant_time_eq, hash_token, Quota, Retired, Usage, User};
 
 pub(crate) static MAX_DIRECTED_LINK_POWER: u32 = 14;
 pub(crate) static EMUNET_NUM_POWER: u32 = 8;
-pub(crate) static EMUNET_NODE_PROPERTY: &'static str = "default";
\ No newline at end of file
+pub(crate) static EMUNET_NODE_PROPERTY: &'static str = "default";
+pub(crate) static JOB_NODE_PROPERTY: &'static str = "job";
+pub(crate) static DEVICE_NODE_PROPERTY: &'static str = "device";
+pub(crate) static LINK_EDGE_PROPERTY: &'static str = "link";
+pub(crate) static K8S_NODE_PROPERTY: &'static str = "k8s_node";
+pub(crate) static PLACEMENT_EDGE_PROPERTY: &'static str = "placement";
+pub(crate) static LEASE_WARNING_SECS: u64 = 3600;
+pub(crate) static MAX_LEASE_SECS: u64 = 366 * 24 * 3600;
\ No newline at end of file
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// the number of finished tickets that are kept for polling
+static FINISHED_TICKET_LIMIT: usize = 1024;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub(crate) enum TicketState {
+    Waiting,
+    Created(Uuid),
+    Failed(String),
+}
+
+impl std::convert::From<TicketState> for String {
+    fn from(s: TicketState) -> String {
+        match s {
+            TicketState::Waiting => "waiting".to_string(),
+            TicketState::Created(_) => "created".to_string(),
+            TicketState::Failed(inner) => format!("failed: {}", inner),
+        }
+    }
+}
+
+// Ticket records a create_emunet request that is waiting for the cluster
+// to have enough free capacity.
+#[derive(Deserialize, Serialize, Clone)]
+pub(crate) struct Ticket {
+    pub(crate) ticket_uuid: Uuid,
+    pub(crate) user: String,
+    pub(crate) emunet: String,
+    pub(crate) capacity: u64,
+    // the lease starts when the emunet is created
+    pub(crate) lease_secs: Option<u64>,
+    pub(crate) priority: u32,
+    seq: u64,
+    pub(crate) state: TicketState,
+}
+
+impl Ticket {
+    pub(crate) fn is_waiting(&self) -> bool {
+        match self.state {
+            TicketState::Waiting => true,
+            _ => false,
+        }
+    }
+}
+
+// TicketQueue serves the waiting tickets with the highest priority first,
+// tickets with the same priority are served in FIFO order.
+#[derive(Deserialize, Serialize, Default)]
+pub(crate) struct TicketQueue {
+    next_seq: u64,
+    tickets: Vec<Ticket>,
+}
+
+impl TicketQueue {
+    pub(crate) fn enqueue(
+        &mut self,
+        user: String,
+        emunet: String,
+        capacity: u64,
+        lease_secs: Option<u64>,
+        priority: u32,
+    ) -> Ticket {
+        let ticket = Ticket {
+            ticket_uuid: indradb::util::generate_uuid_v1(),
+            user,
+            emunet,
+            capacity,
+            lease_secs,
+            priority,
+            seq: self.next_seq,
+            state: TicketState::Waiting,
+        };
+        self.next_seq += 1;
+        self.tickets.push(ticket.clone());
+        ticket
+    }
+
+    // the waiting tickets in the order that they are served
+    pub(crate) fn waiting(&self) -> Vec<&Ticket> {
+        let mut waiting: Vec<&Ticket> = self.tickets.iter().filter(|t| t.is_waiting()).collect();
+        waiting.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.seq.cmp(&b.seq)));
+        waiting
+    }
+
+    // the number of waiting tickets that are served before the ticket
+    pub(crate) fn position(&self, ticket_uuid: &Uuid) -> Option<usize> {
+        self.waiting()
+            .iter()
+            .position(|t| &t.ticket_uuid == ticket_uuid)
+    }
+
+    pub(crate) fn get(&self, ticket_uuid: &Uuid) -> Option<&Ticket> {
+        self.tickets.iter().find(|t| &t.ticket_uuid == ticket_uuid)
+    }
+
+    pub(crate) fn finish(&mut self, ticket_uuid: &Uuid, state: TicketState) {
+        if let Some(ticket) = self
+            .tickets
+            .iter_mut()
+            .find(|t| &t.ticket_uuid == ticket_uuid)
+        {
+            ticket.state = state;
+        }
+        self.drop_finished();
+    }
+
+    // keep the outcome of a creation that never waited in the queue, like
+    // the one booked by a reservation, so that it can be polled as a ticket
+    pub(crate) fn record(
+        &mut self,
+        ticket_uuid: Uuid,
+        user: String,
+        emunet: String,
+        capacity: u64,
+        state: TicketState,
+    ) {
+        self.tickets.push(Ticket {
+            ticket_uuid,
+            user,
+            emunet,
+            capacity,
+            lease_secs: None,
+            priority: 0,
+            seq: self.next_seq,
+            state,
+        });
+        self.next_seq += 1;
+        self.drop_finished();
+    }
+
+    // drop the oldest finished tickets
+    fn drop_finished(&mut self) {
+        let finished = self.tickets.iter().filter(|t| !t.is_waiting()).count();
+        if finished > FINISHED_TICKET_LIMIT {
+            let mut excess = finished - FINISHED_TICKET_LIMIT;
+            self.tickets.retain(|t| {
+                if excess > 0 && !t.is_waiting() {
+                    excess -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    pub(crate) fn remove(&mut self, ticket_uuid: &Uuid) -> Option<Ticket> {
+        let idx = self
+            .tickets
+            .iter()
+            .position(|t| &t.ticket_uuid == ticket_uuid)?;
+        Some(self.tickets.remove(idx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enqueue(queue: &mut TicketQueue, emunet: &str, priority: u32) -> Uuid {
+        queue
+            .enqueue("user".to_string(), emunet.to_string(), 1, None, priority)
+            .ticket_uuid
+    }
+
+    #[test]
+    fn tickets_are_served_by_priority_then_fifo() {
+        let mut queue = TicketQueue::default();
+        let low_first = enqueue(&mut queue, "low_first", 0);
+        let high = enqueue(&mut queue, "high", 2);
+        let low_second = enqueue(&mut queue, "low_second", 0);
+        let middle = enqueue(&mut queue, "middle", 1);
+
+        let order: Vec<&str> = queue.waiting().iter().map(|t| &t.emunet[..]).collect();
+        assert_eq!(order, vec!["high", "middle", "low_first", "low_second"]);
+        assert_eq!(queue.position(&high), Some(0));
+        assert_eq!(queue.position(&low_second), Some(3));
+
+        // finished and removed tickets leave the waiting order
+        queue.finish(&high, TicketState::Failed("test".to_string()));
+        queue.remove(&low_first);
+        assert_eq!(queue.position(&high), None);
+        assert_eq!(queue.position(&middle), Some(0));
+        assert_eq!(queue.position(&low_second), Some(1));
+        assert!(queue.get(&high).is_some());
+        assert!(queue.get(&low_first).is_none());
+
+        // a recorded outcome can be polled but is never served
+        let recorded = Uuid::from_u128(1);
+        let state = TicketState::Created(Uuid::from_u128(2));
+        queue.record(recorded, "user".to_string(), "booked".to_string(), 1, state);
+        assert!(queue.get(&recorded).is_some());
+        assert_eq!(queue.position(&recorded), None);
+        assert_eq!(queue.waiting().len(), 2);
+    }
+}
@@ -1,10 +1,12 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use std::cell::RefCell;
 use std::collections::HashMap;
 
 use super::emunet::Emunet;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub(crate) struct Retired {
     pub(crate) version: u64,
     pub(crate) name: String,
@@ -12,19 +14,99 @@ pub(crate) struct Retired {
     pub(crate) edges: Vec<(u64, u64)>,
 }
 
+impl Retired {
+    pub(crate) fn new(emunet: &Emunet) -> Self {
+        let history = emunet.release_history();
+        Self {
+            version: history.0,
+            name: history.1,
+            nodes: history.2,
+            edges: history.3,
+        }
+    }
+}
+
+// limits that the admin imposes on a user, None means unlimited
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
+pub(crate) struct Quota {
+    pub(crate) max_emunets: Option<u64>,
+    pub(crate) max_capacity: Option<u64>,
+    pub(crate) max_links: Option<u64>,
+}
+
+// resources held by all the emunets of a user
+#[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
+pub(crate) struct Usage {
+    pub(crate) emunets: u64,
+    pub(crate) capacity: u64,
+    pub(crate) links: u64,
+}
+
+impl Quota {
+    // check whether the usage stays within the quota
+    pub(crate) fn check(&self, usage: &Usage) -> Result<(), String> {
+        let limits = [
+            ("emunets", self.max_emunets, usage.emunets),
+            ("capacity", self.max_capacity, usage.capacity),
+            ("links", self.max_links, usage.links),
+        ];
+        for (name, limit, used) in limits.iter() {
+            match limit {
+                Some(limit) if *used > *limit => {
+                    return Err(format!(
+                        "quota exceeded: {} {} is larger than the limit {}",
+                        name, used, limit
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// the tokens are random, a digest is enough to keep them out of the stored
+// records
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha1::digest(token.as_bytes()))
+}
+
+// compare the whole strings, so that the time taken does not tell how much
+// of them matches
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct User {
     name: String,
+    // the plain token of the users stored before the tokens were hashed
+    #[serde(default, skip_serializing)]
+    token: String,
+    #[serde(default)]
+    token_hash: String,
     emunet_name_to_uuid: RefCell<HashMap<String, uuid::Uuid>>,
     retired: RefCell<Vec<Retired>>,
+    #[serde(default)]
+    quota: RefCell<Quota>,
 }
 
 impl User {
-    pub(crate) fn new<S: std::convert::Into<String>>(name: S) -> Self {
+    pub(crate) fn new<S: std::convert::Into<String>, T: std::convert::Into<String>>(
+        name: S,
+        token: T,
+    ) -> Self {
         Self {
             name: name.into(),
+            token: String::new(),
+            token_hash: hash_token(&token.into()),
             emunet_name_to_uuid: RefCell::new(HashMap::new()),
             retired: RefCell::new(Vec::new()),
+            quota: RefCell::new(Quota::default()),
         }
     }
 
@@ -53,18 +135,26 @@ impl User {
         self.emunet_name_to_uuid.borrow_mut().remove(emunet_name)
     }
 
-    pub(crate) fn add_retired(&self, emunet: &Emunet) {
-        let history = emunet.release_history();
-        self.retired.borrow_mut().push(Retired {
-            version: history.0,
-            name: history.1,
-            nodes: history.2,
-            edges: history.3,
-        });
+    pub(crate) fn add_retired(&self, retired: Retired) {
+        self.retired.borrow_mut().push(retired);
     }
 }
 
 impl User {
+    // users registered before tokens were introduced have an empty token
+    // and can only be accessed by the admin
+    pub(crate) fn token_matches(&self, token_hash: &str) -> bool {
+        self.token_hash.len() > 0 && constant_time_eq(&self.token_hash, token_hash)
+    }
+
+    // replace the plain token of a user stored before the tokens were hashed
+    pub(crate) fn hash_plain_token(&mut self) {
+        if self.token.len() > 0 {
+            self.token_hash = hash_token(&self.token);
+            self.token.clear();
+        }
+    }
+
     pub(crate) fn into_uuid_map(self) -> HashMap<String, uuid::Uuid> {
         let hm = std::mem::replace(
             &mut (*self.emunet_name_to_uuid.borrow_mut()),
@@ -76,4 +166,28 @@ impl User {
     pub(crate) fn get_retired_emunets(&self) -> Vec<Retired> {
         self.retired.borrow().clone()
     }
+
+    pub(crate) fn emunet_entries(&self) -> Vec<(String, uuid::Uuid)> {
+        self.emunet_name_to_uuid
+            .borrow()
+            .iter()
+            .map(|(name, uuid)| (name.clone(), uuid.clone()))
+            .collect()
+    }
+
+    pub(crate) fn emunet_uuids(&self) -> Vec<uuid::Uuid> {
+        self.emunet_name_to_uuid
+            .borrow()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn quota(&self) -> Quota {
+        self.quota.borrow().clone()
+    }
+
+    pub(crate) fn set_quota(&self, quota: Quota) {
+        *self.quota.borrow_mut() = quota;
+    }
 }
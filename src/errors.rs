@@ -0,0 +1,118 @@
+use std::convert::From;
+use std::fmt;
+
+use indradb_proto::ClientError;
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+use crate::database::errors::ConnectorError;
+
+// Error is what the request handlers and the background tasks of the
+// mocknet server fail with, the code of each variant is reported to the
+// clients together with the message.
+#[derive(Debug, Clone)]
+pub enum Error {
+    // the database can not be reached or fails the request
+    DbUnavailable(String),
+    // the core records are missing from the database
+    NotInitialized,
+    // a stored record can not be decoded
+    Corrupted(String),
+    EmunetNotFound(Uuid),
+    // the stored emunet has been modified since it was read
+    Conflict(Uuid),
+    // a user, job, ticket, reservation or device, named by the string
+    NotFound(String),
+    // the emunet or the job is not in a state that allows the request
+    InvalidState(String),
+    InvalidInput(String),
+    AlreadyExists(String),
+    // the cluster or the emunet does not have enough servers
+    CapacityExceeded(String),
+    QuotaExceeded(String),
+    Unauthorized(String),
+    PermissionDenied(String),
+    // the k8s api server fails the request
+    K8sUnavailable(String),
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::DbUnavailable(_) => "DB_UNAVAILABLE",
+            Error::NotInitialized => "NOT_INITIALIZED",
+            Error::Corrupted(_) => "CORRUPTED_RECORD",
+            Error::EmunetNotFound(_) => "EMUNET_NOT_FOUND",
+            Error::Conflict(_) => "VERSION_CONFLICT",
+            Error::NotFound(_) => "NOT_FOUND",
+            Error::InvalidState(_) => "INVALID_STATE",
+            Error::InvalidInput(_) => "INVALID_INPUT",
+            Error::AlreadyExists(_) => "ALREADY_EXISTS",
+            Error::CapacityExceeded(_) => "CAPACITY_EXCEEDED",
+            Error::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            Error::Unauthorized(_) => "UNAUTHORIZED",
+            Error::PermissionDenied(_) => "PERMISSION_DENIED",
+            Error::K8sUnavailable(_) => "K8S_UNAVAILABLE",
+        }
+    }
+
+    // the HTTP status that the restful api answers with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::DbUnavailable(_) | Error::NotInitialized => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Corrupted(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::EmunetNotFound(_) | Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_)
+            | Error::InvalidState(_)
+            | Error::AlreadyExists(_)
+            | Error::CapacityExceeded(_) => StatusCode::CONFLICT,
+            Error::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Error::QuotaExceeded(_) | Error::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::K8sUnavailable(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    pub(crate) fn corrupted<E: fmt::Display>(record: &str, e: E) -> Self {
+        Error::Corrupted(format!("{}: {}", record, e))
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(e: ClientError) -> Error {
+        Self::DbUnavailable(format!("{}", e))
+    }
+}
+
+impl From<ConnectorError> for Error {
+    fn from(e: ConnectorError) -> Error {
+        Self::DbUnavailable(format!("{}", e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DbUnavailable(reason) => write!(f, "database unavailable: {}", reason),
+            Error::NotInitialized => write!(f, "database is not correctly initialized"),
+            Error::Corrupted(reason) => write!(f, "corrupted record, {}", reason),
+            Error::EmunetNotFound(emunet_uuid) => {
+                write!(f, "emunet {} does not exist", emunet_uuid)
+            }
+            Error::Conflict(emunet_uuid) => {
+                write!(f, "emunet {} has been modified concurrently", emunet_uuid)
+            }
+            Error::NotFound(target) => write!(f, "{} does not exist", target),
+            Error::InvalidState(s)
+            | Error::InvalidInput(s)
+            | Error::AlreadyExists(s)
+            | Error::CapacityExceeded(s)
+            | Error::QuotaExceeded(s)
+            | Error::Unauthorized(s)
+            | Error::PermissionDenied(s)
+            | Error::K8sUnavailable(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,8 +1,13 @@
+// the route filters of warp are nested deeply
+#![recursion_limit = "256"]
+
 pub mod algo;
 pub mod cli;
 pub mod database;
 pub mod emunet;
+pub mod errors;
 pub mod restful;
+pub mod topology;
 
 mod grpc;
 pub use grpc::k8s_api;
\ No newline at end of file
@@ -1,38 +1,39 @@
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use warp::Filter;
 
 use super::Response;
 use crate::database::{helpers, Client, Connector};
 use crate::emunet::ServerInfo;
+use crate::errors::Error;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "AddNodesRequest")]
 struct Request {
     k8s_nodes: Vec<ServerInfo>,
 }
 
-async fn add_nodes(req: Request, client: &mut Client) -> Result<Response<()>, ClientError> {
+async fn add_nodes(req: Request, client: &mut Client) -> Result<Response<()>, Error> {
     let mut guarded_tran = client.guarded_tran().await?;
 
     let mut cluster_info = helpers::get_cluster_info(&mut guarded_tran).await?;
     for server in req.k8s_nodes {
         let res = cluster_info.add_server_info(server.node_name.clone(), server.max_capacity);
         if res == false {
-            return Ok(Response::fail(format!(
+            return Ok(Response::fail(Error::InvalidInput(format!(
                 "invalid node name {}",
                 server.node_name
-            )));
+            ))));
         }
     }
 
-    helpers::set_cluster_info(&mut guarded_tran, cluster_info)
-        .await
-        .unwrap();
+    helpers::set_cluster_info(&mut guarded_tran, cluster_info).await?;
+    super::emunet_creation::capacity_returned();
 
     Ok(Response::success(()))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
+async fn guard(req: Request, mut client: Client) -> Result<super::Reply, warp::Rejection> {
     let res = add_nodes(req, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
@@ -47,5 +48,12 @@ async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, wa
 pub fn build_filter(
     connector: Connector,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
-    super::filter_template("add_nodes".to_string(), connector, guard)
+    super::admin_filter_template("add_nodes".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/add_nodes", "add k8s nodes to the cluster")
+        .admin()
+        .body::<Request>()
+        .reply::<()>();
 }
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use warp::Filter;
+
+use super::reject;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::{constant_time_eq, hash_token, User};
+use crate::errors::Error;
+
+lazy_static! {
+    static ref ADMIN_TOKEN: Mutex<Option<String>> = Mutex::new(None);
+}
+
+// the admin token is handed to the server on the command line, requests
+// carrying it are allowed to operate on every user and on the cluster
+pub fn set_admin_token(token: String) {
+    *ADMIN_TOKEN.lock().unwrap() = Some(token);
+}
+
+pub fn generate_token() -> String {
+    uuid::Uuid::new_v4().to_simple().to_string()
+}
+
+// the identity behind a bearer token
+#[derive(Clone, Debug)]
+pub(crate) enum Caller {
+    Admin,
+    User(String),
+}
+
+impl Caller {
+    pub(crate) fn is_admin(&self) -> bool {
+        match self {
+            Caller::Admin => true,
+            _ => false,
+        }
+    }
+
+    // whether the caller can operate on the resources owned by user
+    pub(crate) fn can_access(&self, user: &str) -> bool {
+        match self {
+            Caller::Admin => true,
+            Caller::User(name) => name == user,
+        }
+    }
+
+    pub(crate) fn deny<S: std::fmt::Display>(&self, target: S) -> Error {
+        Error::PermissionDenied(match self {
+            Caller::Admin => format!("admin can not access {}", target),
+            Caller::User(name) => format!("user {} can not access {}", name, target),
+        })
+    }
+}
+
+fn bearer_token(header: Option<String>) -> Option<String> {
+    header.and_then(|value| {
+        let value = value.trim();
+        if value.len() > 7 && value[..7].eq_ignore_ascii_case("bearer ") {
+            Some(value[7..].trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+async fn authenticate(token: &str, client: &mut Client) -> Result<Option<Caller>, Error> {
+    let is_admin = ADMIN_TOKEN
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|t| constant_time_eq(t, token))
+        .unwrap_or(false);
+    if is_admin {
+        return Ok(Some(Caller::Admin));
+    }
+
+    // only the hashes of the user tokens are stored
+    let token_hash = hash_token(token);
+    let mut guarded_tran = client.scoped_tran(Scope::new().read_core()).await?;
+    let user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
+    Ok(user_map
+        .into_iter()
+        .find(|(_, user)| user.token_matches(&token_hash))
+        .map(|(name, _)| Caller::User(name)))
+}
+
+async fn guard(
+    header: Option<String>,
+    mut client: Client,
+) -> Result<(Caller, Client), warp::Rejection> {
+    let token = match bearer_token(header) {
+        Some(token) => token,
+        None => {
+            return Err(reject(Error::Unauthorized(
+                "missing bearer token".to_string(),
+            )))
+        }
+    };
+
+    match authenticate(&token, &mut client).await {
+        Ok(Some(caller)) => Ok((caller, client)),
+        Ok(None) => Err(reject(Error::Unauthorized(
+            "invalid bearer token".to_string(),
+        ))),
+        Err(e) => {
+            client.notify_failure();
+            Err(reject(e))
+        }
+    }
+}
+
+// extract the caller identified by the bearer token together with a database client
+pub(crate) fn caller_filter(
+    connector: Connector,
+) -> impl Filter<Extract = (Caller, Client), Error = warp::Rejection> + Send + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(super::client_filter(connector))
+        .and_then(guard)
+        .untuple_one()
+}
+
+// only let the admin through, used by the cluster maintainance endpoints
+pub(crate) fn admin_filter(
+    connector: Connector,
+) -> impl Filter<Extract = (Client,), Error = warp::Rejection> + Send + Clone {
+    caller_filter(connector).and_then(|caller: Caller, client: Client| async move {
+        if caller.is_admin() {
+            Ok(client)
+        } else {
+            Err(reject(caller.deny("the maintainance api")))
+        }
+    })
+}
@@ -1,23 +1,19 @@
-use indradb_proto::ClientError;
 use warp::Filter;
 
 use super::Response;
 use crate::database::{helpers, Client, Connector};
 use crate::emunet::ServerInfo;
+use crate::errors::Error;
 
-async fn clear_garbage_servers(
-    client: &mut Client,
-) -> Result<Response<Vec<ServerInfo>>, ClientError> {
+async fn clear_garbage_servers(client: &mut Client) -> Result<Response<Vec<ServerInfo>>, Error> {
     let mut guarded_tran = client.guarded_tran().await?;
     let garbage_servers = helpers::get_garbage_servesr(&mut guarded_tran).await?;
-    helpers::set_garbage_servesr(&mut guarded_tran, Vec::new())
-        .await
-        .unwrap();
+    helpers::set_garbage_servesr(&mut guarded_tran, Vec::new()).await?;
 
     Ok(Response::success(garbage_servers))
 }
 
-async fn guard(mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
+async fn guard(mut client: Client) -> Result<super::Reply, warp::Rejection> {
     let res = clear_garbage_servers(&mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
@@ -32,16 +28,19 @@ async fn guard(mut client: Client) -> Result<warp::reply::Json, warp::Rejection>
 pub fn build_filter(
     connector: Connector,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
-    let connector_filter = warp::any()
-        .map(move || {
-            let clone = connector.clone();
-            clone
-        })
-        .and_then(super::get_client);
     warp::post()
         .and(warp::path("v1"))
         .and(warp::path("clear_garbage_servers"))
         .and(warp::path::end())
-        .and(connector_filter)
+        .and(super::auth::admin_filter(connector))
         .and_then(guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/clear_garbage_servers",
+        "return the servers left by failed deletions",
+    )
+    .admin()
+    .reply::<Vec<ServerInfo>>();
+}
@@ -0,0 +1,81 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+use super::Response;
+use crate::database::fsck::{self, Violation};
+use crate::database::{Client, Connector};
+use crate::errors::Error;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "FsckRequest")]
+struct Request {
+    // fix the violations that can be repaired automatically
+    #[serde(default)]
+    repair: bool,
+}
+
+async fn check(req: Request, client: &mut Client) -> Result<Response<Vec<Violation>>, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    let violations = fsck::check(&mut guarded_tran, req.repair).await?;
+    Ok(Response::success(violations))
+}
+
+async fn guard(req: Request, mut client: Client) -> Result<super::Reply, warp::Rejection> {
+    let res = check(req, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::admin_api_template("fsck".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/admin/fsck",
+        "check the stored state and optionally repair it",
+    )
+    .admin()
+    .body::<Request>()
+    .reply::<Vec<Violation>>();
+}
+
+pub async fn mnctl_admin_fsck(repair: bool, warp_addr: &str) -> Result<(), String> {
+    let req = Request { repair };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/admin/fsck", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<Vec<Violation>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success == false {
+        return Err(response.message);
+    }
+    let violations = response.data.unwrap();
+    if violations.len() == 0 {
+        println!("no violation is found");
+    }
+    for violation in violations.iter() {
+        if violation.repaired {
+            println!("repaired: {}", violation.message);
+        } else {
+            println!("{}", violation.message);
+        }
+    }
+    Ok(())
+}
@@ -1,62 +1,153 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
-use serde::Deserialize;
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
 use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
 use super::Response;
-use crate::database::{helpers, Client, Connector};
-use crate::emunet::{Emunet, User};
+use crate::database::{helpers, Client, Connector, GuardedTransaction};
+use crate::emunet::{Emunet, Reservation, Ticket, TicketState, User, MAX_LEASE_SECS};
+use crate::errors::Error;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "CreateEmunetRequest")]
 struct Request {
     user: String,
     emunet: String,
     capacity: u64,
+    lease_secs: Option<u64>, // the emunet is deleted once the lease expires
+    queue: Option<bool>,     // wait in the queue if the cluster is short of capacity
+    priority: Option<u32>,
 }
 
-async fn create_emunet(req: Request, client: &mut Client) -> Result<Response<Uuid>, ClientError> {
-    let mut tran = client.guarded_tran().await?;
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub(crate) struct TicketInfo {
+    pub(crate) ticket_uuid: Uuid,
+    pub(crate) state: String,
+    pub(crate) emunet_uuid: Option<Uuid>,
+    pub(crate) position: Option<usize>,
+}
+
+// a queued request is answered with a ticket instead of the emunet uuid
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "CreateEmunetData")]
+#[serde(untagged)]
+pub(crate) enum ResponseData {
+    Created(Uuid),
+    Queued(TicketInfo),
+}
+
+// an emunet to create, either requested through the api, booked by a
+// reservation or waiting in the ticket queue
+pub(crate) struct Creation {
+    pub(crate) user: String,
+    pub(crate) emunet: String,
+    pub(crate) capacity: u64,
+    pub(crate) lease_expiry: Option<u64>,
+    pub(crate) reservation_uuid: Option<Uuid>,
+}
 
-    let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut tran).await?;
+impl From<&Request> for Creation {
+    fn from(req: &Request) -> Self {
+        Self {
+            user: req.user.clone(),
+            emunet: req.emunet.clone(),
+            capacity: req.capacity,
+            lease_expiry: req.lease_secs.and_then(crate::emunet::lease_expiry),
+            reservation_uuid: None,
+        }
+    }
+}
+
+impl From<&Ticket> for Creation {
+    fn from(ticket: &Ticket) -> Self {
+        Self {
+            user: ticket.user.clone(),
+            emunet: ticket.emunet.clone(),
+            capacity: ticket.capacity,
+            lease_expiry: ticket.lease_secs.and_then(crate::emunet::lease_expiry),
+            reservation_uuid: None,
+        }
+    }
+}
+
+// create the emunet in the transaction, nothing is written to the database
+// if the creation fails, a creation failing with CapacityExceeded may be
+// retried once the cluster has enough free capacity
+pub(crate) async fn try_create(
+    req: Creation,
+    caller: &Caller,
+    tran: &mut GuardedTransaction,
+) -> Result<Result<Uuid, Error>, Error> {
+    if !caller.can_access(&req.user) {
+        return Ok(Err(caller.deny(format!("user {}", req.user))));
+    }
+
+    let mut user_map: HashMap<String, User> = helpers::get_user_map(tran).await?;
     if user_map.get(&req.user).is_none() {
-        return Ok(Response::fail("invalid user name".to_string()));
+        return Ok(Err(Error::NotFound(format!("user {}", req.user))));
+    }
+
+    // the new emunet must fit in the quota of the user
+    let quota = user_map.get(&req.user).unwrap().quota();
+    let emunet_uuids = user_map.get(&req.user).unwrap().emunet_uuids();
+    let mut usage = helpers::get_usage(tran, emunet_uuids).await?;
+    usage.emunets += 1;
+    usage.capacity += req.capacity;
+    if let Err(s) = quota.check(&usage) {
+        return Ok(Err(Error::QuotaExceeded(s)));
     }
 
     let user_mut = user_map.get_mut(&req.user).unwrap();
     let emunet_uuid = match user_mut.register_emunet(&req.emunet) {
         Some(uuid) => uuid,
         None => {
-            return Ok(Response::fail(format!(
+            return Ok(Err(Error::AlreadyExists(format!(
                 "invalid emunet name {}",
                 req.emunet
-            )));
+            ))));
         }
     };
 
-    let mut id_allocator = helpers::get_emunet_id_allocator(&mut tran).await?;
+    let mut id_allocator = helpers::get_emunet_id_allocator(tran).await?;
     let emunet_id = match id_allocator.alloc() {
         Some(id) => id,
         None => {
-            return Ok(Response::fail(format!("too many emunets are created")));
+            return Ok(Err(Error::CapacityExceeded(format!(
+                "too many emunets are created"
+            ))));
         }
     };
 
-    let mut cluster_info = helpers::get_cluster_info(&mut tran).await?;
-    let allocation = match cluster_info.allocate_servers(req.capacity) {
+    let returning = helpers::get_leased_servers(tran).await?;
+    let mut cluster_info = helpers::get_cluster_info(tran).await?;
+    // the reserved capacity is handed over to the emunet booked by the reservation
+    if let Some(reservation_uuid) = req.reservation_uuid.as_ref() {
+        if cluster_info.remove_reservation(reservation_uuid).is_none() {
+            return Ok(Err(Error::NotFound(format!(
+                "reservation {}",
+                reservation_uuid
+            ))));
+        }
+    }
+    let allocation = match cluster_info.allocate_servers(req.capacity, req.lease_expiry, &returning)
+    {
         Ok(alloc) => alloc,
-        Err(remaining) => {
-            return Ok(Response::fail(format!(
-                "not enough capacity at backend, remaining capacity: {}",
-                remaining
-            )));
+        Err(s) => {
+            return Ok(Err(Error::CapacityExceeded(s)));
         }
     };
 
     // the following steps should never fail
-    if !(helpers::create_vertex(&mut tran, emunet_uuid.clone()).await?) {
-        panic!(format!("invalid emunet uuid {}", emunet_uuid));
+    if !(helpers::create_vertex(tran, emunet_uuid.clone()).await?) {
+        return Err(Error::Corrupted(format!(
+            "emunet vertex {} already exists",
+            emunet_uuid
+        )));
     }
 
     let emunet = Emunet::new(
@@ -68,22 +159,82 @@ async fn create_emunet(req: Request, client: &mut Client) -> Result<Response<Uui
         cluster_info.emunet_access_info().clone(),
         allocation,
     );
-    let fut = helpers::set_emunet(&mut tran, &emunet);
-    assert!(fut.await.unwrap() == true);
+    emunet.set_lease_expiry(req.lease_expiry);
+    let fut = helpers::set_emunet(tran, &emunet);
+    if !fut.await? {
+        return Err(Error::Conflict(emunet_uuid));
+    }
+
+    helpers::set_user_map(tran, user_map).await?;
+    helpers::set_cluster_info(tran, cluster_info).await?;
+    helpers::set_emunet_id_allocator(tran, id_allocator).await?;
+
+    Ok(Ok(emunet_uuid))
+}
+
+async fn create_emunet(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<ResponseData>, Error> {
+    // the lease is checked here, the creations built from the request and
+    // from its ticket take it as valid
+    if let Some(lease_secs) = req.lease_secs {
+        if crate::emunet::lease_expiry(lease_secs).is_none() {
+            return Ok(Response::fail(Error::InvalidInput(format!(
+                "lease_secs can be at most {}",
+                MAX_LEASE_SECS
+            ))));
+        }
+    }
+
+    let mut tran = client.guarded_tran().await?;
+
+    let e = match try_create((&req).into(), caller, &mut tran).await? {
+        Ok(emunet_uuid) => return Ok(Response::success(ResponseData::Created(emunet_uuid))),
+        Err(e) => e,
+    };
+    let s = match e {
+        Error::CapacityExceeded(s) if req.queue.unwrap_or(false) => s,
+        e => return Ok(Response::fail(e)),
+    };
+    // a ticket that can never be served would block the queue forever
+    let total_capacity = helpers::get_total_capacity(&mut tran).await?;
+    if req.capacity > total_capacity {
+        return Ok(Response::fail(Error::CapacityExceeded(format!(
+            "the capacity {} exceeds the total capacity {} of the cluster",
+            req.capacity, total_capacity
+        ))));
+    }
 
-    helpers::set_user_map(&mut tran, user_map).await.unwrap();
-    helpers::set_cluster_info(&mut tran, cluster_info)
-        .await
-        .unwrap();
-    helpers::set_emunet_id_allocator(&mut tran, id_allocator)
-        .await
-        .unwrap();
+    let mut ticket_queue = helpers::get_ticket_queue(&mut tran).await?;
+    let ticket = ticket_queue.enqueue(
+        req.user,
+        req.emunet,
+        req.capacity,
+        req.lease_secs,
+        req.priority.unwrap_or(0),
+    );
+    let position = ticket_queue.position(&ticket.ticket_uuid);
+    helpers::set_ticket_queue(&mut tran, ticket_queue).await?;
 
-    Ok(Response::success(emunet_uuid))
+    // the message tells why the creation has to wait
+    let mut resp = Response::success(ResponseData::Queued(TicketInfo {
+        ticket_uuid: ticket.ticket_uuid,
+        state: ticket.state.into(),
+        emunet_uuid: None,
+        position,
+    }));
+    resp.message = s;
+    Ok(resp)
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = create_emunet(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = create_emunet(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -94,8 +245,188 @@ async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, wa
     }
 }
 
+lazy_static! {
+    static ref CAPACITY_RETURNED: Notify = Notify::new();
+}
+
+static TICKET_CHECK_INTERVAL: u64 = 30;
+
+// wake up the ticket scheduler after servers are returned to the cluster
+pub(crate) fn capacity_returned() {
+    CAPACITY_RETURNED.notify_one();
+}
+
+// create the emunets of the waiting tickets in order, a ticket that still
+// does not fit blocks the tickets behind it, unless it can never fit
+async fn serve_tickets(client: &mut Client) -> Result<(), Error> {
+    loop {
+        let mut tran = client.guarded_tran().await?;
+
+        let mut ticket_queue = helpers::get_ticket_queue(&mut tran).await?;
+        let ticket = match ticket_queue.waiting().first() {
+            None => return Ok(()),
+            Some(ticket) => (*ticket).clone(),
+        };
+        let caller = Caller::User(ticket.user.clone());
+        let state = match try_create((&ticket).into(), &caller, &mut tran).await? {
+            Ok(emunet_uuid) => TicketState::Created(emunet_uuid),
+            // the cluster may shrink after the ticket is queued
+            Err(Error::CapacityExceeded(s))
+                if ticket.capacity > helpers::get_total_capacity(&mut tran).await? =>
+            {
+                TicketState::Failed(format!(
+                    "the capacity {} exceeds the total capacity of the cluster, {}",
+                    ticket.capacity, s
+                ))
+            }
+            Err(Error::CapacityExceeded(_)) => return Ok(()),
+            Err(e) => TicketState::Failed(format!("{}", e)),
+        };
+        ticket_queue.finish(&ticket.ticket_uuid, state);
+        helpers::set_ticket_queue(&mut tran, ticket_queue).await?;
+    }
+}
+
+// serve the ticket queue whenever capacity is returned to the cluster, the
+// queue is also checked periodically as leased servers and cancelled
+// reservations free capacity as well
+pub async fn ticket_scheduler(connector: Connector) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICKET_CHECK_INTERVAL));
+    loop {
+        tokio::select! {
+            _ = CAPACITY_RETURNED.notified() => {},
+            _ = interval.tick() => {},
+        }
+
+        let mut client = match connector.connect().await {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        if let Err(_) = serve_tickets(&mut client).await {
+            client.notify_failure();
+        }
+    }
+}
+
+static RESERVATION_CHECK_INTERVAL: u64 = 30;
+
+// reservations whose window has started, the outcome of a reservation is
+// kept in the ticket queue under the uuid of the reservation
+async fn started_reservations(client: &mut Client) -> Result<Vec<Reservation>, Error> {
+    let mut tran = client.guarded_tran().await?;
+
+    let now = crate::emunet::unix_time();
+    let mut cluster_info = helpers::get_cluster_info(&mut tran).await?;
+    let mut ticket_queue = helpers::get_ticket_queue(&mut tran).await?;
+    let started: Vec<Reservation> = cluster_info
+        .reservations()
+        .iter()
+        .filter(|r| r.start <= now)
+        .cloned()
+        .collect();
+
+    // drop the reservations whose window has passed without being fulfilled
+    let mut res = Vec::new();
+    let mut changed = false;
+    for reservation in started {
+        if reservation.end <= now {
+            let reason = match reservation.last_error {
+                Some(e) => format!("the reservation ends before the emunet is created, {}", e),
+                None => "the reservation ends before the emunet is created".to_string(),
+            };
+            cluster_info.remove_reservation(&reservation.reservation_uuid);
+            ticket_queue.record(
+                reservation.reservation_uuid,
+                reservation.user,
+                reservation.emunet,
+                reservation.capacity,
+                TicketState::Failed(reason),
+            );
+            changed = true;
+        } else {
+            res.push(reservation);
+        }
+    }
+    if changed {
+        helpers::set_cluster_info(&mut tran, cluster_info).await?;
+        helpers::set_ticket_queue(&mut tran, ticket_queue).await?;
+    }
+
+    Ok(res)
+}
+
+// create the emunet booked by the reservation, a failure is kept on the
+// reservation until the next attempt
+async fn start_reservation(client: &mut Client, reservation: Reservation) -> Result<(), Error> {
+    let mut tran = client.guarded_tran().await?;
+
+    let creation = Creation {
+        user: reservation.user.clone(),
+        emunet: reservation.emunet.clone(),
+        capacity: reservation.capacity,
+        lease_expiry: Some(reservation.end),
+        reservation_uuid: Some(reservation.reservation_uuid.clone()),
+    };
+    match try_create(creation, &Caller::Admin, &mut tran).await? {
+        Ok(emunet_uuid) => {
+            let mut ticket_queue = helpers::get_ticket_queue(&mut tran).await?;
+            ticket_queue.record(
+                reservation.reservation_uuid,
+                reservation.user,
+                reservation.emunet,
+                reservation.capacity,
+                TicketState::Created(emunet_uuid),
+            );
+            helpers::set_ticket_queue(&mut tran, ticket_queue).await
+        }
+        Err(e) => {
+            let mut cluster_info = helpers::get_cluster_info(&mut tran).await?;
+            cluster_info.set_reservation_error(&reservation.reservation_uuid, format!("{}", e));
+            helpers::set_cluster_info(&mut tran, cluster_info).await
+        }
+    }
+}
+
+// periodically create the emunets booked by the reservations whose window
+// has started, a reservation is retried until its window ends
+pub async fn reservation_starter(connector: Connector) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(RESERVATION_CHECK_INTERVAL));
+    loop {
+        interval.tick().await;
+
+        let mut client = match connector.connect().await {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        let started = match started_reservations(&mut client).await {
+            Ok(started) => started,
+            Err(_) => {
+                client.notify_failure();
+                continue;
+            }
+        };
+
+        for reservation in started {
+            if let Err(_) = start_reservation(&mut client, reservation).await {
+                client.notify_failure();
+                break;
+            }
+        }
+    }
+}
+
 pub fn build_filter(
     connector: Connector,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("create_emunet".to_string(), connector, guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/create_emunet",
+        "create an emunet, or queue the creation if the cluster is short of capacity",
+    )
+    .body::<Request>()
+    .reply::<ResponseData>();
+}
@@ -1,78 +1,111 @@
 use std::{collections::HashMap, future::Future};
 
-use serde::Deserialize;
-use tokio::sync::oneshot;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
+use super::emunet_events;
+use super::jobs::{self, CancelToken};
 use super::Response;
-use crate::emunet::{Emunet, EmunetState};
+use crate::database::{helpers, Client, Connector, GuardedTransaction, Scope};
+use crate::emunet::{Emunet, EmunetState, Job, JobKind, Retired, User};
+use crate::errors::Error;
 use crate::k8s_api::{mocknet_client, EmunetReq, Pod, QueryReq};
-use crate::{
-    database::{helpers, Client, Connector, GuardedTransaction},
-    emunet::User,
-};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "DeleteEmunetRequest")]
 struct Request {
-    emunet_uuid: uuid::Uuid,
+    emunet_uuid: Uuid,
+    version: Option<u64>, // the version of the emunet read by the client
 }
 
+// the query string of DELETE /v2/emunets/{uuid}
+#[derive(Deserialize, JsonSchema)]
+struct VersionQuery {
+    version: Option<u64>,
+}
+
+static LEASE_REAP_INTERVAL: u64 = 60;
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "DeleteEmunetData")]
+pub(crate) struct ResponseData {
+    job_uuid: uuid::Uuid,
+}
+
+// remove the emunet and its jobs from the database and return its servers,
+// the torn down topology is kept in the history of the user if retire is
+// set and the deletion job job_uuid is kept for its result
 fn delete_emunet_from_db<'a>(
     emunet: &Emunet,
+    retire: bool,
+    job_uuid: Uuid,
     guarded_tran: &'a mut GuardedTransaction,
-) -> impl Future<Output = ()> + Send + 'a {
+) -> impl Future<Output = Result<(), Error>> + Send + 'a {
     let servers = emunet.release_emunet_servers();
     let emunet_uuid = emunet.emunet_uuid();
     let emunet_user = emunet.emunet_user().to_string();
     let emunet_name = emunet.emunet_name().to_string();
     let emunet_id = emunet.emunet_id();
     let emunet_state = emunet.state();
+    let retired = match retire {
+        true => Some(Retired::new(emunet)),
+        false => None,
+    };
 
     async move {
+        if let Some(retired) = retired {
+            jobs::retire(guarded_tran, &emunet_user, retired).await?;
+        }
+
         match emunet_state {
             EmunetState::Error(_) => {
-                let mut garbage_servers = helpers::get_garbage_servesr(guarded_tran).await.unwrap();
+                let mut garbage_servers = helpers::get_garbage_servesr(guarded_tran).await?;
                 for server in servers.iter() {
                     garbage_servers.push(server.server_info().clone());
                 }
-                helpers::set_garbage_servesr(guarded_tran, garbage_servers)
-                    .await
-                    .unwrap();
+                helpers::set_garbage_servesr(guarded_tran, garbage_servers).await?;
             }
             EmunetState::Working | EmunetState::Uninit => {
-                let mut cluster_info = helpers::get_cluster_info(guarded_tran).await.unwrap();
+                let mut cluster_info = helpers::get_cluster_info(guarded_tran).await?;
                 cluster_info.rellocate_servers(servers);
-                helpers::set_cluster_info(guarded_tran, cluster_info)
-                    .await
-                    .unwrap();
+                helpers::set_cluster_info(guarded_tran, cluster_info).await?;
+                super::emunet_creation::capacity_returned();
             }
             EmunetState::Normal => {
-                panic!("this should never happen");
+                return Err(Error::Corrupted(format!(
+                    "emunet {} is deleted in normal state",
+                    emunet_uuid
+                )));
             }
         }
 
-        helpers::delete_vertex(guarded_tran, emunet_uuid)
-            .await
-            .unwrap();
-
-        let mut user_map = helpers::get_user_map(guarded_tran).await.unwrap();
-        assert!(
-            user_map
-                .get_mut(&emunet_user)
-                .unwrap()
-                .delete_emunet(&emunet_name)
-                .is_some()
-                == true
-        );
-        helpers::set_user_map(guarded_tran, user_map).await.unwrap();
+        // the deletion job is kept, so that its result can be queried
+        helpers::delete_jobs(guarded_tran, emunet_uuid, job_uuid).await?;
+        helpers::delete_emunet(guarded_tran, emunet_uuid).await?;
 
-        let mut id_allocator = helpers::get_emunet_id_allocator(guarded_tran)
-            .await
-            .unwrap();
-        assert!(id_allocator.realloc(emunet_id) == true);
-        helpers::set_emunet_id_allocator(guarded_tran, id_allocator)
-            .await
-            .unwrap();
+        let mut user_map = helpers::get_user_map(guarded_tran).await?;
+        let deleted = user_map
+            .get_mut(&emunet_user)
+            .and_then(|user| user.delete_emunet(&emunet_name));
+        if deleted.is_none() {
+            return Err(Error::Corrupted(format!(
+                "emunet {} is missing from user {}",
+                emunet_name, emunet_user
+            )));
+        }
+        helpers::set_user_map(guarded_tran, user_map).await?;
+
+        let mut id_allocator = helpers::get_emunet_id_allocator(guarded_tran).await?;
+        if !id_allocator.realloc(emunet_id) {
+            return Err(Error::Corrupted(format!(
+                "emunet id {} is already free",
+                emunet_id
+            )));
+        }
+        helpers::set_emunet_id_allocator(guarded_tran, id_allocator).await
     }
 }
 
@@ -80,6 +113,7 @@ pub(crate) async fn delete_background_task(
     api_server_addr: String,
     emunet_req: EmunetReq,
     pods: Vec<Pod>,
+    cancel_token: CancelToken,
 ) -> Result<(), String> {
     let mut k8s_api_client = mocknet_client::MocknetClient::connect(api_server_addr.clone())
         .await
@@ -103,6 +137,9 @@ pub(crate) async fn delete_background_task(
     let total_query_attemps = 600;
     for i in 0..total_query_attemps {
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        if cancel_token.is_cancelled() {
+            return Err("deletion is cancelled".to_string());
+        }
 
         let query = tonic::Request::new(QueryReq {
             is_init: false,
@@ -130,88 +167,277 @@ pub(crate) async fn delete_background_task(
     ))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let mut guarded_tran = match client.guarded_tran().await {
-        Ok(inner) => inner,
-        Err(e) => {
-            client.notify_failure();
-            let resp: Response<_> = e.into();
-            return Ok(resp.into());
+async fn background_task_guard(
+    emunet: Emunet,
+    job: Job,
+    cancel_token: CancelToken,
+    mut client: Client,
+) {
+    let api_server_addr = emunet.api_server_addr().to_string();
+    let emunet_req = emunet.release_init_grpc_request();
+    let pods = emunet.release_pods();
+    let emunet_uuid = emunet.emunet_uuid();
+
+    jobs::log(&emunet, &job, "tearing down the emunet");
+    let res = delete_background_task(
+        api_server_addr.clone(),
+        emunet_req,
+        pods,
+        cancel_token.clone(),
+    )
+    .await;
+    let deleted = match res {
+        Ok(_) => {
+            let res = match client
+                .scoped_tran(Scope::new().write_core().write_emunet(emunet_uuid))
+                .await
+            {
+                Ok(mut guarded_tran) => {
+                    let fut =
+                        delete_emunet_from_db(&emunet, true, job.job_uuid(), &mut guarded_tran);
+                    fut.await
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = res {
+                jobs::abort(&mut client, emunet, job, e).await;
+                return;
+            }
+            job.succeed(format!("emunet {} is deleted", emunet_uuid));
+            emunet_events::publish_deleted(&emunet);
+            true
+        }
+        Err(_) if cancel_token.is_cancelled() => {
+            // the topology is still recorded in the emunet, relaunch it
+            jobs::log(&emunet, &job, "rolling back the deletion");
+            let emunet_req = emunet.release_init_grpc_request();
+            let pods = emunet.release_pods();
+            let res = super::emunet_init::init_background_task(
+                api_server_addr,
+                emunet_req,
+                pods,
+                CancelToken::never(),
+                emunet_events::ReadyPods::new(&emunet),
+            )
+            .await;
+            jobs::finish_rollback(&emunet, &job, res);
+            false
+        }
+        Err(err_str) => {
+            emunet.set_state(EmunetState::Error(err_str.clone()));
+            job.fail(err_str);
+            false
         }
     };
 
-    let res = helpers::get_emunet(&mut guarded_tran, req.emunet_uuid.clone()).await;
-    match res {
-        Ok(Some(emunet)) => {
-            let state = emunet.state();
-            match state {
-                EmunetState::Working => {
-                    return Ok(Response::<()>::fail(format!(
-                        "emunet {} is in working state, can't be deleted",
-                        req.emunet_uuid
-                    ))
-                    .into())
-                }
-                EmunetState::Uninit | EmunetState::Error(_) => {
-                    let fut = delete_emunet_from_db(&emunet, &mut guarded_tran);
-                    fut.await;
-                    return Ok(Response::success(()).into());
-                }
-                EmunetState::Normal => {
-                    emunet.set_state(EmunetState::Working);
-                    emunet.clear_device_login_info();
-                    let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-                    assert!(fut.await.unwrap() == true);
-                    drop(guarded_tran);
-
-                    let api_server_addr = emunet.api_server_addr().to_string();
-                    let emunet_req = emunet.release_init_grpc_request();
-                    let pods = emunet.release_pods();
-
-                    let (sender, receiver) = oneshot::channel();
-
-                    tokio::spawn(async move {
-                        let res = delete_background_task(api_server_addr, emunet_req, pods).await;
-                        let mut guarded_tran = client.guarded_tran().await.unwrap();
-                        match &res {
-                            Ok(_) => {
-                                let user_map: HashMap<String, User> =
-                                    helpers::get_user_map(&mut guarded_tran).await.unwrap();
-                                user_map
-                                    .get(emunet.emunet_user())
-                                    .unwrap()
-                                    .add_retired(&emunet);
-                                helpers::set_user_map(&mut guarded_tran, user_map)
-                                    .await
-                                    .unwrap();
-
-                                let fut = delete_emunet_from_db(&emunet, &mut guarded_tran);
-                                fut.await;
-                            }
-                            Err(err_str) => {
-                                emunet.set_state(EmunetState::Error(err_str.clone()));
-
-                                let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-                                assert!(fut.await.unwrap() == true);
-                            }
-                        }
-                        let _ = sender.send(res);
-                    });
-
-                    match receiver.await.unwrap() {
-                        Ok(_) => Ok(Response::success(()).into()),
-                        Err(err_str) => Ok(Response::<()>::fail(err_str).into()),
-                    }
-                }
+    jobs::unregister(&job.job_uuid());
+    if !deleted {
+        if let Some((emunet, _)) = jobs::save_progress(&mut client, emunet, job).await {
+            emunet_events::publish_state(&emunet);
+        }
+        return;
+    }
+    // only the job is left once the emunet is deleted
+    let res = match client
+        .scoped_tran(Scope::new().write_emunet(emunet_uuid))
+        .await
+    {
+        Ok(mut guarded_tran) => {
+            let fut = helpers::set_job(&mut guarded_tran, &job);
+            fut.await
+        }
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = res {
+        emunet_events::publish_log(
+            &emunet,
+            format!("job {} is left in its stored state, {}", job.job_uuid(), e),
+        );
+    }
+}
+
+// how a deletion proceeds once the emunet is checked
+enum Deletion {
+    Done(Response<ResponseData>),
+    Background(Emunet, Job, CancelToken),
+}
+
+async fn deletion_check(
+    emunet_uuid: Uuid,
+    version: Option<u64>,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Deletion, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    let emunet = match helpers::get_emunet(&mut guarded_tran, emunet_uuid.clone()).await? {
+        Some(emunet) => emunet,
+        None => {
+            return Ok(Deletion::Done(Response::fail(Error::EmunetNotFound(
+                emunet_uuid,
+            ))))
+        }
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Deletion::Done(Response::fail(
+            caller.deny(format!("emunet {}", emunet_uuid)),
+        )));
+    }
+    if let Err(resp) = super::check_version(&emunet, version) {
+        return Ok(Deletion::Done(resp));
+    }
+
+    match emunet.state() {
+        EmunetState::Working => Ok(Deletion::Done(Response::fail(Error::InvalidState(
+            format!(
+                "emunet {} is in working state, can't be deleted",
+                emunet_uuid
+            ),
+        )))),
+        EmunetState::Uninit | EmunetState::Error(_) => {
+            let (job, _) = jobs::create_job(
+                &mut guarded_tran,
+                JobKind::Delete,
+                emunet.emunet_uuid(),
+                emunet.emunet_user().to_string(),
+            )
+            .await?;
+            jobs::unregister(&job.job_uuid());
+
+            let fut = delete_emunet_from_db(&emunet, false, job.job_uuid(), &mut guarded_tran);
+            fut.await?;
+            emunet_events::publish_deleted(&emunet);
+
+            job.succeed(format!("emunet {} is deleted", emunet_uuid));
+            let fut = helpers::set_job(&mut guarded_tran, &job);
+            fut.await?;
+
+            Ok(Deletion::Done(Response::success(ResponseData {
+                job_uuid: job.job_uuid(),
+            })))
+        }
+        EmunetState::Normal => {
+            emunet.set_state(EmunetState::Working);
+            emunet.clear_device_login_info();
+            let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
+            if !fut.await? {
+                return Err(Error::Conflict(emunet_uuid));
             }
+
+            let (job, cancel_token) = jobs::create_job(
+                &mut guarded_tran,
+                JobKind::Delete,
+                emunet.emunet_uuid(),
+                emunet.emunet_user().to_string(),
+            )
+            .await?;
+            Ok(Deletion::Background(emunet, job, cancel_token))
         }
-        Ok(None) => {
-            Ok(Response::<()>::fail(format!("emunet {} does not exist", req.emunet_uuid)).into())
+    }
+}
+
+// start deleting the emunet, this is shared by the delete_emunet api and the lease reaper
+pub(crate) async fn start_deletion(
+    emunet_uuid: Uuid,
+    version: Option<u64>,
+    caller: &Caller,
+    mut client: Client,
+) -> Response<ResponseData> {
+    match deletion_check(emunet_uuid, version, caller, &mut client).await {
+        Ok(Deletion::Done(resp)) => resp,
+        Ok(Deletion::Background(emunet, job, cancel_token)) => {
+            let job_uuid = job.job_uuid();
+            tokio::spawn(background_task_guard(emunet, job, cancel_token, client));
+
+            Response::success(ResponseData { job_uuid })
         }
         Err(e) => {
             client.notify_failure();
-            let resp: Response<_> = e.into();
-            Ok(resp.into())
+            Response::fatal(e)
+        }
+    }
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    Ok(
+        start_deletion(req.emunet_uuid, req.version, &caller, client)
+            .await
+            .into(),
+    )
+}
+
+// emunets whose lease has expired together with their users, each emunet
+// is read in a short shared transaction and only its header is loaded
+async fn expired_emunets(client: &mut Client) -> Result<Vec<(Uuid, String)>, Error> {
+    let mut tran = client.scoped_tran(Scope::new().read_core()).await?;
+    let user_map: HashMap<String, User> = helpers::get_user_map(&mut tran).await?;
+    drop(tran);
+    let emunet_uuids: Vec<Uuid> = user_map
+        .values()
+        .map(|user| user.emunet_uuids())
+        .flatten()
+        .collect();
+    drop(user_map);
+
+    let now = crate::emunet::unix_time();
+    let mut expired = Vec::new();
+    for emunet_uuid in emunet_uuids {
+        let mut tran = client
+            .scoped_tran(Scope::new().read_emunet(emunet_uuid.clone()))
+            .await?;
+        let emunet = match helpers::get_emunet_header(&mut tran, emunet_uuid.clone()).await? {
+            None => continue,
+            Some(emunet) => emunet,
+        };
+        match emunet.state() {
+            EmunetState::Working => continue,
+            _ => {}
+        };
+        if emunet.is_lease_expired(now) {
+            expired.push((emunet_uuid, emunet.emunet_user().to_string()));
+        }
+    }
+    Ok(expired)
+}
+
+// periodically delete the emunets whose lease has expired, working
+// emunets are picked up once their background task finishes
+pub async fn lease_reaper(connector: Connector) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(LEASE_REAP_INTERVAL));
+    loop {
+        interval.tick().await;
+
+        let mut client = match connector.connect().await {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        let expired = match expired_emunets(&mut client).await {
+            Ok(expired) => expired,
+            Err(_) => {
+                client.notify_failure();
+                continue;
+            }
+        };
+        drop(client);
+
+        for (emunet_uuid, user) in expired {
+            let client = match connector.connect().await {
+                Ok(client) => client,
+                Err(_) => break,
+            };
+            let resp = start_deletion(emunet_uuid.clone(), None, &Caller::Admin, client).await;
+            let line = match resp.success {
+                true => "the lease expires, deleting the emunet".to_string(),
+                false => format!(
+                    "fail to delete the emunet with expired lease: {}",
+                    resp.message
+                ),
+            };
+            emunet_events::publish_log_of(emunet_uuid, &user, line);
         }
     }
 }
@@ -221,3 +447,31 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("delete_emunet".to_string(), connector, guard)
 }
+
+// DELETE /v2/emunets/{uuid}?version={version}
+pub fn build_v2_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::delete()
+        .and(warp::path!("v2" / "emunets" / Uuid))
+        .and(warp::query::<VersionQuery>())
+        .and(super::auth::caller_filter(connector))
+        .and_then(
+            |emunet_uuid: Uuid, query: VersionQuery, caller: Caller, client: Client| {
+                let req = Request {
+                    emunet_uuid,
+                    version: query.version,
+                };
+                guard(req, caller, client)
+            },
+        )
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/delete_emunet", "delete an emunet")
+        .body::<Request>()
+        .reply::<ResponseData>();
+    spec.delete("/v2/emunets/{emunet_uuid}", "delete an emunet")
+        .query::<VersionQuery>()
+        .reply::<ResponseData>();
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::{Emunet, User};
+use crate::errors::Error;
+use crate::k8s_api;
+
+lazy_static! {
+    static ref EVENT_SENDER: broadcast::Sender<EmunetEvent> = broadcast::channel(1024).0;
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum EventKind {
+    State { state: String },
+    PodReady { dev_idx: u64, pod_name: String },
+    Log { line: String },
+    Deleted,
+}
+
+impl EventKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EventKind::State { .. } => "state",
+            EventKind::PodReady { .. } => "pod_ready",
+            EventKind::Log { .. } => "log",
+            EventKind::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub(crate) struct EmunetEvent {
+    emunet_uuid: Uuid,
+    user: String,
+    #[serde(flatten)]
+    kind: EventKind,
+}
+
+fn publish(emunet: &Emunet, kind: EventKind) {
+    // an error only means that nobody is listening
+    let _ = EVENT_SENDER.send(EmunetEvent {
+        emunet_uuid: emunet.emunet_uuid(),
+        user: emunet.emunet_user().to_string(),
+        kind,
+    });
+}
+
+pub(crate) fn publish_state(emunet: &Emunet) {
+    publish(
+        emunet,
+        EventKind::State {
+            state: emunet.state().into(),
+        },
+    );
+}
+
+pub(crate) fn publish_log<S: std::convert::Into<String>>(emunet: &Emunet, line: S) {
+    publish(emunet, EventKind::Log { line: line.into() });
+}
+
+// a log line of an emunet that is not loaded, like the ones picked by the
+// lease reaper
+pub(crate) fn publish_log_of<S: std::convert::Into<String>>(
+    emunet_uuid: Uuid,
+    user: &str,
+    line: S,
+) {
+    // an error only means that nobody is listening
+    let _ = EVENT_SENDER.send(EmunetEvent {
+        emunet_uuid,
+        user: user.to_string(),
+        kind: EventKind::Log { line: line.into() },
+    });
+}
+
+// ReadyPods announces the pods of an emunet as they become ready, it keeps
+// no reference to the emunet so that it can be moved into the query loop
+pub(crate) struct ReadyPods {
+    emunet_uuid: Uuid,
+    user: String,
+    // the pods that are not announced yet
+    dev_idxs: HashMap<String, u64>,
+}
+
+impl ReadyPods {
+    pub(crate) fn new(emunet: &Emunet) -> Self {
+        let dev_idxs = emunet
+            .dev_idxs()
+            .into_iter()
+            .filter_map(|dev_idx| Some((emunet.get_pod_name(dev_idx)?, dev_idx)))
+            .collect();
+        Self {
+            emunet_uuid: emunet.emunet_uuid(),
+            user: emunet.emunet_user().to_string(),
+            dev_idxs,
+        }
+    }
+
+    // every pod is announced once, the first time that it is reported ready
+    pub(crate) fn publish(&mut self, device_infos: &Vec<k8s_api::DeviceInfo>) {
+        for dev_info in device_infos {
+            if let Some(dev_idx) = self.dev_idxs.remove(&dev_info.pod_name) {
+                // an error only means that nobody is listening
+                let _ = EVENT_SENDER.send(EmunetEvent {
+                    emunet_uuid: self.emunet_uuid.clone(),
+                    user: self.user.clone(),
+                    kind: EventKind::PodReady {
+                        dev_idx,
+                        pod_name: dev_info.pod_name.clone(),
+                    },
+                });
+            }
+        }
+    }
+}
+
+pub(crate) fn publish_deleted(emunet: &Emunet) {
+    publish(emunet, EventKind::Deleted);
+}
+
+#[derive(Deserialize, Clone, JsonSchema)]
+#[schemars(rename = "EventsQuery")]
+struct Request {
+    emunet_uuid: Option<Uuid>,
+    user: Option<String>,
+}
+
+impl Request {
+    fn matches(&self, event: &EmunetEvent) -> bool {
+        self.emunet_uuid
+            .as_ref()
+            .map(|uuid| *uuid == event.emunet_uuid)
+            .unwrap_or(true)
+            && self
+                .user
+                .as_ref()
+                .map(|user| *user == event.user)
+                .unwrap_or(true)
+    }
+}
+
+// the current state of every emunet watched by the request
+async fn snapshot(
+    req: &Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Result<Vec<EmunetEvent>, Error>, Error> {
+    // the state of an emunet is read from its own vertex in one query, only
+    // the user map needs to be locked
+    let mut tran = client.scoped_tran(Scope::new().read_core()).await?;
+
+    let emunet_uuids = match (req.emunet_uuid.as_ref(), req.user.as_ref()) {
+        (Some(emunet_uuid), _) => vec![emunet_uuid.clone()],
+        (None, Some(user)) => {
+            if !caller.can_access(user) {
+                return Ok(Err(caller.deny(format!("user {}", user))));
+            }
+            let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut tran).await?;
+            match user_map.remove(user) {
+                None => return Ok(Err(Error::NotFound(format!("user {}", user)))),
+                Some(user) => user.into_uuid_map().into_iter().map(|(_, v)| v).collect(),
+            }
+        }
+        (None, None) => {
+            return Ok(Err(Error::InvalidInput(
+                "missing emunet uuid or user name".to_string(),
+            )))
+        }
+    };
+
+    let mut events = Vec::new();
+    for emunet_uuid in emunet_uuids {
+        let emunet = match helpers::get_emunet_header(&mut tran, emunet_uuid.clone()).await? {
+            None => return Ok(Err(Error::EmunetNotFound(emunet_uuid))),
+            Some(emunet) => emunet,
+        };
+        if !caller.can_access(emunet.emunet_user()) {
+            return Ok(Err(caller.deny(format!("emunet {}", emunet_uuid))));
+        }
+        let event = EmunetEvent {
+            emunet_uuid: emunet.emunet_uuid(),
+            user: emunet.emunet_user().to_string(),
+            kind: EventKind::State {
+                state: emunet.state().into(),
+            },
+        };
+        if req.matches(&event) {
+            events.push(event);
+        }
+    }
+
+    Ok(Ok(events))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    // subscribe first so that no event is lost between the snapshot and the stream
+    let receiver = EVENT_SENDER.subscribe();
+
+    let initial = match snapshot(&req, &caller, &mut client).await {
+        Ok(Ok(events)) => events,
+        Ok(Err(e)) => {
+            let resp: super::Reply = Response::<()>::fail(e).into();
+            return Ok(Box::new(resp));
+        }
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            let resp: super::Reply = resp.into();
+            return Ok(Box::new(resp));
+        }
+    };
+    drop(client);
+
+    let live = BroadcastStream::new(receiver).filter_map(move |res| match res {
+        Ok(event) if req.matches(&event) => Some(event),
+        // lagging receivers simply skip the dropped events
+        _ => None,
+    });
+    let events = tokio_stream::iter(initial).chain(live).map(|event| {
+        warp::sse::Event::default()
+            .event(event.kind.name())
+            .json_data(&event)
+    });
+
+    Ok(Box::new(warp::sse::reply(
+        warp::sse::keep_alive().stream(events),
+    )))
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v1" / "events"))
+        .and(warp::query::<Request>())
+        .and(super::auth::caller_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.get("/v1/events", "watch the events of an emunet or a user")
+        .query::<Request>()
+        .raw_reply::<EmunetEvent>(
+            "200",
+            "text/event-stream",
+            "server-sent events, the data of each event is an EmunetEvent",
+        );
+}
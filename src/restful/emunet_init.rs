@@ -1,29 +1,43 @@
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
+use super::emunet_events;
+use super::emunet_update::Topology;
+use super::jobs::{self, CancelToken};
 use super::Response;
 use crate::algo::*;
 use crate::database::{helpers, Client, Connector};
-use crate::emunet::{Emunet, EmunetState, InputDevice, InputLink, MAX_DIRECTED_LINK_POWER};
+use crate::emunet::{
+    Emunet, EmunetState, InputDevice, InputLink, Job, JobKind, MAX_DIRECTED_LINK_POWER,
+};
+use crate::errors::Error;
 use crate::k8s_api::{self, mocknet_client, EmunetReq, Pod, QueryReq};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "InitEmunetRequest")]
 struct Request<String> {
     emunet_uuid: uuid::Uuid,        // uuid of the emunet object on the database
     devs: Vec<InputDevice<String>>, // a list of devices to be created
     links: Vec<InputLink<String>>,  // a list of links to be created
+    version: Option<u64>,           // the version of the emunet read by the client
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "InitEmunetData")]
 struct ResponseData {
     status: String,
+    job_uuid: uuid::Uuid,
 }
 
 pub(crate) async fn init_background_task(
     api_server_addr: String,
     emunet_req: EmunetReq,
     pods: Vec<Pod>,
+    cancel_token: CancelToken,
+    mut ready_pods: emunet_events::ReadyPods,
 ) -> Result<Vec<k8s_api::DeviceInfo>, String> {
     let mut k8s_api_client = mocknet_client::MocknetClient::connect(api_server_addr.clone())
         .await
@@ -47,6 +61,9 @@ pub(crate) async fn init_background_task(
     let total_query_attemps = 300;
     for i in 0..total_query_attemps {
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        if cancel_token.is_cancelled() {
+            return Err("initialization is cancelled".to_string());
+        }
 
         let query = tonic::Request::new(QueryReq {
             is_init: true,
@@ -63,6 +80,8 @@ pub(crate) async fn init_background_task(
             })?
             .into_inner();
 
+        // the ready pods are announced without waiting for the rest
+        ready_pods.publish(&response.device_infos);
         if response.status {
             return Ok(response.device_infos);
         }
@@ -77,62 +96,114 @@ pub(crate) async fn init_background_task(
 async fn background_task_guard(
     emunet: Emunet,
     graph: UndirectedGraph<u64, InputDevice<String>, InputLink<String>>,
+    job: Job,
+    cancel_token: CancelToken,
     mut client: Client,
 ) {
     emunet.build_emunet_graph(&graph);
-    {
-        let mut guarded_tran = client.guarded_tran().await.unwrap();
-        let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-        assert!(fut.await.unwrap() == true);
-    }
+    jobs::log(
+        &emunet,
+        &job,
+        format!("emunet graph is built with {} devices", emunet.dev_count()),
+    );
+    let (emunet, job) = match jobs::save_progress(&mut client, emunet, job).await {
+        Some(progress) => progress,
+        None => return,
+    };
 
     let api_server_addr = emunet.api_server_addr().to_string();
     let emunet_req = emunet.release_init_grpc_request();
     let pods = emunet.release_pods();
-    let res = init_background_task(api_server_addr, emunet_req, pods).await;
+    let res = init_background_task(
+        api_server_addr.clone(),
+        emunet_req,
+        pods,
+        cancel_token.clone(),
+        emunet_events::ReadyPods::new(&emunet),
+    )
+    .await;
     match res {
         Ok(device_infos) => {
             emunet.update_device_login_info(&device_infos);
             emunet.set_state(EmunetState::Normal);
+            job.succeed(format!("emunet {} is initialized", emunet.emunet_uuid()));
+        }
+        Err(_) if cancel_token.is_cancelled() => {
+            // tear down whatever has been launched and return to uninit state
+            jobs::log(&emunet, &job, "rolling back the initialization");
+            let emunet_req = emunet.release_init_grpc_request();
+            let pods = emunet.release_pods();
+            let res = super::emunet_deletion::delete_background_task(
+                api_server_addr,
+                emunet_req,
+                pods,
+                CancelToken::never(),
+            )
+            .await;
+            match res {
+                Ok(_) => {
+                    emunet.clear_emunet_resource();
+                    emunet.set_state(EmunetState::Uninit);
+                    jobs::log(&emunet, &job, "rollback finished");
+                    job.cancel();
+                }
+                Err(err_str) => {
+                    emunet.set_state(EmunetState::Error(err_str.clone()));
+                    job.fail(format!("rollback fails: {}", err_str));
+                }
+            }
         }
         Err(err_str) => {
-            emunet.set_state(EmunetState::Error(err_str));
+            emunet.set_state(EmunetState::Error(err_str.clone()));
+            job.fail(err_str);
         }
     }
 
-    let mut guarded_tran = client.guarded_tran().await.unwrap();
-    let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-    assert!(fut.await.unwrap() == true);
+    jobs::unregister(&job.job_uuid());
+    if let Some((emunet, _)) = jobs::save_progress(&mut client, emunet, job).await {
+        emunet_events::publish_state(&emunet);
+    }
 }
 
 async fn init_check(
     req: Request<String>,
+    caller: &Caller,
     client: &mut Client,
 ) -> Result<
     Result<
         (
             Emunet,
             UndirectedGraph<u64, InputDevice<String>, InputLink<String>>,
+            Job,
+            CancelToken,
         ),
-        String,
+        Response<ResponseData>,
     >,
-    ClientError,
+    Error,
 > {
     let mut guarded_tran = client.guarded_tran().await?;
 
     let emunet: Emunet =
         match helpers::get_emunet(&mut guarded_tran, req.emunet_uuid.clone()).await? {
-            None => return Ok(Err(format!("emunet {} does not exist", req.emunet_uuid))),
+            None => return Ok(Err(Response::fail(Error::EmunetNotFound(req.emunet_uuid)))),
             Some(emunet) => emunet,
         };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Err(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        )));
+    }
+    if let Err(resp) = super::check_version(&emunet, req.version) {
+        return Ok(Err(resp));
+    }
 
     match emunet.state() {
         EmunetState::Uninit => {}
         _ => {
-            return Ok(Err(format!(
+            return Ok(Err(Response::fail(Error::InvalidState(format!(
                 "emunet {} is already initialized",
                 req.emunet_uuid
-            )))
+            )))))
         }
     };
 
@@ -140,43 +211,79 @@ async fn init_check(
         req.devs.into_iter().map(|v| (v.id(), v)).collect(),
         req.links.into_iter().map(|e| (e.link_id(), e)).collect(),
     ) {
-        None => return Ok(Err("invalid input graph".to_string())),
+        None => {
+            return Ok(Err(Response::fail(Error::InvalidInput(
+                "invalid input graph".to_string(),
+            ))))
+        }
         Some(graph) => graph,
     };
     if graph.nodes_num() > emunet.max_capacity() as usize {
-        return Ok(Err("input graph exceeds capacity limitation".to_string()));
+        return Ok(Err(Response::fail(Error::CapacityExceeded(
+            "input graph exceeds capacity limitation".to_string(),
+        ))));
     }
     if graph.edges_num() * 2 > (2 as usize).pow(MAX_DIRECTED_LINK_POWER) {
-        return Ok(Err(format!(
+        return Ok(Err(Response::fail(Error::InvalidInput(format!(
             "input graph can only have at most {} edges",
             (2 as usize).pow(MAX_DIRECTED_LINK_POWER - 1)
-        )));
+        )))));
+    }
+    let res = super::user_info::check_links_quota(
+        &mut guarded_tran,
+        emunet.emunet_user().to_string(),
+        emunet.link_count(),
+        graph.edges_num() as u64,
+    )
+    .await?;
+    if let Err(e) = res {
+        return Ok(Err(Response::fail(e)));
     }
 
     emunet.set_state(EmunetState::Working);
     let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-    assert!(fut.await? == true);
+    if !fut.await? {
+        return Err(Error::Conflict(emunet.emunet_uuid()));
+    }
+    emunet_events::publish_state(&emunet);
 
-    Ok(Ok((emunet, graph)))
+    let (job, cancel_token) = jobs::create_job(
+        &mut guarded_tran,
+        JobKind::Init,
+        emunet.emunet_uuid(),
+        emunet.emunet_user().to_string(),
+    )
+    .await?;
+
+    Ok(Ok((emunet, graph, job, cancel_token)))
 }
 
 async fn guard(
     req: Request<String>,
+    caller: Caller,
     mut client: Client,
-) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = init_check(req, &mut client).await;
+) -> Result<super::Reply, warp::Rejection> {
+    let res = init_check(req, &caller, &mut client).await;
     match res {
         Ok(res) => match res {
-            Ok((emunet, graph)) => {
+            Ok((emunet, graph, job, cancel_token)) => {
                 let state_str = emunet.state().into();
-                tokio::spawn(background_task_guard(emunet, graph, client));
+                let job_uuid = job.job_uuid();
+                tokio::spawn(background_task_guard(
+                    emunet,
+                    graph,
+                    job,
+                    cancel_token,
+                    client,
+                ));
 
-                Ok(Response::success(ResponseData { status: state_str }).into())
-            }
-            Err(s) => {
-                let resp: Response<String> = Response::fail(s);
-                Ok(resp.into())
+                Ok(Response::success(ResponseData {
+                    status: state_str,
+                    job_uuid,
+                })
+                .into())
             }
+            Err(resp) => Ok(resp.into()),
         },
         Err(e) => {
             client.notify_failure();
@@ -191,3 +298,37 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("init_emunet".to_string(), connector, guard)
 }
+
+// POST /v2/emunets/{uuid}/topology
+pub fn build_v2_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::post()
+        .and(warp::path!("v2" / "emunets" / Uuid / "topology"))
+        // the caller is authenticated before the body is read
+        .and(super::auth::caller_filter(connector))
+        .and(super::parse_topology_body())
+        .and_then(
+            |emunet_uuid: Uuid, caller: Caller, client: Client, topology: Topology<String>| {
+                let req = Request {
+                    emunet_uuid,
+                    devs: topology.devs,
+                    links: topology.links,
+                    version: topology.version,
+                };
+                guard(req, caller, client)
+            },
+        )
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/init_emunet", "launch the topology of an emunet")
+        .body::<Request<String>>()
+        .reply::<ResponseData>();
+    spec.post(
+        "/v2/emunets/{emunet_uuid}/topology",
+        "launch the topology of an emunet",
+    )
+    .topology_body::<Topology<String>>()
+    .reply::<ResponseData>();
+}
@@ -1,119 +1,268 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tokio::fs::read_to_string;
 use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
+use super::emunet_events;
+use super::jobs::{self, CancelToken};
 use super::list_user_history::Data;
 use super::Response;
-use crate::database::{helpers, Client, Connector};
-use crate::emunet::{Emunet, EmunetState, InputDevice, InputLink, MAX_DIRECTED_LINK_POWER};
-use crate::{algo::*, emunet::User};
-
-#[derive(Deserialize, Serialize)]
+use crate::algo::*;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::{
+    Emunet, EmunetState, InputDevice, InputLink, Job, JobKind, Retired, MAX_DIRECTED_LINK_POWER,
+};
+use crate::errors::Error;
+use crate::topology;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "UpdateEmunetRequest")]
 struct Request<String> {
     emunet_uuid: uuid::Uuid,        // uuid of the emunet object on the database
     devs: Vec<InputDevice<String>>, // a list of devices to be created
     links: Vec<InputLink<String>>,  // a list of links to be created
+    version: Option<u64>,           // the version of the emunet read by the client
+}
+
+// the body of POST and PUT /v2/emunets/{uuid}/topology, which may be
+// gzip compressed
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "Topology")]
+pub(crate) struct Topology<String> {
+    pub(crate) devs: Vec<InputDevice<String>>,
+    pub(crate) links: Vec<InputLink<String>>,
+    pub(crate) version: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "UpdateEmunetData")]
 struct ResponseData {
     status: String,
+    job_uuid: Uuid,
+}
+
+// rebuild the input graph of a retired topology
+fn retired_graph(
+    nodes: Vec<u64>,
+    edges: Vec<(u64, u64)>,
+) -> UndirectedGraph<u64, InputDevice<String>, InputLink<String>> {
+    UndirectedGraph::new(
+        nodes
+            .into_iter()
+            .map(|nid| {
+                (
+                    nid,
+                    InputDevice {
+                        id: nid,
+                        description: String::new(),
+                    },
+                )
+            })
+            .collect(),
+        edges
+            .into_iter()
+            .map(|eid| {
+                (
+                    eid,
+                    InputLink {
+                        edge_id: eid,
+                        description: String::new(),
+                    },
+                )
+            })
+            .collect(),
+    )
+    .expect("FATAL: this should not happen")
 }
 
 async fn background_task_guard(
     emunet: Emunet,
     input_graph: UndirectedGraph<u64, InputDevice<String>, InputLink<String>>,
+    job: Job,
+    cancel_token: CancelToken,
     mut client: Client,
 ) {
     let api_server_addr = emunet.api_server_addr().to_string();
     let emunet_req = emunet.release_init_grpc_request();
     let pods = emunet.release_pods();
 
-    let res =
-        super::emunet_deletion::delete_background_task(api_server_addr.clone(), emunet_req, pods)
+    jobs::log(&emunet, &job, "tearing down the current topology");
+    let res = super::emunet_deletion::delete_background_task(
+        api_server_addr.clone(),
+        emunet_req,
+        pods,
+        cancel_token.clone(),
+    )
+    .await;
+    let torn_down = match res {
+        Ok(_) => true,
+        Err(_) if cancel_token.is_cancelled() => {
+            // the current topology is still recorded in the emunet
+            jobs::log(&emunet, &job, "rolling back to the current topology");
+            let emunet_req = emunet.release_init_grpc_request();
+            let pods = emunet.release_pods();
+            let res = super::emunet_init::init_background_task(
+                api_server_addr.clone(),
+                emunet_req,
+                pods,
+                CancelToken::never(),
+                emunet_events::ReadyPods::new(&emunet),
+            )
             .await;
-    match res {
+            jobs::finish_rollback(&emunet, &job, res);
+            false
+        }
         Err(err_str) => {
-            emunet.set_state(EmunetState::Error(err_str));
-
-            let mut guarded_tran = client.guarded_tran().await.unwrap();
-            let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-            assert!(fut.await.unwrap() == true);
-            return;
+            emunet.set_state(EmunetState::Error(err_str.clone()));
+            job.fail(err_str);
+            false
         }
-        _ => {}
     };
 
-    {
-        let mut guarded_tran = client.guarded_tran().await.unwrap();
-        let user_map: HashMap<String, User> =
-            helpers::get_user_map(&mut guarded_tran).await.unwrap();
-        user_map
-            .get(emunet.emunet_user())
-            .unwrap()
-            .add_retired(&emunet);
-        helpers::set_user_map(&mut guarded_tran, user_map)
+    let (emunet, job) = if torn_down {
+        let (_, _, old_nodes, old_edges) = emunet.release_history();
+        let retired = Retired::new(&emunet);
+        let emunet_uuid = emunet.emunet_uuid();
+        let emunet_user = emunet.emunet_user().to_string();
+        let res = match client
+            .scoped_tran(Scope::new().write_core().write_emunet(emunet_uuid))
             .await
-            .unwrap();
+        {
+            Ok(mut guarded_tran) => jobs::retire(&mut guarded_tran, &emunet_user, retired).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            jobs::abort(&mut client, emunet, job, e).await;
+            return;
+        }
 
         emunet.clear_emunet_resource();
         emunet.build_emunet_graph(&input_graph);
+        jobs::log(
+            &emunet,
+            &job,
+            format!(
+                "emunet graph is rebuilt with {} devices",
+                emunet.dev_count()
+            ),
+        );
+        let (emunet, job) = match jobs::save_progress(&mut client, emunet, job).await {
+            Some(progress) => progress,
+            None => return,
+        };
 
-        let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-        assert!(fut.await.unwrap() == true);
-    }
-
-    let emunet_req = emunet.release_init_grpc_request();
-    let pods = emunet.release_pods();
-    let res = super::emunet_init::init_background_task(api_server_addr, emunet_req, pods).await;
-
-    match res {
-        Ok(device_infos) => {
-            emunet.update_device_login_info(&device_infos);
-            emunet.set_state(EmunetState::Normal);
-        }
-        Err(err_str) => {
-            emunet.set_state(EmunetState::Error(err_str));
+        let emunet_req = emunet.release_init_grpc_request();
+        let pods = emunet.release_pods();
+        let res = super::emunet_init::init_background_task(
+            api_server_addr.clone(),
+            emunet_req,
+            pods,
+            cancel_token.clone(),
+            emunet_events::ReadyPods::new(&emunet),
+        )
+        .await;
+
+        match res {
+            Ok(device_infos) => {
+                emunet.update_device_login_info(&device_infos);
+                emunet.set_state(EmunetState::Normal);
+                job.succeed(format!("emunet {} is updated", emunet.emunet_uuid()));
+            }
+            Err(_) if cancel_token.is_cancelled() => {
+                jobs::log(&emunet, &job, "rolling back to the previous topology");
+                let emunet_req = emunet.release_init_grpc_request();
+                let pods = emunet.release_pods();
+                let res = super::emunet_deletion::delete_background_task(
+                    api_server_addr.clone(),
+                    emunet_req,
+                    pods,
+                    CancelToken::never(),
+                )
+                .await;
+                match res {
+                    Ok(_) => {
+                        emunet.clear_emunet_resource();
+                        emunet.build_emunet_graph(&retired_graph(old_nodes, old_edges));
+
+                        let emunet_req = emunet.release_init_grpc_request();
+                        let pods = emunet.release_pods();
+                        let res = super::emunet_init::init_background_task(
+                            api_server_addr,
+                            emunet_req,
+                            pods,
+                            CancelToken::never(),
+                            emunet_events::ReadyPods::new(&emunet),
+                        )
+                        .await;
+                        jobs::finish_rollback(&emunet, &job, res);
+                    }
+                    Err(err_str) => {
+                        emunet.set_state(EmunetState::Error(err_str.clone()));
+                        job.fail(format!("rollback fails: {}", err_str));
+                    }
+                }
+            }
+            Err(err_str) => {
+                emunet.set_state(EmunetState::Error(err_str.clone()));
+                job.fail(err_str);
+            }
         }
-    }
+        (emunet, job)
+    } else {
+        (emunet, job)
+    };
 
-    let mut guarded_tran = client.guarded_tran().await.unwrap();
-    let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-    assert!(fut.await.unwrap() == true);
+    jobs::unregister(&job.job_uuid());
+    if let Some((emunet, _)) = jobs::save_progress(&mut client, emunet, job).await {
+        emunet_events::publish_state(&emunet);
+    }
 }
 
 async fn update_check(
     req: Request<String>,
+    caller: &Caller,
     client: &mut Client,
 ) -> Result<
     Result<
         (
             Emunet,
             UndirectedGraph<u64, InputDevice<String>, InputLink<String>>,
+            Job,
+            CancelToken,
         ),
-        String,
+        Response<ResponseData>,
     >,
-    ClientError,
+    Error,
 > {
     let mut guarded_tran = client.guarded_tran().await?;
 
     let emunet: Emunet =
         match helpers::get_emunet(&mut guarded_tran, req.emunet_uuid.clone()).await? {
-            None => return Ok(Err(format!("emunet {} does not exist", req.emunet_uuid))),
+            None => return Ok(Err(Response::fail(Error::EmunetNotFound(req.emunet_uuid)))),
             Some(emunet) => emunet,
         };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Err(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        )));
+    }
+    if let Err(resp) = super::check_version(&emunet, req.version) {
+        return Ok(Err(resp));
+    }
 
     match emunet.state() {
         EmunetState::Normal => {}
         _ => {
-            return Ok(Err(format!(
+            return Ok(Err(Response::fail(Error::InvalidState(format!(
                 "emunet {} is not in normal state",
                 req.emunet_uuid
-            )))
+            )))))
         }
     };
 
@@ -121,44 +270,80 @@ async fn update_check(
         req.devs.into_iter().map(|v| (v.id(), v)).collect(),
         req.links.into_iter().map(|e| (e.link_id(), e)).collect(),
     ) {
-        None => return Ok(Err("invalid input graph".to_string())),
+        None => {
+            return Ok(Err(Response::fail(Error::InvalidInput(
+                "invalid input graph".to_string(),
+            ))))
+        }
         Some(graph) => graph,
     };
     if graph.nodes_num() > emunet.max_capacity() as usize {
-        return Ok(Err("input graph exceeds capacity limitation".to_string()));
+        return Ok(Err(Response::fail(Error::CapacityExceeded(
+            "input graph exceeds capacity limitation".to_string(),
+        ))));
     }
     if graph.edges_num() * 2 > (2 as usize).pow(MAX_DIRECTED_LINK_POWER) {
-        return Ok(Err(format!(
+        return Ok(Err(Response::fail(Error::InvalidInput(format!(
             "input graph can only have at most {} edges",
             (2 as usize).pow(MAX_DIRECTED_LINK_POWER - 1)
-        )));
+        )))));
+    }
+    let res = super::user_info::check_links_quota(
+        &mut guarded_tran,
+        emunet.emunet_user().to_string(),
+        emunet.link_count(),
+        graph.edges_num() as u64,
+    )
+    .await?;
+    if let Err(e) = res {
+        return Ok(Err(Response::fail(e)));
     }
 
     emunet.set_state(EmunetState::Working);
     emunet.clear_device_login_info();
     let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
-    assert!(fut.await? == true);
+    if !fut.await? {
+        return Err(Error::Conflict(emunet.emunet_uuid()));
+    }
+    emunet_events::publish_state(&emunet);
+
+    let (job, cancel_token) = jobs::create_job(
+        &mut guarded_tran,
+        JobKind::Update,
+        emunet.emunet_uuid(),
+        emunet.emunet_user().to_string(),
+    )
+    .await?;
 
-    Ok(Ok((emunet, graph)))
+    Ok(Ok((emunet, graph, job, cancel_token)))
 }
 
 async fn guard(
     req: Request<String>,
+    caller: Caller,
     mut client: Client,
-) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = update_check(req, &mut client).await;
+) -> Result<super::Reply, warp::Rejection> {
+    let res = update_check(req, &caller, &mut client).await;
     match res {
         Ok(res) => match res {
-            Ok((emunet, graph)) => {
+            Ok((emunet, graph, job, cancel_token)) => {
                 let state_str = emunet.state().into();
-                tokio::spawn(background_task_guard(emunet, graph, client));
-
-                Ok(Response::success(ResponseData { status: state_str }).into())
-            }
-            Err(s) => {
-                let resp: Response<String> = Response::fail(s);
-                Ok(resp.into())
+                let job_uuid = job.job_uuid();
+                tokio::spawn(background_task_guard(
+                    emunet,
+                    graph,
+                    job,
+                    cancel_token,
+                    client,
+                ));
+
+                Ok(Response::success(ResponseData {
+                    status: state_str,
+                    job_uuid,
+                })
+                .into())
             }
+            Err(resp) => Ok(resp.into()),
         },
         Err(e) => {
             client.notify_failure();
@@ -174,30 +359,137 @@ pub fn build_filter(
     super::filter_template("update_emunet".to_string(), connector, guard)
 }
 
-#[derive(Deserialize)]
-struct InputNetworkGraph {
-    devs: Vec<InputDevice<String>>,
-    links: Vec<InputLink<String>>,
+// PUT /v2/emunets/{uuid}/topology
+pub fn build_v2_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::put()
+        .and(warp::path!("v2" / "emunets" / Uuid / "topology"))
+        // the caller is authenticated before the body is read
+        .and(super::auth::caller_filter(connector))
+        .and(super::parse_topology_body())
+        .and_then(
+            |emunet_uuid: Uuid, caller: Caller, client: Client, topology: Topology<String>| {
+                let req = Request {
+                    emunet_uuid,
+                    devs: topology.devs,
+                    links: topology.links,
+                    version: topology.version,
+                };
+                guard(req, caller, client)
+            },
+        )
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/update_emunet", "replace the topology of an emunet")
+        .body::<Request<String>>()
+        .reply::<ResponseData>();
+    spec.put(
+        "/v2/emunets/{emunet_uuid}/topology",
+        "replace the topology of an emunet",
+    )
+    .topology_body::<Topology<String>>()
+    .reply::<ResponseData>();
+}
+
+// gzip the topology, which shrinks the descriptions repeated over
+// thousands of devices and links
+pub(crate) fn compress_topology<T: Serialize>(topology: &T) -> Result<Vec<u8>, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut encoder, topology)
+        .map_err(|_| "can not encode network graph".to_string())?;
+    encoder
+        .finish()
+        .map_err(|_| "can not compress network graph".to_string())
+}
+
+// send the update request along with the current version of the emunet,
+// the request is resent if the emunet is modified in the meantime
+pub(crate) async fn send_update(
+    emunet_uuid: &Uuid,
+    mut topology: Topology<String>,
+    warp_addr: &str,
+) -> Result<(), String> {
+    let mut version = super::get_emunet_state::mnctl_emunet_version(emunet_uuid, warp_addr).await?;
+    for _ in 0..super::mnctl_util::CONFLICT_RETRIES {
+        topology.version = Some(version);
+        let http_resp = super::mnctl_util::http_client()
+            .put(format!(
+                "http://{}/v2/emunets/{}/topology",
+                warp_addr, emunet_uuid
+            ))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(reqwest::header::CONTENT_ENCODING, "gzip")
+            .body(compress_topology(&topology)?)
+            .send()
+            .await
+            .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+        let response: Response<ResponseData> = http_resp
+            .json()
+            .await
+            .map_err(|_| format!("can not parse JSON response"))?;
+
+        if response.success == true {
+            let data = response.data.unwrap();
+            println!("update success: {}, job: {}", data.status, data.job_uuid);
+            return Ok(());
+        }
+        match response.current_version {
+            Some(current_version) if response.failed_with("VERSION_CONFLICT") => {
+                println!("{}, retrying", response.message);
+                version = current_version;
+            }
+            _ => return Err(response.message),
+        }
+    }
+
+    Err(format!(
+        "emunet {} keeps being modified, giving up",
+        emunet_uuid
+    ))
 }
 
 pub async fn mnctl_network_update(
     user: &str,
     emunet: &str,
     input_file: &str,
+    format: Option<&str>,
+    id_map_file: Option<&str>,
     warp_addr: &str,
 ) -> Result<(), String> {
     // read input network graph
-    let json_str = read_to_string(&input_file)
+    let format = match format {
+        Some(name) => topology::Format::from_name(name)?,
+        None => topology::Format::from_path(input_file).ok_or(format!(
+            "can't tell the format of {}, please specify --format",
+            input_file
+        ))?,
+    };
+    let text = read_to_string(&input_file)
         .await
         .map_err(|_| format!("can't open input file at: {}", input_file))?;
-    let input_graph: InputNetworkGraph =
-        serde_json::from_str(&json_str).map_err(|_| "invalid network graph format".to_string())?;
+    let input_graph = topology::import(format, &text)?;
+    if input_graph.id_map.len() > 0 {
+        match id_map_file {
+            Some(path) => {
+                let json_str = serde_json::to_string_pretty(&input_graph.id_map).unwrap();
+                tokio::fs::write(path, json_str)
+                    .await
+                    .map_err(|_| format!("can't write the id map to {}", path))?;
+            }
+            None => println!(
+                "{} nodes are renumbered from 0, their names are kept in the device descriptions",
+                input_graph.id_map.len()
+            ),
+        }
+    }
 
     // query emunet_uuid
     let req = super::list_emunet::Request {
         user: user.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_emunet", warp_addr))
         .json(&req)
         .send()
@@ -217,28 +509,12 @@ pub async fn mnctl_network_update(
         .ok_or(format!("emunet {} does not exist", emunet))?;
 
     // send update request
-    let req = Request {
-        emunet_uuid: emunet_uuid.clone(),
+    let topology = Topology {
         devs: input_graph.devs,
         links: input_graph.links,
+        version: None,
     };
-    let http_resp = reqwest::Client::new()
-        .post(format!("http://{}/v1/update_emunet", warp_addr))
-        .json(&req)
-        .send()
-        .await
-        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
-    let response: Response<ResponseData> = http_resp
-        .json()
-        .await
-        .map_err(|_| format!("can not parse JSON response"))?;
-
-    if response.success == true {
-        println!("update success: {}", response.data.unwrap().status);
-        Ok(())
-    } else {
-        Err(response.message)
-    }
+    send_update(emunet_uuid, topology, warp_addr).await
 }
 
 pub async fn mnctl_network_restore(
@@ -251,7 +527,7 @@ pub async fn mnctl_network_restore(
     let req = super::list_user_history::Request {
         name: user.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_user_history", warp_addr))
         .json(&req)
         .send()
@@ -276,7 +552,7 @@ pub async fn mnctl_network_restore(
     let req = super::list_emunet::Request {
         user: user.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_emunet", warp_addr))
         .json(&req)
         .send()
@@ -297,8 +573,7 @@ pub async fn mnctl_network_restore(
 
     // send update request
     let retired_network = &retired_networks[restore_index];
-    let req = Request {
-        emunet_uuid: emunet_uuid.clone(),
+    let topology = Topology {
         devs: retired_network
             .nodes
             .iter()
@@ -315,22 +590,7 @@ pub async fn mnctl_network_restore(
                 description: String::new(),
             })
             .collect(),
+        version: None,
     };
-    let http_resp = reqwest::Client::new()
-        .post(format!("http://{}/v1/update_emunet", warp_addr))
-        .json(&req)
-        .send()
-        .await
-        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
-    let response: Response<ResponseData> = http_resp
-        .json()
-        .await
-        .map_err(|_| format!("can not parse JSON response"))?;
-
-    if response.success == true {
-        println!("update success: {}", response.data.unwrap().status);
-        Ok(())
-    } else {
-        Err(response.message)
-    }
+    send_update(emunet_uuid, topology, warp_addr).await
 }
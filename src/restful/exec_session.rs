@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+
+use futures::{SinkExt, StreamExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+use uuid::Uuid;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::EmunetState;
+use crate::errors::Error;
+use crate::k8s_api::ExecReq;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ExecSessionQuery")]
+pub(crate) struct Request {
+    pub(crate) emunet_uuid: Uuid,
+    pub(crate) dev_idx: u64,
+    pub(crate) cmd: String,
+}
+
+// messages sent from the server to the websocket client, every message
+// that the client sends is forwarded to the stdin of the command
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum SessionOutput {
+    StdOut { data: String },
+    StdErr { data: String },
+    Exit { code: i32 },
+    Error { message: String },
+}
+
+impl From<SessionOutput> for Message {
+    fn from(output: SessionOutput) -> Message {
+        Message::text(serde_json::to_string(&output).unwrap())
+    }
+}
+
+// find the api server address and the pod name of the device
+async fn session_target(
+    req: &Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Result<(String, String), Error>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
+
+    let emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Err(Error::EmunetNotFound(req.emunet_uuid))),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Err(caller.deny(format!("emunet {}", req.emunet_uuid))));
+    }
+    match emunet.state() {
+        EmunetState::Normal => {}
+        _ => {
+            return Ok(Err(Error::InvalidState(format!(
+                "emunet {} is not in normal state",
+                req.emunet_uuid
+            ))))
+        }
+    };
+
+    match emunet.get_pod_name(req.dev_idx) {
+        Some(pod_name) => Ok(Ok((emunet.api_server_addr().to_string(), pod_name))),
+        None => Ok(Err(Error::NotFound(format!(
+            "device {} in emunet {}",
+            req.dev_idx, req.emunet_uuid
+        )))),
+    }
+}
+
+async fn exec_session(socket: WebSocket, api_server_addr: String, pod_name: String, cmd: String) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let mut k8s_api_client = match super::execute_command::k8s_api_client(&api_server_addr).await {
+        Ok(inner) => inner,
+        Err(message) => {
+            let _ = ws_tx.send(SessionOutput::Error { message }.into()).await;
+            return;
+        }
+    };
+
+    let (stdin_tx, stdin_rx) = mpsc::channel(16);
+    let _ = stdin_tx
+        .send(ExecReq {
+            pod_name,
+            cmd: cmd.clone(),
+            std_in: Vec::new(),
+        })
+        .await;
+    let mut stream = match k8s_api_client
+        .exec(tonic::Request::new(ReceiverStream::new(stdin_rx)))
+        .await
+    {
+        Ok(inner) => inner.into_inner(),
+        Err(_) => {
+            let output = SessionOutput::Error {
+                message: format!("fail to execute command '{}'", cmd),
+            };
+            let _ = ws_tx.send(output.into()).await;
+            return;
+        }
+    };
+
+    // forward the websocket messages to the stdin of the command
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = ws_rx.next().await {
+            if msg.is_close() {
+                break;
+            }
+            // the pings and pongs are answered by warp, only the text and
+            // binary frames carry the input
+            if !(msg.is_text() || msg.is_binary()) {
+                continue;
+            }
+            let req = ExecReq {
+                pod_name: String::new(),
+                cmd: String::new(),
+                std_in: msg.into_bytes(),
+            };
+            if stdin_tx.send(req).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let output = match stream.message().await {
+            Ok(Some(resp)) => {
+                if resp.std_out.len() > 0 {
+                    let output = SessionOutput::StdOut {
+                        data: String::from_utf8_lossy(&resp.std_out).into_owned(),
+                    };
+                    if ws_tx.send(output.into()).await.is_err() {
+                        return;
+                    }
+                }
+                if resp.std_err.len() > 0 {
+                    let output = SessionOutput::StdErr {
+                        data: String::from_utf8_lossy(&resp.std_err).into_owned(),
+                    };
+                    if ws_tx.send(output.into()).await.is_err() {
+                        return;
+                    }
+                }
+                if !resp.exited {
+                    continue;
+                }
+                SessionOutput::Exit {
+                    code: resp.exit_code,
+                }
+            }
+            Ok(None) => SessionOutput::Error {
+                message: format!("command '{}' does not report its exit code", cmd),
+            },
+            Err(_) => SessionOutput::Error {
+                message: format!("fail to execute command '{}'", cmd),
+            },
+        };
+        let _ = ws_tx.send(output.into()).await;
+        let _ = ws_tx.close().await;
+        return;
+    }
+}
+
+async fn guard(
+    ws: Ws,
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let res = session_target(&req, &caller, &mut client).await;
+    match res {
+        Ok(Ok((api_server_addr, pod_name))) => {
+            let cmd = req.cmd;
+            Ok(Box::new(ws.on_upgrade(move |socket| {
+                exec_session(socket, api_server_addr, pod_name, cmd)
+            })))
+        }
+        Ok(Err(e)) => {
+            let resp: super::Reply = Response::<()>::fail(e).into();
+            Ok(Box::new(resp))
+        }
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            let resp: super::Reply = resp.into();
+            Ok(Box::new(resp))
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::path!("v1" / "exec_session")
+        .and(warp::ws())
+        .and(warp::query::<Request>())
+        .and(super::auth::caller_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.get(
+        "/v1/exec_session",
+        "run an interactive command over a websocket",
+    )
+    .query::<Request>()
+    .raw_reply::<SessionOutput>(
+        "101",
+        "application/json",
+        "the websocket carries the output of the command as JSON messages",
+    );
+}
+
+pub async fn mnctl_network_exec(
+    user: &str,
+    emunet: &str,
+    dev_idx: u64,
+    cmd: &str,
+    warp_addr: &str,
+) -> Result<(), String> {
+    // query emunet_uuid
+    let req = super::list_emunet::Request {
+        user: user.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_emunet", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<HashMap<String, Uuid>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let map = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+    let emunet_uuid = map
+        .get(emunet)
+        .ok_or(format!("emunet {} does not exist", emunet))?;
+
+    // open the exec session
+    let url = reqwest::Url::parse_with_params(
+        &format!("ws://{}/v1/exec_session", warp_addr),
+        &[
+            ("emunet_uuid", emunet_uuid.to_string()),
+            ("dev_idx", dev_idx.to_string()),
+            ("cmd", cmd.to_string()),
+        ],
+    )
+    .map_err(|_| format!("invalid warp address {}", warp_addr))?;
+    let mut request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|_| format!("invalid warp address {}", warp_addr))?;
+    if let Some(value) = super::mnctl_util::auth_header() {
+        let value = tungstenite::http::HeaderValue::from_str(&value)
+            .map_err(|_| "invalid bearer token".to_string())?;
+        request.headers_mut().insert("authorization", value);
+    }
+    let (socket, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(|_| format!("can not open exec session at {}", warp_addr))?;
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    // forward the local stdin line by line
+    tokio::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if ws_tx
+                .send(tungstenite::Message::Text(format!("{}\n", line)))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        let _ = ws_tx.close().await;
+    });
+
+    while let Some(msg) = ws_rx.next().await {
+        let msg = msg.map_err(|_| "exec session is broken".to_string())?;
+        let text = match msg {
+            tungstenite::Message::Text(text) => text,
+            tungstenite::Message::Close(_) => break,
+            _ => continue,
+        };
+        let output: SessionOutput =
+            serde_json::from_str(&text).map_err(|_| format!("can not parse session output"))?;
+        match output {
+            SessionOutput::StdOut { data } => print!("{}", data),
+            SessionOutput::StdErr { data } => eprint!("{}", data),
+            SessionOutput::Exit { code } => {
+                println!("exit code: {}", code);
+                return Ok(());
+            }
+            SessionOutput::Error { message } => return Err(message),
+        }
+    }
+
+    Err("exec session is closed without an exit code".to_string())
+}
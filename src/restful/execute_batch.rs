@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::execute_command::{run_command, ExecOutput};
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::EmunetState;
+use crate::errors::Error;
+
+static DEFAULT_CONCURRENCY: usize = 16;
+static MAX_CONCURRENCY: usize = 128;
+
+#[derive(Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Target {
+    Dev(u64),
+    Devs(Vec<u64>),
+    All,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct BatchCommand {
+    pub(crate) target: Target,
+    pub(crate) cmd: String,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ExecuteBatchRequest")]
+pub(crate) struct Request {
+    pub(crate) emunet_uuid: Uuid,
+    pub(crate) commands: Vec<BatchCommand>,
+    pub(crate) concurrency: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct DevResult {
+    pub(crate) dev_idx: u64,
+    pub(crate) cmd: String,
+    pub(crate) output: Option<ExecOutput>,
+    pub(crate) error: Option<String>,
+}
+
+async fn execute_batch(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<Vec<DevResult>>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
+
+    // make sure that we can execute command in this emunet
+    let emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
+    match emunet.state() {
+        EmunetState::Normal => {}
+        _ => {
+            return Ok(Response::fail(Error::InvalidState(format!(
+                "emunet {} is not in normal state",
+                req.emunet_uuid
+            ))))
+        }
+    };
+    drop(tran);
+
+    // expand the targets into (dev_idx, pod_name, cmd) triples
+    let mut jobs = Vec::new();
+    for command in req.commands.into_iter() {
+        let dev_idxs = match command.target {
+            Target::Dev(dev_idx) => vec![dev_idx],
+            Target::Devs(dev_idxs) => dev_idxs,
+            Target::All => emunet.dev_idxs(),
+        };
+        for dev_idx in dev_idxs {
+            let pod_name = match emunet.get_pod_name(dev_idx) {
+                Some(inner) => inner,
+                None => {
+                    return Ok(Response::fail(Error::NotFound(format!(
+                        "device {} in emunet {}",
+                        dev_idx, req.emunet_uuid
+                    ))))
+                }
+            };
+            jobs.push((dev_idx, pod_name, command.cmd.clone()));
+        }
+    }
+
+    let concurrency = req
+        .concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .max(1)
+        .min(MAX_CONCURRENCY);
+    let api_server_addr = emunet.api_server_addr().to_string();
+    let results = futures::stream::iter(jobs.into_iter().map(|(dev_idx, pod_name, cmd)| {
+        let api_server_addr = api_server_addr.clone();
+        async move {
+            match run_command(api_server_addr, pod_name, cmd.clone()).await {
+                Ok(output) => DevResult {
+                    dev_idx,
+                    cmd,
+                    output: Some(output),
+                    error: None,
+                },
+                Err(err) => DevResult {
+                    dev_idx,
+                    cmd,
+                    output: None,
+                    error: Some(err),
+                },
+            }
+        }
+    }))
+    .buffered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+
+    Ok(Response::success(results))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = execute_batch(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("execute_batch".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/execute_batch", "run commands on several devices")
+        .body::<Request>()
+        .reply::<Vec<DevResult>>();
+}
+
+pub async fn mnctl_network_batch_exec(
+    user: &str,
+    emunet: &str,
+    dev_idxs: Option<Vec<u64>>,
+    cmd: &str,
+    warp_addr: &str,
+) -> Result<(), String> {
+    // query emunet_uuid
+    let req = super::list_emunet::Request {
+        user: user.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_emunet", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<HashMap<String, Uuid>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let map = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+    let emunet_uuid = map
+        .get(emunet)
+        .ok_or(format!("emunet {} does not exist", emunet))?;
+
+    // run the command on all the selected devices
+    let req = Request {
+        emunet_uuid: emunet_uuid.clone(),
+        commands: vec![BatchCommand {
+            target: dev_idxs
+                .map(|inner| Target::Devs(inner))
+                .unwrap_or(Target::All),
+            cmd: cmd.to_string(),
+        }],
+        concurrency: None,
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/execute_batch", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<Vec<DevResult>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success {
+        for result in response.data.unwrap() {
+            match (result.output, result.error) {
+                (Some(output), _) => {
+                    println!("device {}: exit code {}", result.dev_idx, output.exit_code);
+                    print!("{}", output.std_out);
+                    eprint!("{}", output.std_err);
+                }
+                (None, err) => {
+                    println!("device {}: {}", result.dev_idx, err.unwrap_or_default());
+                }
+            }
+        }
+        Ok(())
+    } else {
+        Err(response.message)
+    }
+}
@@ -1,44 +1,136 @@
-use indradb_proto::ClientError;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tonic::transport::Channel;
 use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
 use super::Response;
-use crate::database::{helpers, Client, Connector};
+use crate::database::{helpers, Client, Connector, Scope};
 use crate::emunet::EmunetState;
-use crate::k8s_api::{mocknet_client, ExecReq};
+use crate::errors::Error;
+use crate::k8s_api::{mocknet_client::MocknetClient, ExecReq};
+
+lazy_static! {
+    static ref K8S_API_CLIENTS: Mutex<HashMap<String, MocknetClient<Channel>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub(crate) struct ExecOutput {
+    pub(crate) std_out: String,
+    pub(crate) std_err: String,
+    pub(crate) exit_code: i32,
+}
+
+// the clients multiplex all the grpc calls over a single connection,
+// so they are shared by all the requests to the same api server
+pub(crate) async fn k8s_api_client(
+    api_server_addr: &str,
+) -> Result<MocknetClient<Channel>, String> {
+    let cached = K8S_API_CLIENTS
+        .lock()
+        .unwrap()
+        .get(api_server_addr)
+        .map(|client| client.clone());
+    if let Some(client) = cached {
+        return Ok(client);
+    }
+
+    let client = MocknetClient::connect(api_server_addr.to_string())
+        .await
+        .map_err(|_| format!("can't connect to k8s api server at {}", api_server_addr))?;
+    K8S_API_CLIENTS
+        .lock()
+        .unwrap()
+        .insert(api_server_addr.to_string(), client.clone());
+    Ok(client)
+}
+
+fn forget_k8s_api_client(api_server_addr: &str) {
+    K8S_API_CLIENTS.lock().unwrap().remove(api_server_addr);
+}
+
+// run a non-interactive command on a pod and wait for it to exit
+pub(crate) async fn run_command(
+    api_server_addr: String,
+    pod_name: String,
+    cmd: String,
+) -> Result<ExecOutput, String> {
+    let mut k8s_api_client = k8s_api_client(&api_server_addr).await?;
+
+    let grpc_req = tonic::Request::new(tokio_stream::iter(vec![ExecReq {
+        pod_name,
+        cmd: cmd.clone(),
+        std_in: Vec::new(),
+    }]));
+    let mut stream = match k8s_api_client.exec(grpc_req).await {
+        Ok(inner) => inner.into_inner(),
+        Err(_) => {
+            // the connection may be broken, reconnect on the next call
+            forget_k8s_api_client(&api_server_addr);
+            return Err(format!("fail to execute command '{}'", cmd));
+        }
+    };
 
-#[derive(Deserialize, Serialize)]
+    let mut std_out = Vec::new();
+    let mut std_err = Vec::new();
+    loop {
+        let resp = stream
+            .message()
+            .await
+            .map_err(|_| format!("fail to execute command '{}'", cmd))?
+            .ok_or(format!("command '{}' does not report its exit code", cmd))?;
+        std_out.extend_from_slice(&resp.std_out);
+        std_err.extend_from_slice(&resp.std_err);
+        if resp.exited {
+            return Ok(ExecOutput {
+                std_out: String::from_utf8_lossy(&std_out).into_owned(),
+                std_err: String::from_utf8_lossy(&std_err).into_owned(),
+                exit_code: resp.exit_code,
+            });
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ExecuteCommandRequest")]
 pub(crate) struct Request {
     pub(crate) emunet_uuid: Uuid,
     pub(crate) dev_idx: u64,
     pub(crate) cmd: String,
-    pub(crate) api_server_addr: String,
 }
 
 async fn execute_command(
     req: Request,
+    caller: &Caller,
     client: &mut Client,
-) -> Result<Response<String>, ClientError> {
-    let mut tran = client.guarded_tran().await?;
+) -> Result<Response<ExecOutput>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
 
     // make sure that we can execute command in this emunet
     let emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
-        None => {
-            return Ok(Response::fail(format!(
-                "emunet {} does not exist",
-                req.emunet_uuid
-            )))
-        }
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
         Some(emunet) => emunet,
     };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
     match emunet.state() {
         EmunetState::Normal => {}
         _ => {
-            return Ok(Response::fail(format!(
+            return Ok(Response::fail(Error::InvalidState(format!(
                 "emunet {} is not in normal state",
                 req.emunet_uuid
-            )))
+            ))))
         }
     };
 
@@ -46,43 +138,32 @@ async fn execute_command(
     let pod_name = match emunet.get_pod_name(req.dev_idx) {
         Some(inner) => inner,
         None => {
-            return Ok(Response::fail(format!(
-                "device {} is not presetned in emunet {}",
+            return Ok(Response::fail(Error::NotFound(format!(
+                "device {} in emunet {}",
                 req.dev_idx, req.emunet_uuid
-            )))
+            ))))
         }
     };
 
-    // run the grpc command
-    let mut k8s_api_client =
-        match mocknet_client::MocknetClient::connect(req.api_server_addr.clone()).await {
-            Ok(inner) => inner,
-            Err(_) => {
-                return Ok(Response::fail(format!(
-                    "can't connect to k8s api server at {}",
-                    req.api_server_addr
-                )));
-            }
-        };
-    let grpc_req = tonic::Request::new(ExecReq {
-        pod_name: pod_name,
-        cmd: req.cmd.clone(),
-    });
-    let response = match k8s_api_client.exec(grpc_req).await {
-        Ok(inner) => inner.into_inner(),
-        Err(_) => {
-            return Ok(Response::fail(format!(
-                "fail to execute command '{}' on device {}",
-                req.cmd, req.dev_idx
-            )));
-        }
-    };
-
-    Ok(Response::success(response.std_out))
+    // run the grpc command, other transactions on the emunet should not
+    // wait for the command to finish
+    let api_server_addr = emunet.api_server_addr().to_string();
+    drop(tran);
+    match run_command(api_server_addr, pod_name, req.cmd.clone()).await {
+        Ok(output) => Ok(Response::success(output)),
+        Err(_) => Ok(Response::fail(Error::K8sUnavailable(format!(
+            "fail to execute command '{}' on device {}",
+            req.cmd, req.dev_idx
+        )))),
+    }
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = execute_command(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = execute_command(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -98,3 +179,9 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("execute_command".to_string(), connector, guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/execute_command", "run a command on a device")
+        .body::<Request>()
+        .reply::<ExecOutput>();
+}
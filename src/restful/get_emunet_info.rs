@@ -1,16 +1,19 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warp::Filter;
 
-use super::Response;
+use super::auth::Caller;
+use super::{Cached, Response};
 use crate::algo::UndirectedGraph;
-use crate::database::{helpers, Client, Connector};
-use crate::emunet::{EmunetAccessInfo, OutputDevice, OutputLink};
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::{Emunet, EmunetAccessInfo, LeaseStage, OutputDevice, OutputLink};
+use crate::errors::Error;
+use crate::topology;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 struct EmunetInfo {
     emunet_id: u8,
     emunet_name: String,
@@ -20,33 +23,67 @@ struct EmunetInfo {
     access_info: EmunetAccessInfo,
     state: String,
     dev_count: u64,
+    version: u64,
+    lease_expiry: Option<u64>,
+    lease_warning: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "EmunetInfoData")]
 struct ResponseData {
     emunet_info: EmunetInfo,
     devices: Vec<OutputDevice>,
     links: Vec<OutputLink>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "GetEmunetInfoRequest")]
 struct Request {
     emunet_uuid: Uuid,
 }
 
+// the revision of the emunet changes whenever the emunet is stored
+fn etag(emunet: &Emunet) -> String {
+    format!("{}-{}", emunet.emunet_uuid(), emunet.revision())
+}
+
+// the lease warning in the emunet info is computed at request time, so its
+// etag also changes when the lease moves to another stage
+fn info_etag(emunet: &Emunet, now: u64) -> String {
+    let lease_stage = match emunet.lease_stage(now) {
+        LeaseStage::Running => "running",
+        LeaseStage::Expiring => "expiring",
+        LeaseStage::Expired => "expired",
+    };
+    format!("{}-{}", etag(emunet), lease_stage)
+}
+
 async fn get_emunet_info(
     req: Request,
+    if_none_match: Option<&str>,
+    caller: &Caller,
     client: &mut Client,
-) -> Result<Response<ResponseData>, ClientError> {
-    let mut tran = client.guarded_tran().await?;
+) -> Result<Cached<ResponseData>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
+
+    // the devices are only read if the client does not hold them yet
+    let emunet = match helpers::get_emunet_header(&mut tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid)).into()),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(caller.deny(format!("emunet {}", req.emunet_uuid))).into());
+    }
+    let now = crate::emunet::unix_time();
+    let etag = info_etag(&emunet, now);
+    if super::etag_matches(if_none_match, &etag) {
+        return Ok(Cached::NotModified(etag));
+    }
 
     let emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
-        None => {
-            return Ok(Response::fail(format!(
-                "emunet {} does not exist",
-                req.emunet_uuid
-            )))
-        }
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid)).into()),
         Some(emunet) => emunet,
     };
     let (devices, links) = emunet.release_output_emunet();
@@ -65,19 +102,27 @@ async fn get_emunet_info(
         },
         state: emunet.state().into(),
         dev_count: emunet.dev_count(),
+        version: emunet.version_num(),
+        lease_expiry: emunet.lease_expiry(),
+        lease_warning: emunet.lease_warning(now),
     };
 
-    Ok(Response::success(ResponseData {
+    let resp = Response::success(ResponseData {
         emunet_info,
         devices,
         links,
-    }))
+    });
+    Ok(Cached::new(resp, etag, if_none_match))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = get_emunet_info(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = get_emunet_info(req, None, &caller, &mut client).await;
     match res {
-        Ok(resp) => Ok(resp.into()),
+        Ok(cached) => Ok(cached.into_response().into()),
         Err(e) => {
             client.notify_failure();
             let resp: Response<_> = e.into();
@@ -86,18 +131,145 @@ async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, wa
     }
 }
 
+async fn v2_guard(
+    emunet_uuid: Uuid,
+    if_none_match: Option<String>,
+    caller: Caller,
+    mut client: Client,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let req = Request { emunet_uuid };
+    let res = get_emunet_info(req, if_none_match.as_deref(), &caller, &mut client).await;
+    match res {
+        Ok(cached) => Ok(cached.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(Cached::from(resp).into())
+        }
+    }
+}
+
 pub fn build_filter(
     connector: Connector,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("get_emunet_info".to_string(), connector, guard)
 }
 
+// GET /v2/emunets/{uuid}
+pub fn build_v2_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v2" / "emunets" / Uuid))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(super::auth::caller_filter(connector))
+        .and_then(v2_guard)
+}
+
+// a single device of the emunet, only the vertex of the device is read
+async fn get_device(
+    req: Request,
+    dev_id: u64,
+    if_none_match: Option<&str>,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Cached<OutputDevice>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
+
+    let emunet = match helpers::get_emunet_header(&mut tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid)).into()),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(caller.deny(format!("emunet {}", req.emunet_uuid))).into());
+    }
+    let etag = etag(&emunet);
+    if super::etag_matches(if_none_match, &etag) {
+        return Ok(Cached::NotModified(etag));
+    }
+
+    // emunets stored in the old format carry their devices in the header
+    let dev = match emunet.devices().get(&dev_id) {
+        Some(dev) => Some(dev.get_output_device()),
+        None => None,
+    };
+    let dev = match (dev, emunet.device_vertex(dev_id)) {
+        (Some(dev), _) => Some(dev),
+        (None, Some(dev_vid)) => helpers::get_device(&mut tran, dev_vid)
+            .await?
+            .map(|dev| dev.get_output_device()),
+        (None, None) => None,
+    };
+    let resp = match dev {
+        Some(dev) => Response::success(dev),
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "device {} in emunet {}",
+                dev_id, req.emunet_uuid
+            )))
+            .into())
+        }
+    };
+    Ok(Cached::new(resp, etag, if_none_match))
+}
+
+async fn device_guard(
+    emunet_uuid: Uuid,
+    dev_id: u64,
+    if_none_match: Option<String>,
+    caller: Caller,
+    mut client: Client,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let req = Request { emunet_uuid };
+    let res = get_device(req, dev_id, if_none_match.as_deref(), &caller, &mut client).await;
+    match res {
+        Ok(cached) => Ok(cached.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(Cached::from(resp).into())
+        }
+    }
+}
+
+// GET /v2/emunets/{uuid}/devices/{id}
+pub fn build_v2_device_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v2" / "emunets" / Uuid / "devices" / u64))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(super::auth::caller_filter(connector))
+        .and_then(device_guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/get_emunet_info",
+        "get the devices and links of an emunet",
+    )
+    .body::<Request>()
+    .reply::<ResponseData>();
+    spec.get(
+        "/v2/emunets/{emunet_uuid}",
+        "get the devices and links of an emunet",
+    )
+    .cached_reply::<ResponseData>();
+    spec.get(
+        "/v2/emunets/{emunet_uuid}/devices/{dev_id}",
+        "get a device of an emunet",
+    )
+    .cached_reply::<OutputDevice>();
+}
+
 pub async fn mnctl_network_info(user: &str, emunet: &str, warp_addr: &str) -> Result<(), String> {
     // query emunet_uuid
     let req = super::list_emunet::Request {
         user: user.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_emunet", warp_addr))
         .json(&req)
         .send()
@@ -120,7 +292,7 @@ pub async fn mnctl_network_info(user: &str, emunet: &str, warp_addr: &str) -> Re
     let req = Request {
         emunet_uuid: emunet_uuid.clone(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/get_emunet_info", warp_addr))
         .json(&req)
         .send()
@@ -138,6 +310,13 @@ pub async fn mnctl_network_info(user: &str, emunet: &str, warp_addr: &str) -> Re
         println!("state: {}", &data.emunet_info.state);
         println!("max capacity: {}", data.emunet_info.max_capacity);
         println!("active devices: {}", data.emunet_info.dev_count);
+        println!("version: {}", data.emunet_info.version);
+        if let Some(lease_expiry) = data.emunet_info.lease_expiry {
+            println!("lease expiry: {}", lease_expiry);
+        }
+        if let Some(lease_warning) = data.emunet_info.lease_warning.as_ref() {
+            println!("warning: {}", lease_warning);
+        }
         println!(
             "login server address: {}",
             &data.emunet_info.access_info.login_server_addr
@@ -167,6 +346,70 @@ pub async fn mnctl_network_info(user: &str, emunet: &str, warp_addr: &str) -> Re
     }
 }
 
+pub async fn mnctl_network_export(
+    user: &str,
+    emunet: &str,
+    format: &str,
+    output_file: Option<&str>,
+    warp_addr: &str,
+) -> Result<(), String> {
+    let format = topology::Format::from_name(format)?;
+
+    // query emunet_uuid
+    let req = super::list_emunet::Request {
+        user: user.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_emunet", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<HashMap<String, Uuid>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let map = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+    let emunet_uuid = map
+        .get(emunet)
+        .ok_or(format!("emunet {} does not exist", emunet))?;
+
+    // the devices and links carry the placement and the addresses
+    let req = Request {
+        emunet_uuid: emunet_uuid.clone(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/get_emunet_info", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<ResponseData> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let data = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+
+    let text = topology::export(format, emunet, &data.devices, &data.links)?;
+    match output_file {
+        Some(path) => tokio::fs::write(path, text)
+            .await
+            .map_err(|_| format!("can not write to {}", path)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
 pub async fn mnctl_network_dev(
     user: &str,
     emunet: &str,
@@ -177,7 +420,7 @@ pub async fn mnctl_network_dev(
     let req = super::list_emunet::Request {
         user: user.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_emunet", warp_addr))
         .json(&req)
         .send()
@@ -200,7 +443,7 @@ pub async fn mnctl_network_dev(
     let req = Request {
         emunet_uuid: emunet_uuid.clone(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/get_emunet_info", warp_addr))
         .json(&req)
         .send()
@@ -273,7 +516,7 @@ pub async fn mnctl_network_path(
     let req = super::list_emunet::Request {
         user: user.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_emunet", warp_addr))
         .json(&req)
         .send()
@@ -296,7 +539,7 @@ pub async fn mnctl_network_path(
     let req = Request {
         emunet_uuid: emunet_uuid.clone(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/get_emunet_info", warp_addr))
         .json(&req)
         .send()
@@ -359,7 +602,7 @@ pub async fn mnctl_network_connect(
         path,
         is_add,
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/route_command", warp_addr))
         .json(&req)
         .send()
@@ -383,26 +626,25 @@ pub async fn mnctl_network_connect(
             emunet_uuid: emunet_uuid.clone(),
             dev_idx,
             cmd,
-            api_server_addr: route_commands.api_server_addr.clone(),
         };
-        let http_resp = reqwest::Client::new()
+        let http_resp = super::mnctl_util::http_client()
             .post(format!("http://{}/v1/execute_command", warp_addr))
             .json(&req)
             .send()
             .await
             .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
-        let response: Response<String> = http_resp
+        let response: Response<super::execute_command::ExecOutput> = http_resp
             .json()
             .await
             .map_err(|_| format!("can not parse JSON response"))?;
         if response.success == false {
             return Err(response.message);
         }
-        let err_msg = response.data.unwrap();
-        if err_msg.len() == 0 {
+        let output = response.data.unwrap();
+        if output.exit_code == 0 {
             println!("done");
         } else {
-            println!("conflict, cancelled");
+            println!("conflict, cancelled: {}", output.std_err.trim_end());
         }
     }
     for (dev_idx, cmd) in route_commands.backward_route_commands {
@@ -411,26 +653,25 @@ pub async fn mnctl_network_connect(
             emunet_uuid: emunet_uuid.clone(),
             dev_idx,
             cmd,
-            api_server_addr: route_commands.api_server_addr.clone(),
         };
-        let http_resp = reqwest::Client::new()
+        let http_resp = super::mnctl_util::http_client()
             .post(format!("http://{}/v1/execute_command", warp_addr))
             .json(&req)
             .send()
             .await
             .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
-        let response: Response<String> = http_resp
+        let response: Response<super::execute_command::ExecOutput> = http_resp
             .json()
             .await
             .map_err(|_| format!("can not parse JSON response"))?;
         if response.success == false {
             return Err(response.message);
         }
-        let err_msg = response.data.unwrap();
-        if err_msg.len() == 0 {
+        let output = response.data.unwrap();
+        if output.exit_code == 0 {
             println!("done");
         } else {
-            println!("conflict, cancelled");
+            println!("conflict, cancelled: {}", output.std_err.trim_end());
         }
     }
 
@@ -1,47 +1,60 @@
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
 use super::Response;
-use crate::database::{helpers, Client, Connector};
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::errors::Error;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "GetEmunetStateRequest")]
 struct Request {
     emunet_uuid: Uuid,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "EmunetStateData")]
 struct State {
     emunet_uuid: Uuid,
     state: String,
+    version: u64,
 }
 
 async fn get_emunet_state(
     req: Request,
+    caller: &Caller,
     client: &mut Client,
-) -> Result<Response<State>, ClientError> {
-    let mut tran = client.guarded_tran().await?;
+) -> Result<Response<State>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
 
     let emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
-        None => {
-            return Ok(Response::fail(format!(
-                "emunet {} does not exist",
-                req.emunet_uuid
-            )))
-        }
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
         Some(emunet) => emunet,
     };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
     
 
     Ok(Response::success(State {
         emunet_uuid: req.emunet_uuid,
         state: emunet.state().into(),
+        version: emunet.version_num(),
     }))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = get_emunet_state(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = get_emunet_state(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -57,3 +70,39 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("get_emunet_state".to_string(), connector, guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/get_emunet_state",
+        "get the state and version of an emunet",
+    )
+    .body::<Request>()
+    .reply::<State>();
+}
+
+// the current version of the emunet, mnctl sends it along with the requests
+// that modify the emunet
+pub(crate) async fn mnctl_emunet_version(
+    emunet_uuid: &Uuid,
+    warp_addr: &str,
+) -> Result<u64, String> {
+    let req = Request {
+        emunet_uuid: emunet_uuid.clone(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/get_emunet_state", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<State> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success {
+        Ok(response.data.unwrap().version)
+    } else {
+        Err(response.message)
+    }
+}
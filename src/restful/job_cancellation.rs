@@ -0,0 +1,70 @@
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector};
+use crate::errors::Error;
+
+async fn cancel_job(
+    job_uuid: Uuid,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<()>, Error> {
+    let mut tran = client.guarded_tran().await?;
+
+    let job = match helpers::get_job(&mut tran, job_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::NotFound(format!("job {}", job_uuid)))),
+        Some(job) => job,
+    };
+    if !caller.can_access(job.user_name()) {
+        return Ok(Response::fail(caller.deny(format!("job {}", job_uuid))));
+    }
+    if !job.is_running() {
+        return Ok(Response::fail(Error::InvalidState(format!(
+            "job {} is not running",
+            job_uuid
+        ))));
+    }
+
+    // the background task of the job performs the rollback and records
+    // the final job state
+    if !super::jobs::cancel(&job_uuid) {
+        return Ok(Response::fail(Error::InvalidState(format!(
+            "job {} can not be cancelled",
+            job_uuid
+        ))));
+    }
+
+    Ok(Response::success(()))
+}
+
+async fn guard(
+    job_uuid: Uuid,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = cancel_job(job_uuid, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::post()
+        .and(warp::path!("v1" / "jobs" / Uuid / "cancel"))
+        .and(super::auth::caller_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/jobs/{job_uuid}/cancel", "cancel a running job")
+        .reply::<()>();
+}
@@ -0,0 +1,82 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::errors::Error;
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub(crate) struct JobInfo {
+    pub(crate) job_uuid: Uuid,
+    pub(crate) kind: String,
+    pub(crate) emunet_uuid: Uuid,
+    pub(crate) state: String,
+    pub(crate) finished: bool,
+    pub(crate) logs: Vec<String>,
+    pub(crate) result: Option<String>,
+}
+
+async fn get_job(
+    job_uuid: Uuid,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<JobInfo>, Error> {
+    // a job is stored in a single property, which is read atomically
+    let mut tran = client.scoped_tran(Scope::new()).await?;
+
+    let job = match helpers::get_job(&mut tran, job_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::NotFound(format!("job {}", job_uuid)))),
+        Some(job) => job,
+    };
+    if !caller.can_access(job.user_name()) {
+        return Ok(Response::fail(caller.deny(format!("job {}", job_uuid))));
+    }
+
+    Ok(Response::success(JobInfo {
+        job_uuid: job.job_uuid(),
+        kind: job.kind().into(),
+        emunet_uuid: job.emunet_uuid(),
+        state: job.state().into(),
+        finished: !job.is_running(),
+        // the stored logs of a running job lag behind until its progress
+        // is saved
+        logs: match job.is_running() {
+            true => super::jobs::running_logs(&job_uuid).unwrap_or(job.logs()),
+            false => job.logs(),
+        },
+        result: job.result(),
+    }))
+}
+
+async fn guard(
+    job_uuid: Uuid,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = get_job(job_uuid, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v1" / "jobs" / Uuid))
+        .and(super::auth::caller_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.get("/v1/jobs/{job_uuid}", "get the state and logs of a job")
+        .reply::<JobInfo>();
+}
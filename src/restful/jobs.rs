@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::database::helpers::{self, EmunetWrite};
+use crate::database::{Client, GuardedTransaction, Scope};
+use crate::emunet::{self, Emunet, EmunetState, Job, JobKind, Retired};
+use crate::errors::Error;
+use crate::k8s_api;
+
+lazy_static! {
+    static ref CANCEL_SENDERS: Mutex<HashMap<Uuid, watch::Sender<bool>>> =
+        Mutex::new(HashMap::new());
+    // the logs of the jobs running in this server process, the stored job
+    // only catches up when its progress is saved
+    static ref RUNNING_LOGS: Mutex<HashMap<Uuid, Vec<String>>> = Mutex::new(HashMap::new());
+}
+
+// CancelToken is handed to the background task of a job, the task
+// checks it between two queries to the k8s api server.
+#[derive(Clone)]
+pub(crate) struct CancelToken {
+    rx: watch::Receiver<bool>,
+}
+
+impl CancelToken {
+    // a token that can never be cancelled, used when rolling back a job
+    pub(crate) fn never() -> Self {
+        let (_, rx) = watch::channel(false);
+        Self { rx }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        *self.rx.borrow()
+    }
+}
+
+pub(crate) fn unregister(job_uuid: &Uuid) {
+    CANCEL_SENDERS.lock().unwrap().remove(job_uuid);
+    RUNNING_LOGS.lock().unwrap().remove(job_uuid);
+}
+
+// the up to date logs of a job, None if the job is not running in this
+// server process
+pub(crate) fn running_logs(job_uuid: &Uuid) -> Option<Vec<String>> {
+    RUNNING_LOGS.lock().unwrap().get(job_uuid).cloned()
+}
+
+// return false if the job is not running in this server process
+pub(crate) fn cancel(job_uuid: &Uuid) -> bool {
+    CANCEL_SENDERS
+        .lock()
+        .unwrap()
+        .get(job_uuid)
+        .map(|sender| sender.send(true).is_ok())
+        .unwrap_or(false)
+}
+
+// create a new job vertex in the database and register its cancel token
+pub(crate) async fn create_job(
+    guarded_tran: &mut GuardedTransaction,
+    kind: JobKind,
+    emunet_uuid: Uuid,
+    user_name: String,
+) -> Result<(Job, CancelToken), Error> {
+    let job_uuid = indradb::util::generate_uuid_v1();
+    if !(helpers::create_vertex(guarded_tran, job_uuid.clone()).await?) {
+        return Err(Error::Corrupted(format!(
+            "job vertex {} already exists",
+            job_uuid
+        )));
+    }
+
+    let job = Job::new(job_uuid.clone(), kind, emunet_uuid, user_name);
+    let fut = helpers::set_job(guarded_tran, &job);
+    if !fut.await? {
+        return Err(Error::Corrupted(format!(
+            "job vertex {} is missing",
+            job_uuid
+        )));
+    }
+    // the jobs are deleted together with their emunet
+    if !(helpers::link_job(guarded_tran, emunet_uuid, job_uuid.clone()).await?) {
+        return Err(Error::EmunetNotFound(emunet_uuid));
+    }
+
+    let (tx, rx) = watch::channel(false);
+    {
+        let mut cancel_senders = CANCEL_SENDERS.lock().unwrap();
+        if cancel_senders.contains_key(&job_uuid) {
+            return Err(Error::Corrupted(format!(
+                "job {} is already registered",
+                job_uuid
+            )));
+        }
+        cancel_senders.insert(job_uuid, tx);
+    }
+    RUNNING_LOGS.lock().unwrap().insert(job_uuid, Vec::new());
+
+    Ok((job, CancelToken { rx }))
+}
+
+// write the emunet and its job back to the database
+fn store_progress<'a>(
+    client: &'a mut Client,
+    emunet: &Emunet,
+    job: &Job,
+) -> impl Future<Output = Result<(), Error>> + Send + 'a {
+    let emunet_uuid = emunet.emunet_uuid();
+    let emunet_write = EmunetWrite::new(emunet);
+    let job_uuid = job.job_uuid();
+    let job_jv = serde_json::to_value(job).unwrap();
+
+    async move {
+        let mut guarded_tran = client
+            .scoped_tran(Scope::new().write_emunet(emunet_uuid))
+            .await?;
+        if !emunet_write.store(&mut guarded_tran).await? {
+            return Err(Error::Conflict(emunet_uuid));
+        }
+        helpers::set_vertex_json_value(
+            &mut guarded_tran,
+            job_uuid,
+            emunet::JOB_NODE_PROPERTY,
+            &job_jv,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+// store the progress of a background task, None is returned if the write
+// fails and the task should stop
+pub(crate) async fn save_progress(
+    client: &mut Client,
+    emunet: Emunet,
+    job: Job,
+) -> Option<(Emunet, Job)> {
+    let revision = emunet.revision();
+    let fut = store_progress(client, &emunet, &job);
+    match fut.await {
+        Ok(_) => Some((emunet, job)),
+        Err(e) => {
+            // the failed write does not take the new revision
+            emunet.reset_revision(revision);
+            abort(client, emunet, job, e).await;
+            None
+        }
+    }
+}
+
+// stop a background task on a database failure, the failure is recorded in
+// the emunet state and the job, which are stored in a last attempt
+pub(crate) async fn abort(client: &mut Client, emunet: Emunet, job: Job, e: Error) {
+    let err_str = format!("database failure, {}", e);
+    emunet.set_state(EmunetState::Error(err_str.clone()));
+    job.fail(err_str);
+    unregister(&job.job_uuid());
+
+    let fut = store_progress(client, &emunet, &job);
+    if let Err(e) = fut.await {
+        super::emunet_events::publish_log(
+            &emunet,
+            format!("the emunet is left in its stored state, {}", e),
+        );
+    }
+    super::emunet_events::publish_state(&emunet);
+}
+
+// append a torn down topology to the history of the user
+pub(crate) async fn retire(
+    guarded_tran: &mut GuardedTransaction,
+    user_name: &str,
+    retired: Retired,
+) -> Result<(), Error> {
+    let user_map = helpers::get_user_map(guarded_tran).await?;
+    match user_map.get(user_name) {
+        Some(user) => user.add_retired(retired),
+        None => return Err(Error::Corrupted(format!("user {} is missing", user_name))),
+    }
+    helpers::set_user_map(guarded_tran, user_map).await
+}
+
+// record a log line on the job and forward it to the event stream
+pub(crate) fn log<S: std::convert::Into<String>>(emunet: &Emunet, job: &Job, line: S) {
+    let line = line.into();
+    super::emunet_events::publish_log(emunet, line.clone());
+    if let Some(logs) = RUNNING_LOGS.lock().unwrap().get_mut(&job.job_uuid()) {
+        logs.push(line.clone());
+    }
+    job.log(line);
+}
+
+// apply the result of relaunching the previous topology after a job is cancelled
+pub(crate) fn finish_rollback(
+    emunet: &Emunet,
+    job: &Job,
+    res: Result<Vec<k8s_api::DeviceInfo>, String>,
+) {
+    match res {
+        Ok(device_infos) => {
+            emunet.update_device_login_info(&device_infos);
+            emunet.set_state(EmunetState::Normal);
+            log(emunet, job, "rollback finished");
+            job.cancel();
+        }
+        Err(err_str) => {
+            emunet.set_state(EmunetState::Error(err_str.clone()));
+            job.fail(format!("rollback fails: {}", err_str));
+        }
+    }
+}
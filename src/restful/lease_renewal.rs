@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector};
+use crate::emunet::{EmunetState, ServerInfo, MAX_LEASE_SECS};
+use crate::errors::Error;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "RenewLeaseRequest")]
+pub(crate) struct Request {
+    pub(crate) emunet_uuid: Uuid,
+    pub(crate) lease_secs: u64,
+    pub(crate) version: Option<u64>, // the version of the emunet read by the client
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "RenewLeaseData")]
+pub(crate) struct ResponseData {
+    pub(crate) lease_expiry: u64,
+}
+
+async fn renew_lease(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<ResponseData>, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    let emunet = match helpers::get_emunet(&mut guarded_tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
+    if let Err(resp) = super::check_version(&emunet, req.version) {
+        return Ok(resp);
+    }
+
+    // the background task of a working emunet writes back its own copy
+    // of the emunet, which would discard the renewal
+    match emunet.state() {
+        EmunetState::Working => {
+            return Ok(Response::fail(Error::InvalidState(format!(
+                "emunet {} is in working state, can't renew its lease",
+                req.emunet_uuid
+            ))))
+        }
+        _ => {}
+    };
+    if emunet.is_lease_expired(crate::emunet::unix_time()) {
+        return Ok(Response::fail(Error::InvalidState(format!(
+            "the lease of emunet {} has expired",
+            req.emunet_uuid
+        ))));
+    }
+
+    // the servers of the emunet return to the cluster later, which must not
+    // break the reservations that start before the new expiry
+    let lease_expiry = match crate::emunet::lease_expiry(req.lease_secs) {
+        None => {
+            return Ok(Response::fail(Error::InvalidInput(format!(
+                "lease_secs can be at most {}",
+                MAX_LEASE_SECS
+            ))))
+        }
+        Some(lease_expiry) => lease_expiry,
+    };
+    let servers: Vec<ServerInfo> = emunet
+        .servers()
+        .values()
+        .map(|server| server.server_info().clone())
+        .collect();
+    let mut returning: Vec<(u64, ServerInfo)> = helpers::get_leased_servers(&mut guarded_tran)
+        .await?
+        .into_iter()
+        .filter(|(_, s)| servers.iter().all(|server| server.node_name != s.node_name))
+        .collect();
+    returning.extend(servers.into_iter().map(|server| (lease_expiry, server)));
+    let cluster_info = helpers::get_cluster_info(&mut guarded_tran).await?;
+    if let Err(conflicts) =
+        cluster_info.check_reservations(cluster_info.free_servers(), &returning, None)
+    {
+        return Ok(Response::fail(Error::CapacityExceeded(format!(
+            "the renewed lease conflicts with reservations {}",
+            conflicts
+                .iter()
+                .map(|r| r.reservation_uuid.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))));
+    }
+
+    emunet.set_lease_expiry(Some(lease_expiry));
+    let fut = helpers::set_emunet(&mut guarded_tran, &emunet);
+    if !fut.await? {
+        return Err(Error::Conflict(emunet.emunet_uuid()));
+    }
+
+    Ok(Response::success(ResponseData { lease_expiry }))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = renew_lease(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("renew_lease".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/renew_lease", "extend the lease of an emunet")
+        .body::<Request>()
+        .reply::<ResponseData>();
+}
+
+pub async fn mnctl_network_renew(
+    user: &str,
+    emunet: &str,
+    lease_secs: u64,
+    warp_addr: &str,
+) -> Result<(), String> {
+    // query emunet_uuid
+    let req = super::list_emunet::Request {
+        user: user.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_emunet", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<HashMap<String, Uuid>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let map = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+    let emunet_uuid = map
+        .get(emunet)
+        .ok_or(format!("emunet {} does not exist", emunet))?;
+
+    let mut version = super::get_emunet_state::mnctl_emunet_version(emunet_uuid, warp_addr).await?;
+    for _ in 0..super::mnctl_util::CONFLICT_RETRIES {
+        let req = Request {
+            emunet_uuid: emunet_uuid.clone(),
+            lease_secs,
+            version: Some(version),
+        };
+        let http_resp = super::mnctl_util::http_client()
+            .post(format!("http://{}/v1/renew_lease", warp_addr))
+            .json(&req)
+            .send()
+            .await
+            .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+        let response: Response<ResponseData> = http_resp
+            .json()
+            .await
+            .map_err(|_| format!("can not parse JSON response"))?;
+
+        if response.success {
+            println!("lease expiry: {}", response.data.unwrap().lease_expiry);
+            return Ok(());
+        }
+        match response.current_version {
+            Some(current_version) if response.failed_with("VERSION_CONFLICT") => {
+                println!("{}, retrying", response.message);
+                version = current_version;
+            }
+            _ => return Err(response.message),
+        }
+    }
+
+    Err(format!("emunet {} keeps being modified, giving up", emunet))
+}
@@ -1,22 +1,26 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::Serialize;
 use warp::Filter;
 
 use super::Response;
 use crate::database::{helpers, Client, Connector};
 use crate::emunet::{Emunet, ServerInfo, User};
+use crate::errors::Error;
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "ListAllData")]
 struct Inner {
+    // the emunets are dumped as they are stored
+    #[schemars(with = "HashMap<String, HashMap<String, serde_json::Value>>")]
     users: HashMap<String, HashMap<String, Emunet>>,
     usable_servers: HashMap<String, ServerInfo>,
     garbage_servers: Vec<ServerInfo>,
     usable_ids: usize,
 }
 
-async fn list_all(client: &mut Client) -> Result<Response<Inner>, ClientError> {
+async fn list_all(client: &mut Client) -> Result<Response<Inner>, Error> {
     let mut guarded_tran = client.guarded_tran().await?;
 
     let user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
@@ -33,7 +37,7 @@ async fn list_all(client: &mut Client) -> Result<Response<Inner>, ClientError> {
         for (emunet_name, emunet_uuid) in emunet_map.into_iter() {
             let emunet = helpers::get_emunet(&mut guarded_tran, emunet_uuid.clone())
                 .await?
-                .unwrap();
+                .ok_or(Error::EmunetNotFound(emunet_uuid))?;
 
             emunets.insert(emunet_name, emunet);
         }
@@ -60,7 +64,7 @@ async fn list_all(client: &mut Client) -> Result<Response<Inner>, ClientError> {
     }))
 }
 
-async fn guard(mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
+async fn guard(mut client: Client) -> Result<super::Reply, warp::Rejection> {
     let res = list_all(&mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
@@ -75,16 +79,19 @@ async fn guard(mut client: Client) -> Result<warp::reply::Json, warp::Rejection>
 pub fn build_filter(
     connector: Connector,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
-    let connector_filter = warp::any()
-        .map(move || {
-            let clone = connector.clone();
-            clone
-        })
-        .and_then(super::get_client);
     warp::post()
         .and(warp::path("v1"))
         .and(warp::path("list_all"))
         .and(warp::path::end())
-        .and(connector_filter)
+        .and(super::auth::admin_filter(connector))
         .and_then(guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/list_all",
+        "dump the users, the emunets and the servers",
+    )
+    .admin()
+    .reply::<Inner>();
+}
@@ -1,34 +1,63 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
 
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warp::Filter;
 
-use super::Response;
-use crate::database::{helpers, Client, Connector};
+use super::auth::Caller;
+use super::{Cached, Response};
+use crate::database::{helpers, Client, Connector, Scope};
 use crate::emunet::User;
+use crate::errors::Error;
 
 type RespType = HashMap<String, Uuid>;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ListEmunetRequest")]
 pub(crate) struct Request {
     pub(crate) user: String,
 }
 
-async fn list_emunet(req: Request, client: &mut Client) -> Result<Response<RespType>, ClientError> {
-    let mut guarded_tran = client.guarded_tran().await?;
+// the user map keeps no revision, so the etag is taken from the emunets
+fn etag(uuid_map: &RespType) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    uuid_map
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+async fn list_emunet(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<RespType>, Error> {
+    if !caller.can_access(&req.user) {
+        return Ok(Response::fail(caller.deny(format!("user {}", req.user))));
+    }
+
+    let mut guarded_tran = client.scoped_tran(Scope::new().read_core()).await?;
 
     let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
     let res = user_map.remove(&req.user);
     match res {
-        None => Ok(Response::fail(format!("invalid user name {}", req.user))),
+        None => Ok(Response::fail(Error::NotFound(format!(
+            "user {}",
+            req.user
+        )))),
         Some(user) => Ok(Response::success(user.into_uuid_map())),
     }
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = list_emunet(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = list_emunet(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -44,3 +73,42 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("list_emunet".to_string(), connector, guard)
 }
+
+async fn v2_guard(
+    user: String,
+    if_none_match: Option<String>,
+    caller: Caller,
+    mut client: Client,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let res = list_emunet(Request { user }, &caller, &mut client).await;
+    match res {
+        Ok(resp) => match resp.data.as_ref().map(etag) {
+            Some(etag) => Ok(Cached::new(resp, etag, if_none_match.as_deref()).into()),
+            None => Ok(Cached::from(resp).into()),
+        },
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(Cached::from(resp).into())
+        }
+    }
+}
+
+// GET /v2/users/{user}/emunets
+pub fn build_v2_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v2" / "users" / String / "emunets"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(super::auth::caller_filter(connector))
+        .and_then(v2_guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/list_emunet", "list the emunets of a user")
+        .body::<Request>()
+        .reply::<RespType>();
+    spec.get("/v2/users/{user}/emunets", "list the emunets of a user")
+        .cached_reply::<RespType>();
+}
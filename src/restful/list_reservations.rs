@@ -0,0 +1,99 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::Reservation;
+use crate::errors::Error;
+
+// the calendar is shared by all the users, so that everyone can see
+// when the capacity is booked
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ListReservationsRequest")]
+pub(crate) struct Request {
+    pub(crate) user: Option<String>, // only list the reservations of this user
+}
+
+async fn list_reservations(
+    req: Request,
+    _caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<Vec<Reservation>>, Error> {
+    let mut tran = client.scoped_tran(Scope::new().read_core()).await?;
+
+    let cluster_info = helpers::get_cluster_info(&mut tran).await?;
+    let mut reservations: Vec<Reservation> = cluster_info
+        .reservations()
+        .iter()
+        .filter(|r| {
+            req.user
+                .as_ref()
+                .map(|user| *user == r.user)
+                .unwrap_or(true)
+        })
+        .cloned()
+        .collect();
+    reservations.sort_by(|a, b| a.start.cmp(&b.start));
+
+    Ok(Response::success(reservations))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = list_reservations(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("list_reservations".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/list_reservations", "list the reservations of a user")
+        .body::<Request>()
+        .reply::<Vec<Reservation>>();
+}
+
+pub async fn mnctl_calendar(warp_addr: &str) -> Result<(), String> {
+    let req = Request { user: None };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_reservations", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+
+    let response: Response<Vec<Reservation>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success == false {
+        Err(response.message)
+    } else {
+        for r in response.data.unwrap().iter() {
+            println!(
+                "{}: user: {}, emunet: {}, capacity: {}, window: {}-{}",
+                r.reservation_uuid, r.user, r.emunet, r.capacity, r.start, r.end
+            );
+            if let Some(e) = r.last_error.as_ref() {
+                println!("  last error: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,19 +1,23 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use warp::Filter;
 
+use super::auth::Caller;
 use super::Response;
-use crate::database::{helpers, Client, Connector};
+use crate::database::{helpers, Client, Connector, Scope};
 use crate::emunet::{Retired, User};
+use crate::errors::Error;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ListUserHistoryRequest")]
 pub(crate) struct Request {
     pub(crate) name: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "UserHistoryData")]
 pub(crate) struct Data {
     pub(crate) network_names: Vec<String>,
     pub(crate) retired_networks: Vec<Retired>,
@@ -21,14 +25,22 @@ pub(crate) struct Data {
 
 async fn list_user_history(
     req: Request,
+    caller: &Caller,
     client: &mut Client,
-) -> Result<Response<Data>, ClientError> {
-    let mut guarded_tran = client.guarded_tran().await?;
+) -> Result<Response<Data>, Error> {
+    if !caller.can_access(&req.name) {
+        return Ok(Response::fail(caller.deny(format!("user {}", req.name))));
+    }
+
+    let mut guarded_tran = client.scoped_tran(Scope::new().read_core()).await?;
 
     let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
     let user = user_map.remove(&req.name);
     if user.is_none() {
-        return Ok(Response::fail(format!("user {} does not exist", req.name)));
+        return Ok(Response::fail(Error::NotFound(format!(
+            "user {}",
+            req.name
+        ))));
     }
     let user = user.unwrap();
     let retired_networks = user.get_retired_emunets();
@@ -44,8 +56,12 @@ async fn list_user_history(
     Ok(Response::success(data))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = list_user_history(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = list_user_history(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -62,11 +78,20 @@ pub fn build_filter(
     super::filter_template("list_user_history".to_string(), connector, guard)
 }
 
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/list_user_history",
+        "list the emunets and retired topologies of a user",
+    )
+    .body::<Request>()
+    .reply::<Data>();
+}
+
 pub async fn mnctl_user_history(username: &str, warp_addr: &str) -> Result<(), String> {
     let req = Request {
         name: username.to_string(),
     };
-    let http_resp = reqwest::Client::new()
+    let http_resp = super::mnctl_util::http_client()
         .post(format!("http://{}/v1/list_user_history", warp_addr))
         .json(&req)
         .send()
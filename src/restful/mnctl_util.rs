@@ -0,0 +1,38 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref TOKEN: Mutex<Option<String>> = Mutex::new(None);
+}
+
+// set the bearer token attached to every request sent by mnctl
+pub fn set_token(token: Option<String>) {
+    *TOKEN.lock().unwrap() = token;
+}
+
+pub(crate) fn auth_header() -> Option<String> {
+    TOKEN
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|token| format!("Bearer {}", token))
+}
+
+// a HTTP client that carries the bearer token
+pub(crate) fn http_client() -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(value) = auth_header() {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&value) {
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+    }
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+// the number of times that mnctl resends a request rejected because the
+// emunet has been modified concurrently
+pub(crate) static CONFLICT_RETRIES: usize = 3;
@@ -1,22 +1,140 @@
 use std::convert::From;
 use std::future::Future;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use indradb_proto::ClientError;
+use futures::{Stream, StreamExt};
+use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use warp::http::StatusCode;
+use warp::hyper::body::Buf;
 use warp::Filter;
 
 use crate::database::{Client, Connector};
+use crate::emunet::Emunet;
+use crate::errors::Error;
+
+// the size limits of the request bodies, they are handed to the server on
+// the command line before the routes are built
+static BODY_LIMIT: AtomicU64 = AtomicU64::new(1024 * 1024);
+static TOPOLOGY_LIMIT: AtomicU64 = AtomicU64::new(1024 * 1024 * 64);
+
+pub fn set_body_limit(limit: u64) {
+    BODY_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+// the topology limit applies to the decompressed body
+pub fn set_topology_limit(limit: u64) {
+    TOPOLOGY_LIMIT.store(limit, Ordering::Relaxed);
+}
 
 fn parse_json_body<T: DeserializeOwned + Send>(
 ) -> impl warp::Filter<Extract = (T,), Error = warp::Rejection> + Clone {
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+    warp::body::content_length_limit(BODY_LIMIT.load(Ordering::Relaxed)).and(warp::body::json())
+}
+
+// read a streamed body, which may be gzip compressed, up to the limit
+async fn read_topology<T, S, B>(
+    encoding: Option<String>,
+    mut body: S,
+    limit: u64,
+) -> Result<T, warp::Rejection>
+where
+    T: DeserializeOwned + Send + 'static,
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    let too_large = || {
+        reject(Error::InvalidInput(format!(
+            "topology is larger than {} bytes",
+            limit
+        )))
+    };
+
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| {
+            reject(Error::InvalidInput(format!(
+                "can not read request body, {}",
+                e
+            )))
+        })?;
+        if buf.len() as u64 + chunk.remaining() as u64 > limit {
+            return Err(too_large());
+        }
+        while chunk.has_remaining() {
+            let len = chunk.chunk().len();
+            buf.extend_from_slice(chunk.chunk());
+            chunk.advance(len);
+        }
+    }
+
+    // decompressing and parsing thousands of devices blocks for a while
+    let res = tokio::task::spawn_blocking(move || {
+        let json = match encoding.as_deref() {
+            None | Some("identity") => buf,
+            Some("gzip") => {
+                let mut json = Vec::new();
+                flate2::read::GzDecoder::new(&buf[..])
+                    .take(limit + 1)
+                    .read_to_end(&mut json)
+                    .map_err(|e| Error::InvalidInput(format!("invalid gzip body, {}", e)))?;
+                if json.len() as u64 > limit {
+                    return Ok(None);
+                }
+                json
+            }
+            Some(encoding) => {
+                return Err(Error::InvalidInput(format!(
+                    "unsupported content encoding {}",
+                    encoding
+                )))
+            }
+        };
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| Error::InvalidInput(format!("invalid request body, {}", e)))
+    })
+    .await
+    .map_err(|e| {
+        reject(Error::InvalidInput(format!(
+            "can not decode topology, {}",
+            e
+        )))
+    })?;
+
+    match res {
+        Ok(Some(topology)) => Ok(topology),
+        Ok(None) => Err(too_large()),
+        Err(e) => Err(reject(e)),
+    }
+}
+
+// the body of the topology uploads, it can be far larger than the other
+// requests, so it may be gzip compressed and sent without a content length
+fn parse_topology_body<T: DeserializeOwned + Send + 'static>(
+) -> impl warp::Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-encoding")
+        .and(warp::body::stream())
+        .and_then(|encoding, body| {
+            read_topology(encoding, body, TOPOLOGY_LIMIT.load(Ordering::Relaxed))
+        })
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct Response<T> {
     pub(crate) success: bool,
     pub(crate) data: Option<T>,
     pub(crate) message: String,
+    // the current version of the emunet if the request carries a stale one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) current_version: Option<u64>,
+    // the code of the error that fails the request, see errors::Error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) code: Option<String>,
+    // the HTTP status of the reply, taken from the error that fails the request
+    #[serde(skip)]
+    pub(crate) status: StatusCode,
 }
 
 impl<T> Response<T> {
@@ -25,16 +143,61 @@ impl<T> Response<T> {
             success: true,
             data: Some(data),
             message: String::new(),
+            current_version: None,
+            code: None,
+            status: StatusCode::OK,
         }
     }
 
-    fn fail(err_msg: String) -> Self {
+    // fail with the code of the error, which the clients can match on
+    fn fail(e: Error) -> Self {
         Self {
             success: false,
             data: None,
-            message: err_msg,
+            message: format!("{}", e),
+            current_version: None,
+            code: Some(e.code().to_string()),
+            status: e.status(),
         }
     }
+
+    // the error aborts the request handler, the client that carries out
+    // the request is reset
+    fn fatal(e: Error) -> Self {
+        let mut resp = Self::fail(e);
+        resp.message = format!("fatal: {}", resp.message);
+        resp
+    }
+
+    fn conflict(emunet: &Emunet) -> Self {
+        let e = Error::Conflict(emunet.emunet_uuid());
+        Self {
+            success: false,
+            data: None,
+            message: format!(
+                "emunet {} has been modified, its current version is {}",
+                emunet.emunet_uuid(),
+                emunet.version_num()
+            ),
+            current_version: Some(emunet.version_num()),
+            code: Some(e.code().to_string()),
+            status: e.status(),
+        }
+    }
+
+    // whether the request fails with the error of the given code
+    pub(crate) fn failed_with(&self, code: &str) -> bool {
+        !self.success && self.code.as_ref().map(|c| c.as_str()) == Some(code)
+    }
+}
+
+// requests modifying an emunet may carry the version that the client has
+// read, the request is rejected if the topology has been rebuilt since then
+fn check_version<T>(emunet: &Emunet, version: Option<u64>) -> Result<(), Response<T>> {
+    match version {
+        Some(version) if version != emunet.version_num() => Err(Response::conflict(emunet)),
+        _ => Ok(()),
+    }
 }
 
 impl<T: Serialize> From<Response<T>> for warp::reply::Json {
@@ -43,14 +206,163 @@ impl<T: Serialize> From<Response<T>> for warp::reply::Json {
     }
 }
 
-impl From<ClientError> for Response<()> {
-    fn from(e: ClientError) -> Self {
-        Response::<()>::fail(format!("fatal: {}", e))
+// the reply of the restful apis, whose status follows the error that
+// fails the request
+pub(crate) type Reply = warp::reply::WithStatus<warp::reply::Json>;
+
+impl<T: Serialize> From<Response<T>> for Reply {
+    fn from(resp: Response<T>) -> Self {
+        let status = resp.status;
+        warp::reply::with_status(warp::reply::json(&resp), status)
     }
 }
 
+// the reply of a GET request, the etag changes whenever the data does, so
+// that the client can revalidate its copy by sending the etag back in
+// If-None-Match
+pub(crate) enum Cached<T> {
+    Data(Response<T>, Option<String>),
+    NotModified(String),
+}
+
+impl<T> From<Response<T>> for Cached<T> {
+    fn from(resp: Response<T>) -> Self {
+        Cached::Data(resp, None)
+    }
+}
+
+impl<T> Cached<T> {
+    // the reply of a request holding the data of the etag, if any
+    pub(crate) fn new(resp: Response<T>, etag: String, if_none_match: Option<&str>) -> Self {
+        if etag_matches(if_none_match, &etag) {
+            Cached::NotModified(etag)
+        } else {
+            Cached::Data(resp, Some(etag))
+        }
+    }
+
+    // the POST apis send no If-None-Match, so their data is always sent
+    pub(crate) fn into_response(self) -> Response<T> {
+        match self {
+            Cached::Data(resp, _) => resp,
+            Cached::NotModified(_) => unreachable!(),
+        }
+    }
+}
+
+// whether the If-None-Match header names the etag
+pub(crate) fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let quoted = format!("\"{}\"", etag);
+    if_none_match
+        .map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim())
+                .any(|tag| tag == "*" || tag == quoted || tag == format!("W/{}", quoted))
+        })
+        .unwrap_or(false)
+}
+
+impl<T: Serialize> From<Cached<T>> for warp::reply::Response {
+    fn from(cached: Cached<T>) -> Self {
+        use warp::http::header::{HeaderValue, CACHE_CONTROL, ETAG};
+        use warp::Reply as _;
+
+        let (mut reply, etag) = match cached {
+            Cached::Data(resp, etag) => (Reply::from(resp).into_response(), etag),
+            Cached::NotModified(etag) => (StatusCode::NOT_MODIFIED.into_response(), Some(etag)),
+        };
+        if let Some(etag) = etag {
+            let headers = reply.headers_mut();
+            headers.insert(
+                ETAG,
+                HeaderValue::from_str(&format!("\"{}\"", etag)).unwrap(),
+            );
+            // the data is private to the caller and has to be revalidated
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+        }
+        reply
+    }
+}
+
+impl From<Error> for Response<()> {
+    fn from(e: Error) -> Self {
+        Response::<()>::fatal(e)
+    }
+}
+
+// a request that is rejected before reaching its handler
+#[derive(Debug)]
+pub(crate) struct Rejected(pub(crate) Error);
+
+impl warp::reject::Reject for Rejected {}
+
+pub(crate) fn reject(e: Error) -> warp::Rejection {
+    warp::reject::custom(Rejected(e))
+}
+
+// turn the rejections into JSON responses carrying the error code
+pub async fn handle_rejection(err: warp::Rejection) -> Result<Reply, warp::Rejection> {
+    let (e, status) = if let Some(Rejected(e)) = err.find::<Rejected>() {
+        (e.clone(), e.status())
+    } else if err.is_not_found() {
+        (Error::NotFound("api".to_string()), StatusCode::NOT_FOUND)
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        let e = Error::InvalidInput(format!("invalid request body, {}", e));
+        (e, StatusCode::BAD_REQUEST)
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        let e = Error::InvalidInput("request body is too large".to_string());
+        (e, StatusCode::PAYLOAD_TOO_LARGE)
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        let e = Error::InvalidInput("unsupported content type".to_string());
+        (e, StatusCode::UNSUPPORTED_MEDIA_TYPE)
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        let e = Error::InvalidInput("method not allowed".to_string());
+        (e, StatusCode::METHOD_NOT_ALLOWED)
+    } else {
+        return Err(err);
+    };
+
+    let mut resp = Response::<()>::fail(e);
+    resp.status = status;
+    Ok(resp.into())
+}
+
 async fn get_client(connector: Connector) -> Result<Client, warp::Rejection> {
-    connector.connect().await.map_err(|_| warp::reject())
+    connector
+        .connect()
+        .await
+        .map_err(|e| reject(Error::from(e)))
+}
+
+fn client_filter(
+    connector: Connector,
+) -> impl Filter<Extract = (Client,), Error = warp::Rejection> + Send + Clone {
+    warp::any()
+        .map(move || {
+            let clone = connector.clone();
+            clone
+        })
+        .and_then(get_client)
+}
+
+// template for the apis that can be accessed without a bearer token
+fn public_filter_template<Req, F, R>(
+    api_prefix: String,
+    connector: Connector,
+    handle: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Send + Clone
+where
+    Req: DeserializeOwned + Send,
+    F: Fn(Req, Client) -> R + Send + Clone,
+    R: Future<Output = Result<Reply, warp::Rejection>> + Send,
+{
+    warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path(api_prefix))
+        .and(warp::path::end())
+        .and(parse_json_body())
+        .and(client_filter(connector))
+        .and_then(handle)
 }
 
 fn filter_template<Req, F, R>(
@@ -58,45 +370,166 @@ fn filter_template<Req, F, R>(
     connector: Connector,
     handle: F,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Send + Clone
+where
+    Req: DeserializeOwned + Send,
+    F: Fn(Req, auth::Caller, Client) -> R + Send + Clone,
+    R: Future<Output = Result<Reply, warp::Rejection>> + Send,
+{
+    warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path(api_prefix))
+        .and(warp::path::end())
+        .and(parse_json_body())
+        .and(auth::caller_filter(connector))
+        .and_then(handle)
+}
+
+// template for the cluster maintainance apis that only the admin can access
+fn admin_filter_template<Req, F, R>(
+    api_prefix: String,
+    connector: Connector,
+    handle: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Send + Clone
 where
     Req: DeserializeOwned + Send,
     F: Fn(Req, Client) -> R + Send + Clone,
-    R: Future<Output = Result<warp::reply::Json, warp::Rejection>> + Send,
+    R: Future<Output = Result<Reply, warp::Rejection>> + Send,
 {
-    let connector_filter = warp::any()
-        .map(move || {
-            let clone = connector.clone();
-            clone
-        })
-        .and_then(get_client);
     warp::post()
         .and(warp::path("v1"))
         .and(warp::path(api_prefix))
         .and(warp::path::end())
         .and(parse_json_body())
-        .and(connector_filter)
+        .and(auth::admin_filter(connector))
         .and_then(handle)
 }
 
+// template for the database administration apis under /v1/admin
+fn admin_api_template<Req, F, R>(
+    api_name: String,
+    connector: Connector,
+    handle: F,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Send + Clone
+where
+    Req: DeserializeOwned + Send,
+    F: Fn(Req, Client) -> R + Send + Clone,
+    R: Future<Output = Result<Reply, warp::Rejection>> + Send,
+{
+    warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("admin"))
+        .and(warp::path(api_name))
+        .and(warp::path::end())
+        .and(parse_json_body())
+        .and(auth::admin_filter(connector))
+        .and_then(handle)
+}
+
+// every route served by mocknet_server, each of them is described in
+// openapi::document
+pub fn build_routes(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    let routes = user_registration::build_filter(connector.clone());
+    let routes = routes.or(emunet_creation::build_filter(connector.clone()));
+    let routes = routes.or(list_all::build_filter(connector.clone()));
+    let routes = routes.or(list_emunet::build_filter(connector.clone()));
+    let routes = routes.or(user_deletion::build_filter(connector.clone()));
+    let routes = routes.or(emunet_init::build_filter(connector.clone()));
+    let routes = routes.or(emunet_deletion::build_filter(connector.clone()));
+    let routes = routes.or(lease_renewal::build_filter(connector.clone()));
+    let routes = routes.or(reservation_creation::build_filter(connector.clone()));
+    let routes = routes.or(reservation_cancellation::build_filter(connector.clone()));
+    let routes = routes.or(list_reservations::build_filter(connector.clone()));
+    let routes = routes.or(get_emunet_info::build_filter(connector.clone()));
+    let routes = routes.or(get_emunet_state::build_filter(connector.clone()));
+    let routes = routes.or(query_devices::build_filter(connector.clone()));
+    let routes = routes.or(emunet_update::build_filter(connector.clone()));
+    let routes = routes.or(add_nodes::build_filter(connector.clone()));
+    let routes = routes.or(clear_garbage_servers::build_filter(connector.clone()));
+    let routes = routes.or(list_user_history::build_filter(connector.clone()));
+    let routes = routes.or(user_info::build_filter(connector.clone()));
+    let routes = routes.or(set_quota::build_filter(connector.clone()));
+    let routes = routes.or(route_command::build_filter(connector.clone()));
+    let routes = routes.or(execute_command::build_filter(connector.clone()));
+    let routes = routes.or(exec_session::build_filter(connector.clone()));
+    let routes = routes.or(execute_batch::build_filter(connector.clone()));
+    let routes = routes.or(job_query::build_filter(connector.clone()));
+    let routes = routes.or(job_cancellation::build_filter(connector.clone()));
+    let routes = routes.or(ticket_query::build_filter(connector.clone()));
+    let routes = routes.or(ticket_cancellation::build_filter(connector.clone()));
+    let routes = routes.or(emunet_events::build_filter(connector.clone()));
+    let routes = routes.or(schema_migration::build_filter(connector.clone()));
+    let routes = routes.or(consistency_check::build_filter(connector.clone()));
+    let routes = routes.or(state_export::build_filter(connector.clone()));
+    let routes = routes.or(state_import::build_filter(connector.clone()));
+    let routes = routes.or(topology_generation::build_filter(connector.clone()));
+    let routes = routes.or(topology_validation::build_filter(connector.clone()));
+
+    // the resource-oriented api, which shares the handlers with v1
+    let routes = routes.or(list_emunet::build_v2_filter(connector.clone()));
+    let routes = routes.or(get_emunet_info::build_v2_filter(connector.clone()));
+    let routes = routes.or(get_emunet_info::build_v2_device_filter(connector.clone()));
+    let routes = routes.or(emunet_deletion::build_v2_filter(connector.clone()));
+    let routes = routes.or(emunet_init::build_v2_filter(connector.clone()));
+    let routes = routes.or(emunet_update::build_v2_filter(connector));
+
+    routes.or(openapi::build_filter())
+}
+
+pub mod auth;
+
 pub mod emunet_creation;
 pub mod emunet_deletion;
+pub mod emunet_events;
 pub mod emunet_init;
 pub mod emunet_update;
+pub mod exec_session;
+pub mod execute_batch;
 pub mod execute_command;
 pub mod get_emunet_info;
 pub mod get_emunet_state;
+pub mod lease_renewal;
 pub mod list_all;
 pub mod list_emunet;
+pub mod list_reservations;
 pub mod list_user_history;
+pub mod query_devices;
+pub mod reservation_cancellation;
+pub mod reservation_creation;
 pub mod route_command;
 pub mod user_deletion;
+pub mod user_info;
 pub mod user_registration;
 
+// asynchronous jobs
+pub mod job_cancellation;
+pub mod job_query;
+pub(crate) mod jobs;
+
+// queued emunet creations
+pub mod ticket_cancellation;
+pub mod ticket_query;
+
 pub mod server_ping;
 
 // maintainance utilities
 pub mod add_nodes;
 pub mod clear_garbage_servers;
+pub mod set_quota;
+
+// synthetic topologies and dry runs
+pub mod topology_generation;
+pub mod topology_validation;
+
+// database administration
+pub mod consistency_check;
+pub mod schema_migration;
+pub mod state_export;
+pub mod state_import;
+
+// the OpenAPI document of the routes
+pub mod openapi;
 
 // mnctl utilities
 pub mod mnctl_util;
@@ -0,0 +1,410 @@
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use warp::Filter;
+
+use super::Response;
+
+lazy_static! {
+    static ref DOCUMENT: Value = document();
+}
+
+// Spec collects the routes of the restful api into an OpenAPI 3 document,
+// every handler module describes its routes with the same types that its
+// handler parses and replies with
+pub(crate) struct Spec {
+    gen: SchemaGenerator,
+    paths: BTreeMap<String, Map<String, Value>>,
+}
+
+// an operation under construction, it is added to the spec once its
+// reply is described
+pub(crate) struct Operation<'a> {
+    spec: &'a mut Spec,
+    path: String,
+    method: &'static str,
+    op: Map<String, Value>,
+}
+
+impl Spec {
+    fn new() -> Self {
+        let settings = SchemaSettings::openapi3().with(|s| {
+            s.option_nullable = true;
+            s.option_add_null_type = false;
+        });
+        Self {
+            gen: settings.into_generator(),
+            paths: BTreeMap::new(),
+        }
+    }
+
+    fn schema_of<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.gen.subschema_for::<T>()).unwrap()
+    }
+
+    // path parameters are written as {name}, names ending with uuid are
+    // uuids and names ending with id are integers
+    pub(crate) fn route(&mut self, method: &'static str, path: &str, summary: &str) -> Operation {
+        let parameters: Vec<Value> = path
+            .split('/')
+            .filter(|seg| seg.starts_with('{') && seg.ends_with('}'))
+            .map(|seg| {
+                let name = &seg[1..seg.len() - 1];
+                let schema = if name.ends_with("uuid") {
+                    json!({"type": "string", "format": "uuid"})
+                } else if name.ends_with("id") {
+                    json!({"type": "integer", "format": "uint64", "minimum": 0})
+                } else {
+                    json!({"type": "string"})
+                };
+                json!({"name": name, "in": "path", "required": true, "schema": schema})
+            })
+            .collect();
+
+        let mut op = Map::new();
+        op.insert("summary".to_string(), Value::from(summary));
+        if parameters.len() > 0 {
+            op.insert("parameters".to_string(), Value::from(parameters));
+        }
+        Operation {
+            spec: self,
+            path: path.to_string(),
+            method,
+            op,
+        }
+    }
+
+    pub(crate) fn get(&mut self, path: &str, summary: &str) -> Operation {
+        self.route("get", path, summary)
+    }
+
+    pub(crate) fn post(&mut self, path: &str, summary: &str) -> Operation {
+        self.route("post", path, summary)
+    }
+
+    pub(crate) fn put(&mut self, path: &str, summary: &str) -> Operation {
+        self.route("put", path, summary)
+    }
+
+    pub(crate) fn delete(&mut self, path: &str, summary: &str) -> Operation {
+        self.route("delete", path, summary)
+    }
+
+    fn into_document(mut self) -> Value {
+        let error_schema = self.schema_of::<Response<()>>();
+        let schemas: Map<String, Value> = self
+            .gen
+            .definitions()
+            .iter()
+            .map(|(name, schema)| (name.clone(), serde_json::to_value(schema).unwrap()))
+            .collect();
+
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "mocknet",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "Failed requests are answered with success set to false, \
+                    a message and an error code, the HTTP status follows the code.",
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "responses": {
+                    "Error": {
+                        "description": "the request fails",
+                        "content": {"application/json": {"schema": error_schema}},
+                    },
+                },
+                "securitySchemes": {
+                    "bearer": {"type": "http", "scheme": "bearer"},
+                },
+            },
+            "security": [{"bearer": []}],
+        })
+    }
+}
+
+impl<'a> Operation<'a> {
+    // the JSON body of the request
+    pub(crate) fn body<T: JsonSchema>(mut self) -> Self {
+        let schema = self.spec.schema_of::<T>();
+        self.op.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": {"application/json": {"schema": schema}},
+            }),
+        );
+        self
+    }
+
+    // a JSON body that may be gzip compressed and streamed without a
+    // content length
+    pub(crate) fn topology_body<T: JsonSchema>(mut self) -> Self {
+        self = self.body::<T>();
+        let mut parameters = match self.op.remove("parameters") {
+            Some(Value::Array(parameters)) => parameters,
+            _ => Vec::new(),
+        };
+        parameters.push(json!({
+            "name": "Content-Encoding",
+            "in": "header",
+            "required": false,
+            "schema": {"type": "string", "enum": ["gzip", "identity"]},
+        }));
+        self.op
+            .insert("parameters".to_string(), Value::from(parameters));
+        self
+    }
+
+    // the fields of T are passed in the query string
+    pub(crate) fn query<T: JsonSchema>(mut self) -> Self {
+        let schema = self.spec.gen.root_schema_for::<T>().schema;
+        let object = schema.object.unwrap_or_default();
+        let mut parameters = match self.op.remove("parameters") {
+            Some(Value::Array(parameters)) => parameters,
+            _ => Vec::new(),
+        };
+        for (name, field) in object.properties.iter() {
+            let field = match field {
+                Schema::Object(field) => {
+                    let mut field = field.clone();
+                    field.extensions.remove("nullable");
+                    field
+                }
+                Schema::Bool(_) => continue,
+            };
+            parameters.push(json!({
+                "name": name,
+                "in": "query",
+                "required": object.required.contains(name),
+                "schema": field,
+            }));
+        }
+        self.op
+            .insert("parameters".to_string(), Value::from(parameters));
+        self
+    }
+
+    // the route can be accessed without a bearer token
+    pub(crate) fn public(mut self) -> Self {
+        self.op.insert("security".to_string(), json!([]));
+        self
+    }
+
+    // only the admin token is accepted
+    pub(crate) fn admin(mut self) -> Self {
+        self.op.insert("tags".to_string(), json!(["admin"]));
+        self
+    }
+
+    // the route replies with Response<T>
+    pub(crate) fn reply<T: JsonSchema>(mut self) {
+        let schema = self.spec.schema_of::<Response<T>>();
+        self.op.insert(
+            "responses".to_string(),
+            json!({
+                "200": {
+                    "description": "the request succeeds",
+                    "content": {"application/json": {"schema": schema}},
+                },
+                "default": {"$ref": "#/components/responses/Error"},
+            }),
+        );
+        self.finish();
+    }
+
+    // the route replies with Response<T> and its etag, a request that
+    // sends the current etag in If-None-Match is answered with 304
+    pub(crate) fn cached_reply<T: JsonSchema>(mut self) {
+        let mut parameters = match self.op.remove("parameters") {
+            Some(Value::Array(parameters)) => parameters,
+            _ => Vec::new(),
+        };
+        parameters.push(json!({
+            "name": "If-None-Match",
+            "in": "header",
+            "required": false,
+            "schema": {"type": "string"},
+        }));
+        self.op
+            .insert("parameters".to_string(), Value::from(parameters));
+
+        let schema = self.spec.schema_of::<Response<T>>();
+        self.op.insert(
+            "responses".to_string(),
+            json!({
+                "200": {
+                    "description": "the request succeeds",
+                    "headers": {"ETag": {"schema": {"type": "string"}}},
+                    "content": {"application/json": {"schema": schema}},
+                },
+                "304": {
+                    "description": "the data held by the client is current",
+                    "headers": {"ETag": {"schema": {"type": "string"}}},
+                },
+                "default": {"$ref": "#/components/responses/Error"},
+            }),
+        );
+        self.finish();
+    }
+
+    // the route replies with T itself instead of Response<T>, e.g. a stream
+    // of server-sent events or the messages of a websocket
+    pub(crate) fn raw_reply<T: JsonSchema>(
+        mut self,
+        status: &str,
+        media_type: &str,
+        description: &str,
+    ) {
+        let schema = self.spec.schema_of::<T>();
+        self.op.insert(
+            "responses".to_string(),
+            json!({
+                status: {
+                    "description": description,
+                    "content": {media_type: {"schema": schema}},
+                },
+                "default": {"$ref": "#/components/responses/Error"},
+            }),
+        );
+        self.finish();
+    }
+
+    fn finish(self) {
+        let methods = self.spec.paths.entry(self.path).or_insert(Map::new());
+        assert!(methods
+            .insert(self.method.to_string(), Value::Object(self.op))
+            .is_none());
+    }
+}
+
+// the document of every route mounted by mocknet_server
+pub(crate) fn document() -> Value {
+    let mut spec = Spec::new();
+
+    super::user_registration::describe(&mut spec);
+    super::emunet_creation::describe(&mut spec);
+    super::list_all::describe(&mut spec);
+    super::list_emunet::describe(&mut spec);
+    super::user_deletion::describe(&mut spec);
+    super::emunet_init::describe(&mut spec);
+    super::emunet_deletion::describe(&mut spec);
+    super::lease_renewal::describe(&mut spec);
+    super::reservation_creation::describe(&mut spec);
+    super::reservation_cancellation::describe(&mut spec);
+    super::list_reservations::describe(&mut spec);
+    super::get_emunet_info::describe(&mut spec);
+    super::get_emunet_state::describe(&mut spec);
+    super::query_devices::describe(&mut spec);
+    super::emunet_update::describe(&mut spec);
+    super::add_nodes::describe(&mut spec);
+    super::clear_garbage_servers::describe(&mut spec);
+    super::list_user_history::describe(&mut spec);
+    super::user_info::describe(&mut spec);
+    super::set_quota::describe(&mut spec);
+    super::route_command::describe(&mut spec);
+    super::execute_command::describe(&mut spec);
+    super::exec_session::describe(&mut spec);
+    super::execute_batch::describe(&mut spec);
+    super::job_query::describe(&mut spec);
+    super::job_cancellation::describe(&mut spec);
+    super::ticket_query::describe(&mut spec);
+    super::ticket_cancellation::describe(&mut spec);
+    super::emunet_events::describe(&mut spec);
+    super::schema_migration::describe(&mut spec);
+    super::consistency_check::describe(&mut spec);
+    super::state_export::describe(&mut spec);
+    super::state_import::describe(&mut spec);
+    super::topology_generation::describe(&mut spec);
+    super::topology_validation::describe(&mut spec);
+    describe(&mut spec);
+
+    spec.into_document()
+}
+
+fn describe(spec: &mut Spec) {
+    spec.get("/v1/openapi.json", "this document")
+        .public()
+        .raw_reply::<Value>("200", "application/json", "the OpenAPI document");
+}
+
+pub fn build_filter(
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v1" / "openapi.json"))
+        .map(|| warp::reply::json(&*DOCUMENT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{new_connector, StorageConfig};
+    use warp::http::StatusCode;
+
+    // the document that the clients are generated from, regenerate it with
+    // MOCKNET_UPDATE_OPENAPI=1 cargo test openapi
+    const DOCUMENT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/deploy/openapi.json");
+
+    #[test]
+    fn openapi_document_matches_handlers() {
+        let generated = document();
+        if std::env::var("MOCKNET_UPDATE_OPENAPI").is_ok() {
+            let s = serde_json::to_string_pretty(&generated).unwrap();
+            std::fs::write(DOCUMENT_PATH, s + "\n").unwrap();
+            return;
+        }
+
+        let stored: Value =
+            serde_json::from_str(&std::fs::read_to_string(DOCUMENT_PATH).unwrap()).unwrap();
+        assert!(
+            stored == generated,
+            "deploy/openapi.json is out of date with the handlers, regenerate it with \
+             MOCKNET_UPDATE_OPENAPI=1 cargo test openapi"
+        );
+    }
+
+    // the routes are probed without a bearer token and with an empty body,
+    // so that no handler is reached and only an unmounted route is not found
+    #[tokio::test]
+    async fn documented_routes_are_mounted() {
+        let connector = new_connector(StorageConfig::Memory).await.unwrap();
+        let routes = super::super::build_routes(connector);
+
+        let document = document();
+        for (path, methods) in document["paths"].as_object().unwrap() {
+            let uri: Vec<String> = path
+                .split('/')
+                .map(|seg| match seg.strip_prefix('{') {
+                    Some(name) if name.ends_with("uuid}") => uuid::Uuid::nil().to_string(),
+                    Some(name) if name.ends_with("id}") => "0".to_string(),
+                    Some(_) => "name".to_string(),
+                    None => seg.to_string(),
+                })
+                .collect();
+            let uri = uri.join("/");
+
+            for method in methods.as_object().unwrap().keys() {
+                let method = method.to_uppercase();
+                let resp = warp::test::request()
+                    .method(&method)
+                    .path(&uri)
+                    .body("")
+                    .reply(&routes)
+                    .await;
+                assert!(
+                    resp.status() != StatusCode::NOT_FOUND
+                        && resp.status() != StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {} is documented but not mounted",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::Emunet;
+use crate::errors::Error;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "QueryDevicesRequest")]
+pub(crate) struct Request {
+    pub(crate) emunet_uuid: Uuid,
+    pub(crate) k8s_node: Option<String>, // the devices placed on the k8s node
+    pub(crate) neighbors_of: Option<u64>, // the devices linked to the device
+}
+
+// emunets stored in the old format keep their devices in the emunet vertex,
+// the query is answered from the loaded emunet instead
+fn query_loaded(emunet: &Emunet, req: &Request) -> Result<Vec<u64>, Error> {
+    let devices = emunet.devices();
+    let mut res: Vec<u64> = match req.neighbors_of {
+        None => devices.keys().map(|id| *id).collect(),
+        Some(dev_id) => devices
+            .get(&dev_id)
+            .ok_or(Error::NotFound(format!("device {}", dev_id)))?
+            .links()
+            .iter()
+            .map(|link| link.link_id().1)
+            .collect(),
+    };
+    if let Some(k8s_node) = req.k8s_node.as_ref() {
+        // the server keeps the devices placed on it
+        match emunet.servers().get(k8s_node) {
+            None => res.clear(),
+            Some(server) => {
+                let on_server = server.devs();
+                res.retain(|id| on_server.contains(id));
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+async fn query_devices(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<Vec<u64>>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
+
+    let emunet = match helpers::get_emunet_header(&mut tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
+    if emunet.devices().len() > 0 {
+        return Ok(match query_loaded(&emunet, &req) {
+            Ok(res) => Response::success(res),
+            Err(e) => Response::fail(e),
+        });
+    }
+
+    // both queries run in the database without loading the whole emunet
+    let neighbors = match req.neighbors_of {
+        None => None,
+        Some(dev_id) => {
+            let dev_vid = match emunet.device_vertex(dev_id) {
+                None => {
+                    return Ok(Response::fail(Error::NotFound(format!(
+                        "device {}",
+                        dev_id
+                    ))))
+                }
+                Some(vid) => vid,
+            };
+            Some(helpers::get_neighbors(&mut tran, dev_vid).await?)
+        }
+    };
+    let res = match (req.k8s_node, neighbors) {
+        (None, None) => emunet.stored_dev_idxs(),
+        (None, Some(neighbors)) => neighbors,
+        (Some(k8s_node), neighbors) => {
+            let on_server = match emunet.node_vertex(&k8s_node) {
+                None => Vec::new(),
+                Some(node_vid) => helpers::get_devices_on_node(&mut tran, node_vid).await?,
+            };
+            match neighbors {
+                None => on_server,
+                Some(neighbors) => on_server
+                    .into_iter()
+                    .filter(|id| neighbors.contains(id))
+                    .collect(),
+            }
+        }
+    };
+
+    Ok(Response::success(res))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = query_devices(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("query_devices".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/query_devices",
+        "find the devices on a k8s node or next to a device",
+    )
+    .body::<Request>()
+    .reply::<Vec<u64>>();
+}
@@ -0,0 +1,78 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector};
+use crate::errors::Error;
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "CancelReservationRequest")]
+struct Request {
+    reservation_uuid: Uuid,
+}
+
+async fn cancel_reservation(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<()>, Error> {
+    let mut tran = client.guarded_tran().await?;
+
+    let mut cluster_info = helpers::get_cluster_info(&mut tran).await?;
+    let owner = match cluster_info
+        .reservations()
+        .iter()
+        .find(|r| r.reservation_uuid == req.reservation_uuid)
+    {
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "reservation {}",
+                req.reservation_uuid
+            ))))
+        }
+        Some(r) => r.user.clone(),
+    };
+    if !caller.can_access(&owner) {
+        return Ok(Response::fail(
+            caller.deny(format!("reservation {}", req.reservation_uuid)),
+        ));
+    }
+
+    cluster_info.remove_reservation(&req.reservation_uuid);
+    helpers::set_cluster_info(&mut tran, cluster_info).await?;
+    // the capacity held back for the reservation may serve the ticket queue
+    super::emunet_creation::capacity_returned();
+
+    Ok(Response::success(()))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = cancel_reservation(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("cancel_reservation".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/cancel_reservation", "cancel a reservation")
+        .body::<Request>()
+        .reply::<()>();
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector};
+use crate::emunet::{Reservation, User};
+use crate::errors::Error;
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "ReserveCapacityRequest")]
+struct Request {
+    user: String,
+    emunet: String, // name of the emunet created when the window starts
+    capacity: u64,
+    start: u64, // start of the window in seconds since the unix epoch
+    end: u64,   // end of the window, when the lease of the emunet expires
+}
+
+async fn reserve_capacity(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<Uuid>, Error> {
+    if !caller.can_access(&req.user) {
+        return Ok(Response::fail(caller.deny(format!("user {}", req.user))));
+    }
+    if req.start < crate::emunet::unix_time() || req.end <= req.start {
+        return Ok(Response::fail(Error::InvalidInput(format!(
+            "invalid reservation window {}-{}",
+            req.start, req.end
+        ))));
+    }
+
+    let mut tran = client.guarded_tran().await?;
+
+    let user_map: HashMap<String, User> = helpers::get_user_map(&mut tran).await?;
+    if user_map.get(&req.user).is_none() {
+        return Ok(Response::fail(Error::NotFound(format!(
+            "user {}",
+            req.user
+        ))));
+    }
+    drop(user_map);
+
+    let reservation = Reservation {
+        reservation_uuid: indradb::util::generate_uuid_v1(),
+        user: req.user,
+        emunet: req.emunet,
+        capacity: req.capacity,
+        start: req.start,
+        end: req.end,
+        last_error: None,
+    };
+
+    let returning = helpers::get_leased_servers(&mut tran).await?;
+    let mut cluster_info = helpers::get_cluster_info(&mut tran).await?;
+
+    // two reservations of the same user can not create the same emunet
+    let duplicated = cluster_info.reservations().iter().find(|r| {
+        r.user == reservation.user
+            && r.emunet == reservation.emunet
+            && r.start < reservation.end
+            && reservation.start < r.end
+    });
+    if let Some(r) = duplicated {
+        return Ok(Response::fail(Error::AlreadyExists(format!(
+            "emunet {} is already reserved by {} ({}-{})",
+            r.emunet, r.reservation_uuid, r.start, r.end
+        ))));
+    }
+
+    let reservation_uuid = reservation.reservation_uuid.clone();
+    if let Err(conflicts) = cluster_info.add_reservation(reservation, &returning) {
+        return Ok(Response::fail(Error::CapacityExceeded(format!(
+            "not enough capacity during the window, conflicting reservations: {}",
+            conflicts
+                .iter()
+                .filter(|r| r.reservation_uuid != reservation_uuid)
+                .map(|r| format!(
+                    "{} of user {} ({}-{}, capacity {})",
+                    r.reservation_uuid, r.user, r.start, r.end, r.capacity
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))));
+    }
+    helpers::set_cluster_info(&mut tran, cluster_info).await?;
+
+    Ok(Response::success(reservation_uuid))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = reserve_capacity(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("reserve_capacity".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/reserve_capacity",
+        "book capacity for a future time window",
+    )
+    .body::<Request>()
+    .reply::<Uuid>();
+}
@@ -1,20 +1,24 @@
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warp::Filter;
 
+use super::auth::Caller;
 use super::Response;
-use crate::database::{helpers, Client, Connector};
+use crate::database::{helpers, Client, Connector, Scope};
 use crate::emunet::EmunetState;
+use crate::errors::Error;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "RouteCommandRequest")]
 pub(crate) struct Request {
     pub(crate) emunet_uuid: Uuid,
     pub(crate) path: Vec<u64>,
     pub(crate) is_add: bool,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "RouteCommandData")]
 pub(crate) struct RespData {
     pub(crate) forward_route_commands: Vec<(u64, String)>,
     pub(crate) backward_route_commands: Vec<(u64, String)>,
@@ -25,31 +29,40 @@ pub(crate) struct RespData {
     pub(crate) api_server_addr: String,
 }
 
-// this must only be called by the mnctl_network_connect, which provides
-// three preconditions that always holds.
-// 1. req.path contains a valid path inside the specified emunet.
-// 2. req.path is longer than 2.
-// 3. the emunet is available
+// the path comes from the caller, it is checked against the emunet before
+// the route commands are generated
 async fn route_command(
     req: Request,
+    caller: &Caller,
     client: &mut Client,
-) -> Result<Response<RespData>, ClientError> {
-    let mut tran = client.guarded_tran().await?;
+) -> Result<Response<RespData>, Error> {
+    let mut tran = client
+        .scoped_tran(Scope::new().read_emunet(req.emunet_uuid.clone()))
+        .await?;
 
-    let emunet = helpers::get_emunet(&mut tran, req.emunet_uuid.clone())
-        .await?
-        .unwrap();
+    let emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
+        Some(emunet) => emunet,
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
     match emunet.state() {
         EmunetState::Normal => {}
         _ => {
-            return Ok(Response::fail(format!(
+            return Ok(Response::fail(Error::InvalidState(format!(
                 "emunet {} is not in normal state",
                 req.emunet_uuid
-            )))
+            ))))
         }
     };
 
     let path = req.path;
+    if let Err(s) = emunet.check_path(&path[..]) {
+        return Ok(Response::fail(Error::InvalidInput(s)));
+    }
     let (forward_route_commands, (dest_idx, dest_ip)) =
         emunet.release_route_command(&path[..], req.is_add);
 
@@ -68,8 +81,12 @@ async fn route_command(
     }))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = route_command(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = route_command(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -85,3 +102,12 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("route_command".to_string(), connector, guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/route_command",
+        "install or remove the routes along a path",
+    )
+    .body::<Request>()
+    .reply::<RespData>();
+}
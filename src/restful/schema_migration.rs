@@ -0,0 +1,87 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+use super::Response;
+use crate::database::migration::{self, MigrationReport};
+use crate::database::{Client, Connector};
+use crate::errors::Error;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "MigrateRequest")]
+struct Request {
+    // only report the pending migration steps
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn migrate(req: Request, client: &mut Client) -> Result<Response<MigrationReport>, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    match migration::run_migration(&mut guarded_tran, req.dry_run).await? {
+        Ok(report) => Ok(Response::success(report)),
+        Err(s) => Ok(Response::fail(Error::InvalidState(s))),
+    }
+}
+
+async fn guard(req: Request, mut client: Client) -> Result<super::Reply, warp::Rejection> {
+    let res = migrate(req, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::admin_api_template("migrate".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/admin/migrate",
+        "migrate the stored records to the current schema",
+    )
+    .admin()
+    .body::<Request>()
+    .reply::<MigrationReport>();
+}
+
+pub async fn mnctl_admin_migrate(dry_run: bool, warp_addr: &str) -> Result<(), String> {
+    let req = Request { dry_run };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/admin/migrate", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<MigrationReport> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success == false {
+        return Err(response.message);
+    }
+    let report = response.data.unwrap();
+    println!(
+        "schema version: {}, target version: {}",
+        report.schema_version, report.target_version
+    );
+    if report.steps.len() == 0 {
+        println!("the database is up to date");
+    }
+    for step in report.steps.iter() {
+        if report.applied {
+            println!("applied step {}", step);
+        } else {
+            println!("pending step {}", step);
+        }
+    }
+    Ok(())
+}
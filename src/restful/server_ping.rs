@@ -1,16 +1,16 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
 use serde::Deserialize;
 use warp::Filter;
 
 use super::Response;
+use crate::errors::Error;
 #[derive(Deserialize)]
 struct Request {
     server_ips: Vec<String>,
 }
 
-async fn server_ping(req: Request) -> Result<Response<HashMap<String, bool>>, ClientError> {
+async fn server_ping(req: Request) -> Result<Response<HashMap<String, bool>>, Error> {
     let res: Option<Vec<std::net::Ipv4Addr>> = req
         .server_ips
         .into_iter()
@@ -20,7 +20,9 @@ async fn server_ping(req: Request) -> Result<Response<HashMap<String, bool>>, Cl
     let server_ips = match res {
         Some(inner) => inner,
         None => {
-            return Ok(Response::fail(format!("invalid IPv4 address")));
+            return Ok(Response::fail(Error::InvalidInput(format!(
+                "invalid IPv4 address"
+            ))));
         }
     };
 
@@ -32,7 +34,7 @@ async fn server_ping(req: Request) -> Result<Response<HashMap<String, bool>>, Cl
     Ok(Response::success(res))
 }
 
-async fn guard(req: Request) -> Result<warp::reply::Json, warp::Rejection> {
+async fn guard(req: Request) -> Result<super::Reply, warp::Rejection> {
     let res = server_ping(req).await;
     match res {
         Ok(resp) => Ok(resp.into()),
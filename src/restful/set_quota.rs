@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use warp::Filter;
+
+use super::Response;
+use crate::database::{helpers, Client, Connector};
+use crate::emunet::{Quota, User};
+use crate::errors::Error;
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "SetQuotaRequest")]
+struct Request {
+    name: String,
+    quota: Quota,
+}
+
+async fn set_quota(req: Request, client: &mut Client) -> Result<Response<()>, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    let user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
+    match user_map.get(&req.name) {
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "user {}",
+                req.name
+            ))))
+        }
+        Some(user) => user.set_quota(req.quota),
+    };
+
+    // the resources that the user already holds are not reclaimed, the
+    // new quota only blocks further allocations
+    helpers::set_user_map(&mut guarded_tran, user_map).await?;
+
+    Ok(Response::success(()))
+}
+
+async fn guard(req: Request, mut client: Client) -> Result<super::Reply, warp::Rejection> {
+    let res = set_quota(req, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::admin_filter_template("set_quota".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/set_quota", "set the quota of a user")
+        .admin()
+        .body::<Request>()
+        .reply::<()>();
+}
@@ -0,0 +1,71 @@
+use warp::Filter;
+
+use super::Response;
+use crate::database::archive::{self, Archive};
+use crate::database::{Client, Connector};
+use crate::errors::Error;
+
+async fn export(client: &mut Client) -> Result<Response<Archive>, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    let archive = archive::export(&mut guarded_tran).await?;
+    Ok(Response::success(archive))
+}
+
+async fn guard(mut client: Client) -> Result<super::Reply, warp::Rejection> {
+    let res = export(&mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("admin"))
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(super::auth::admin_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/admin/export", "dump the whole control-plane state")
+        .admin()
+        .reply::<Archive>();
+}
+
+pub async fn mnctl_admin_backup(file_path: &str, warp_addr: &str) -> Result<(), String> {
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/admin/export", warp_addr))
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<Archive> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success == false {
+        return Err(response.message);
+    }
+    let archive = response.data.unwrap();
+    let json_str = serde_json::to_string(&archive).unwrap();
+    tokio::fs::write(file_path, json_str)
+        .await
+        .map_err(|e| format!("can not write {}: {}", file_path, e))?;
+    println!(
+        "saved {} emunets with schema version {} to {}",
+        archive.emunets.len(),
+        archive.schema_version,
+        file_path
+    );
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use warp::Filter;
+
+use super::Response;
+use crate::database::archive::{self, Archive};
+use crate::database::fsck::Violation;
+use crate::database::{Client, Connector};
+use crate::errors::Error;
+
+// an archive carries every emunet, it is far larger than the other requests
+static ARCHIVE_SIZE_LIMIT: u64 = 1024 * 1024 * 256;
+
+async fn import(req: Archive, client: &mut Client) -> Result<Response<Vec<Violation>>, Error> {
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    match archive::import(&mut guarded_tran, req).await? {
+        Ok(violations) => {
+            // the imported cluster may have capacity for the waiting tickets
+            super::emunet_creation::capacity_returned();
+            Ok(Response::success(violations))
+        }
+        Err(s) => Ok(Response::fail(Error::InvalidInput(s))),
+    }
+}
+
+async fn guard(req: Archive, mut client: Client) -> Result<super::Reply, warp::Rejection> {
+    let res = import(req, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::post()
+        .and(warp::path("v1"))
+        .and(warp::path("admin"))
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(ARCHIVE_SIZE_LIMIT))
+        .and(warp::body::json())
+        .and(super::auth::admin_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/admin/import",
+        "replace the whole control-plane state and check the result",
+    )
+    .admin()
+    .body::<Archive>()
+    .reply::<Vec<Violation>>();
+}
+
+pub async fn mnctl_admin_restore(file_path: &str, warp_addr: &str) -> Result<(), String> {
+    let json_str = tokio::fs::read_to_string(file_path)
+        .await
+        .map_err(|e| format!("can not read {}: {}", file_path, e))?;
+    let archive: Archive =
+        serde_json::from_str(&json_str).map_err(|_| format!("invalid archive {}", file_path))?;
+
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/admin/import", warp_addr))
+        .json(&archive)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<Vec<Violation>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success == false {
+        return Err(response.message);
+    }
+    println!(
+        "restored {} emunets from {}",
+        archive.emunets.len(),
+        file_path
+    );
+    // the archive is imported even if the restored state is inconsistent
+    for violation in response.data.unwrap().iter() {
+        println!("{}", violation.message);
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector};
+use crate::errors::Error;
+
+async fn cancel_ticket(
+    ticket_uuid: Uuid,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<()>, Error> {
+    let mut tran = client.guarded_tran().await?;
+
+    let mut ticket_queue = helpers::get_ticket_queue(&mut tran).await?;
+    match ticket_queue.get(&ticket_uuid) {
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "ticket {}",
+                ticket_uuid
+            ))))
+        }
+        Some(ticket) if !caller.can_access(&ticket.user) => {
+            return Ok(Response::fail(
+                caller.deny(format!("ticket {}", ticket_uuid)),
+            ));
+        }
+        Some(ticket) if !ticket.is_waiting() => {
+            return Ok(Response::fail(Error::InvalidState(format!(
+                "ticket {} is not waiting",
+                ticket_uuid
+            ))));
+        }
+        Some(_) => {}
+    };
+
+    ticket_queue.remove(&ticket_uuid);
+    helpers::set_ticket_queue(&mut tran, ticket_queue).await?;
+    // the tickets behind the cancelled one may fit now
+    super::emunet_creation::capacity_returned();
+
+    Ok(Response::success(()))
+}
+
+async fn guard(
+    ticket_uuid: Uuid,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = cancel_ticket(ticket_uuid, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::post()
+        .and(warp::path!("v1" / "tickets" / Uuid / "cancel"))
+        .and(super::auth::caller_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/tickets/{ticket_uuid}/cancel",
+        "cancel a queued emunet creation",
+    )
+    .reply::<()>();
+}
@@ -0,0 +1,77 @@
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::emunet_creation::TicketInfo;
+use super::Response;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::TicketState;
+use crate::errors::Error;
+
+async fn get_ticket(
+    ticket_uuid: Uuid,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<TicketInfo>, Error> {
+    let mut tran = client.scoped_tran(Scope::new().read_core()).await?;
+
+    let ticket_queue = helpers::get_ticket_queue(&mut tran).await?;
+    let ticket = match ticket_queue.get(&ticket_uuid) {
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "ticket {}",
+                ticket_uuid
+            ))))
+        }
+        Some(ticket) => ticket,
+    };
+    if !caller.can_access(&ticket.user) {
+        return Ok(Response::fail(
+            caller.deny(format!("ticket {}", ticket_uuid)),
+        ));
+    }
+
+    let emunet_uuid = match ticket.state {
+        TicketState::Created(emunet_uuid) => Some(emunet_uuid),
+        _ => None,
+    };
+    Ok(Response::success(TicketInfo {
+        ticket_uuid: ticket.ticket_uuid.clone(),
+        state: ticket.state.clone().into(),
+        emunet_uuid,
+        position: ticket_queue.position(&ticket_uuid),
+    }))
+}
+
+async fn guard(
+    ticket_uuid: Uuid,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = get_ticket(ticket_uuid, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::get()
+        .and(warp::path!("v1" / "tickets" / Uuid))
+        .and(super::auth::caller_filter(connector))
+        .and_then(guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.get(
+        "/v1/tickets/{ticket_uuid}",
+        "get a queued emunet creation, or the outcome of a reservation by its uuid",
+    )
+    .reply::<TicketInfo>();
+}
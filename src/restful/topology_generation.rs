@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::fs::write;
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::emunet_update::Topology;
+use super::Response;
+use crate::database::{Client, Connector};
+use crate::emunet::{InputDevice, InputLink};
+use crate::errors::Error;
+use crate::topology::{self, Shape};
+
+// the input network graph taken by init_emunet and update_emunet
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "GenerateTopologyData")]
+struct ResponseData {
+    devs: Vec<InputDevice<String>>,
+    links: Vec<InputLink<String>>,
+}
+
+fn generate_topology(shape: Shape) -> Response<ResponseData> {
+    match topology::generate(&shape) {
+        Ok(generated) => Response::success(ResponseData {
+            devs: generated.devs,
+            links: generated.links,
+        }),
+        Err(s) => Response::fail(Error::InvalidInput(s)),
+    }
+}
+
+// the topology is generated without touching the database
+async fn guard(
+    shape: Shape,
+    _caller: Caller,
+    _client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    Ok(generate_topology(shape).into())
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("generate_topology".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/generate_topology",
+        "generate a synthetic topology for init_emunet or update_emunet",
+    )
+    .body::<Shape>()
+    .reply::<ResponseData>();
+}
+
+pub async fn mnctl_network_generate(
+    user: &str,
+    emunet: &str,
+    shape: &Shape,
+    output_file: Option<&str>,
+    warp_addr: &str,
+) -> Result<(), String> {
+    let generated = topology::generate(shape)?;
+    println!(
+        "generated {} devices and {} links",
+        generated.devs.len(),
+        generated.links.len()
+    );
+
+    // only save the topology, which can be passed to update later
+    if let Some(path) = output_file {
+        let data = ResponseData {
+            devs: generated.devs,
+            links: generated.links,
+        };
+        let json_str = serde_json::to_string_pretty(&data).unwrap();
+        return write(path, json_str)
+            .await
+            .map_err(|_| format!("can not write to {}", path));
+    }
+
+    // query emunet_uuid
+    let req = super::list_emunet::Request {
+        user: user.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_emunet", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<HashMap<String, Uuid>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let map = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+    let emunet_uuid = map
+        .get(emunet)
+        .ok_or(format!("emunet {} does not exist", emunet))?;
+
+    let topology = Topology {
+        devs: generated.devs,
+        links: generated.links,
+        version: None,
+    };
+    super::emunet_update::send_update(emunet_uuid, topology, warp_addr).await
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::fs::read_to_string;
+use uuid::Uuid;
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::algo::*;
+use crate::database::{helpers, Client, Connector, Scope};
+use crate::emunet::{Emunet, InputDevice, InputLink, User, MAX_DIRECTED_LINK_POWER};
+use crate::errors::Error;
+use crate::topology::{self, Issue, IssueKind, Severity};
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ValidateTopologyRequest")]
+struct Request<String> {
+    emunet_uuid: Uuid,              // uuid of the emunet to launch the topology on
+    devs: Vec<InputDevice<String>>, // the devices as sent to init_emunet
+    links: Vec<InputLink<String>>,  // the links as sent to init_emunet
+    max_degree: Option<u64>,        // the number of interfaces a device can have
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "ValidateTopologyData")]
+struct ResponseData {
+    // no issue is an error
+    valid: bool,
+    devices: u64,
+    links: u64,
+    components: u64,
+    issues: Vec<Issue>,
+    // the number of devices placed on every k8s node, absent if the devices
+    // can not be placed
+    placement: Option<BTreeMap<String, u64>>,
+}
+
+// the devices placed on the servers of the emunet as if it was launched
+// from scratch, the servers of the emunet are left untouched
+fn place(
+    emunet: &Emunet,
+    graph: &UndirectedGraph<u64, InputDevice<String>, InputLink<String>>,
+) -> Option<BTreeMap<String, u64>> {
+    let mut servers: Vec<_> = emunet.servers().values().cloned().collect();
+    for server in servers.iter_mut() {
+        server.reset_devs(HashSet::new());
+    }
+    let assignment = graph.partition(servers.iter_mut())?;
+
+    let mut placement = BTreeMap::new();
+    for server in servers.iter() {
+        placement.insert(server.server_info().node_name.clone(), 0);
+    }
+    for (_, server_name) in assignment.into_iter() {
+        *placement.entry(server_name).or_insert(0) += 1;
+    }
+    Some(placement)
+}
+
+async fn validate_topology(
+    req: Request<String>,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<ResponseData>, Error> {
+    // only the emunet and the quota of its user are read, the transaction
+    // is dropped before the topology is checked
+    let mut tran = client
+        .scoped_tran(
+            Scope::new()
+                .read_core()
+                .read_emunet(req.emunet_uuid.clone()),
+        )
+        .await?;
+
+    let emunet: Emunet = match helpers::get_emunet(&mut tran, req.emunet_uuid.clone()).await? {
+        None => return Ok(Response::fail(Error::EmunetNotFound(req.emunet_uuid))),
+        Some(emunet) => emunet,
+    };
+    if !caller.can_access(emunet.emunet_user()) {
+        return Ok(Response::fail(
+            caller.deny(format!("emunet {}", req.emunet_uuid)),
+        ));
+    }
+    let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut tran).await?;
+    let user = match user_map.remove(emunet.emunet_user()) {
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "user {}",
+                emunet.emunet_user()
+            ))))
+        }
+        Some(user) => user,
+    };
+    let mut usage = helpers::get_usage(&mut tran, user.emunet_uuids()).await?;
+    drop(tran);
+
+    let mut lint = topology::lint(&req.devs, &req.links, req.max_degree);
+
+    // the limits checked by init_emunet and update_emunet
+    if lint.devices > emunet.max_capacity() {
+        lint.issues.push(Issue::new(
+            IssueKind::CapacityExceeded,
+            Vec::new(),
+            format!(
+                "{} devices exceed the capacity of {} devices",
+                lint.devices,
+                emunet.max_capacity()
+            ),
+        ));
+    }
+    if lint.links * 2 > (2 as u64).pow(MAX_DIRECTED_LINK_POWER) {
+        lint.issues.push(Issue::new(
+            IssueKind::TooManyLinks,
+            Vec::new(),
+            format!(
+                "input graph can only have at most {} edges",
+                (2 as u64).pow(MAX_DIRECTED_LINK_POWER - 1)
+            ),
+        ));
+    }
+    usage.links = usage.links - emunet.link_count() + lint.links;
+    if let Err(e) = user.quota().check(&usage) {
+        lint.issues.push(Issue::new(
+            IssueKind::QuotaExceeded,
+            Vec::new(),
+            format!("{}", Error::QuotaExceeded(e)),
+        ));
+    }
+
+    let valid = lint
+        .issues
+        .iter()
+        .all(|issue| issue.severity != Severity::Error);
+    let placement = if lint.devices <= emunet.max_capacity() {
+        UndirectedGraph::new(
+            req.devs.into_iter().map(|v| (v.id(), v)).collect(),
+            req.links.into_iter().map(|e| (e.link_id(), e)).collect(),
+        )
+        .and_then(|graph| place(&emunet, &graph))
+    } else {
+        None
+    };
+
+    Ok(Response::success(ResponseData {
+        valid,
+        devices: lint.devices,
+        links: lint.links,
+        components: lint.components,
+        issues: lint.issues,
+        placement,
+    }))
+}
+
+async fn guard(
+    req: Request<String>,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = validate_topology(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+// the topology is taken like the uploads of init_emunet and update_emunet,
+// so that whatever they accept can be validated
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    warp::post()
+        .and(warp::path!("v1" / "validate_topology"))
+        .and(super::auth::caller_filter(connector))
+        .and(super::parse_topology_body())
+        .and_then(|caller: Caller, client: Client, req: Request<String>| guard(req, caller, client))
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/validate_topology",
+        "check a topology against an emunet without launching it",
+    )
+    .topology_body::<Request<String>>()
+    .reply::<ResponseData>();
+}
+
+pub async fn mnctl_network_validate(
+    user: &str,
+    emunet: &str,
+    input_file: &str,
+    format: Option<&str>,
+    max_degree: Option<u64>,
+    warp_addr: &str,
+) -> Result<(), String> {
+    // read input network graph
+    let format = match format {
+        Some(name) => topology::Format::from_name(name)?,
+        None => topology::Format::from_path(input_file).ok_or(format!(
+            "can't tell the format of {}, please specify --format",
+            input_file
+        ))?,
+    };
+    let text = read_to_string(&input_file)
+        .await
+        .map_err(|_| format!("can't open input file at: {}", input_file))?;
+    let input_graph = topology::import(format, &text)?;
+
+    // query emunet_uuid
+    let req = super::list_emunet::Request {
+        user: user.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/list_emunet", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<HashMap<String, Uuid>> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let map = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+    let emunet_uuid = map
+        .get(emunet)
+        .ok_or(format!("emunet {} does not exist", emunet))?;
+
+    let req = Request {
+        emunet_uuid: emunet_uuid.clone(),
+        devs: input_graph.devs,
+        links: input_graph.links,
+        max_degree,
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/validate_topology", warp_addr))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::CONTENT_ENCODING, "gzip")
+        .body(super::emunet_update::compress_topology(&req)?)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+    let response: Response<ResponseData> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+    let data = if response.success {
+        response.data.unwrap()
+    } else {
+        return Err(response.message);
+    };
+
+    println!(
+        "{} devices, {} links, {} connected components",
+        data.devices, data.links, data.components
+    );
+    for issue in data.issues.iter() {
+        let severity = match issue.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        println!("{}: {}", severity, issue.message);
+    }
+    match data.placement {
+        Some(placement) => {
+            for (node_name, devs) in placement.iter() {
+                println!("{} devices are placed on {}", devs, node_name);
+            }
+        }
+        None => println!("the devices can not be placed"),
+    }
+    if data.valid {
+        println!("the topology is valid");
+        Ok(())
+    } else {
+        Err("the topology is invalid".to_string())
+    }
+}
@@ -1,43 +1,60 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use warp::Filter;
 
+use super::auth::Caller;
 use super::Response;
 use crate::database::{helpers, Client, Connector};
 use crate::emunet::User;
+use crate::errors::Error;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "DeleteUserRequest")]
 struct Request {
     name: String,
 }
 
-async fn user_deletion(req: Request, client: &mut Client) -> Result<Response<()>, ClientError> {
+async fn user_deletion(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<()>, Error> {
+    if !caller.can_access(&req.name) {
+        return Ok(Response::fail(caller.deny(format!("user {}", req.name))));
+    }
+
     let mut guarded_tran = client.guarded_tran().await?;
 
     let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
     let user = user_map.remove(&req.name);
     if user.is_none() {
-        return Ok(Response::fail(format!("user {} does not exist", req.name)));
+        return Ok(Response::fail(Error::NotFound(format!(
+            "user {}",
+            req.name
+        ))));
     }
 
     let emunets = user.unwrap().into_uuid_map();
     if emunets.len() > 0 {
-        return Ok(Response::fail(format!(
+        return Ok(Response::fail(Error::InvalidState(format!(
             "user {} still has active emunets",
             req.name
-        )));
+        ))));
     }
 
-    helpers::set_user_map(&mut guarded_tran, user_map)
-        .await.unwrap();
+    helpers::set_user_map(&mut guarded_tran, user_map).await?;
 
     Ok(Response::success(()))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
-    let res = user_deletion(req, &mut client).await;
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = user_deletion(req, &caller, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
         Err(e) => {
@@ -53,3 +70,9 @@ pub fn build_filter(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
     super::filter_template("delete_user".to_string(), connector, guard)
 }
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/delete_user", "delete a user that has no emunet")
+        .body::<Request>()
+        .reply::<()>();
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use warp::Filter;
+
+use super::auth::Caller;
+use super::Response;
+use crate::database::{helpers, Client, Connector, GuardedTransaction};
+use crate::emunet::{Quota, Usage, User};
+use crate::errors::Error;
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "UserInfoRequest")]
+pub(crate) struct Request {
+    pub(crate) name: String,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "UserInfoData")]
+pub(crate) struct Data {
+    pub(crate) name: String,
+    pub(crate) quota: Quota,
+    pub(crate) usage: Usage,
+}
+
+// check whether the user can replace released_links links with new_links links
+pub(crate) async fn check_links_quota(
+    guarded_tran: &mut GuardedTransaction,
+    user_name: String,
+    released_links: u64,
+    new_links: u64,
+) -> Result<Result<(), Error>, Error> {
+    let mut user_map: HashMap<String, User> = helpers::get_user_map(guarded_tran).await?;
+    let user = match user_map.remove(&user_name) {
+        None => return Ok(Err(Error::NotFound(format!("user {}", user_name)))),
+        Some(user) => user,
+    };
+
+    let mut usage = helpers::get_usage(guarded_tran, user.emunet_uuids()).await?;
+    usage.links = usage.links - released_links + new_links;
+    Ok(user.quota().check(&usage).map_err(Error::QuotaExceeded))
+}
+
+async fn user_info(
+    req: Request,
+    caller: &Caller,
+    client: &mut Client,
+) -> Result<Response<Data>, Error> {
+    if !caller.can_access(&req.name) {
+        return Ok(Response::fail(caller.deny(format!("user {}", req.name))));
+    }
+
+    let mut guarded_tran = client.guarded_tran().await?;
+
+    let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
+    let user = match user_map.remove(&req.name) {
+        None => {
+            return Ok(Response::fail(Error::NotFound(format!(
+                "user {}",
+                req.name
+            ))))
+        }
+        Some(user) => user,
+    };
+    let usage = helpers::get_usage(&mut guarded_tran, user.emunet_uuids()).await?;
+
+    Ok(Response::success(Data {
+        name: req.name,
+        quota: user.quota(),
+        usage,
+    }))
+}
+
+async fn guard(
+    req: Request,
+    caller: Caller,
+    mut client: Client,
+) -> Result<super::Reply, warp::Rejection> {
+    let res = user_info(req, &caller, &mut client).await;
+    match res {
+        Ok(resp) => Ok(resp.into()),
+        Err(e) => {
+            client.notify_failure();
+            let resp: Response<_> = e.into();
+            Ok(resp.into())
+        }
+    }
+}
+
+pub fn build_filter(
+    connector: Connector,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
+    super::filter_template("user_info".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post("/v1/user_info", "get the quota and usage of a user")
+        .body::<Request>()
+        .reply::<Data>();
+}
+
+fn limit_str(limit: Option<u64>) -> String {
+    limit
+        .map(|l| l.to_string())
+        .unwrap_or("unlimited".to_string())
+}
+
+pub async fn mnctl_user_info(username: &str, warp_addr: &str) -> Result<(), String> {
+    let req = Request {
+        name: username.to_string(),
+    };
+    let http_resp = super::mnctl_util::http_client()
+        .post(format!("http://{}/v1/user_info", warp_addr))
+        .json(&req)
+        .send()
+        .await
+        .map_err(|_| format!("can not send HTTP request to {}", warp_addr))?;
+
+    let response: Response<Data> = http_resp
+        .json()
+        .await
+        .map_err(|_| format!("can not parse JSON response"))?;
+
+    if response.success == false {
+        Err(response.message)
+    } else {
+        let data = response.data.unwrap();
+        println!("user: {}", data.name);
+        println!(
+            "emunets: {} / {}",
+            data.usage.emunets,
+            limit_str(data.quota.max_emunets)
+        );
+        println!(
+            "capacity: {} / {}",
+            data.usage.capacity,
+            limit_str(data.quota.max_capacity)
+        );
+        println!(
+            "links: {} / {}",
+            data.usage.links,
+            limit_str(data.quota.max_links)
+        );
+
+        Ok(())
+    }
+}
@@ -1,40 +1,53 @@
 use std::collections::HashMap;
 
-use indradb_proto::ClientError;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use warp::Filter;
 
 use super::Response;
 use crate::database::{helpers, Client, Connector};
 use crate::emunet::User;
+use crate::errors::Error;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(rename = "RegisterUserRequest")]
 struct Request {
     name: String,
 }
 
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "RegisterUserData")]
+struct ResponseData {
+    name: String,
+    token: String, // bearer token used to authenticate the following requests
+}
+
 async fn user_registration(
     req: Request,
     client: &mut Client,
-) -> Result<Response<String>, ClientError> {
+) -> Result<Response<ResponseData>, Error> {
     let mut guarded_tran = client.guarded_tran().await?;
 
     let mut user_map: HashMap<String, User> = helpers::get_user_map(&mut guarded_tran).await?;
     if user_map.get(&req.name).is_some() {
-        return Ok(Response::fail(format!(
+        return Ok(Response::fail(Error::AlreadyExists(format!(
             "user {} has already registered",
             req.name
-        )));
+        ))));
     }
 
-    let user = User::new(&req.name);
+    let token = super::auth::generate_token();
+    let user = User::new(&req.name, token.clone());
     user_map.insert(req.name.clone(), user);
-    helpers::set_user_map(&mut guarded_tran, user_map).await.unwrap();
+    helpers::set_user_map(&mut guarded_tran, user_map).await?;
 
-    Ok(Response::success(req.name))
+    Ok(Response::success(ResponseData {
+        name: req.name,
+        token,
+    }))
 }
 
-async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, warp::Rejection> {
+async fn guard(req: Request, mut client: Client) -> Result<super::Reply, warp::Rejection> {
     let res = user_registration(req, &mut client).await;
     match res {
         Ok(resp) => Ok(resp.into()),
@@ -49,5 +62,15 @@ async fn guard(req: Request, mut client: Client) -> Result<warp::reply::Json, wa
 pub fn build_filter(
     connector: Connector,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + Send {
-    super::filter_template("register_user".to_string(), connector, guard)
+    super::public_filter_template("register_user".to_string(), connector, guard)
+}
+
+pub(crate) fn describe(spec: &mut super::openapi::Spec) {
+    spec.post(
+        "/v1/register_user",
+        "register a user and return its bearer token",
+    )
+    .public()
+    .body::<Request>()
+    .reply::<ResponseData>();
 }
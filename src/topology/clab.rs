@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Exported, RawGraph};
+
+// the labels that carry the placement of the exported devices
+static LABEL_PREFIX: &str = "mocknet-";
+
+#[derive(Deserialize, Serialize)]
+struct Lab {
+    #[serde(default)]
+    name: String,
+    topology: LabTopology,
+}
+
+#[derive(Deserialize, Serialize)]
+struct LabTopology {
+    #[serde(default)]
+    nodes: serde_yaml::Mapping,
+    #[serde(default)]
+    links: Vec<LabLink>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct LabLink {
+    endpoints: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct LabNode {
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    // the commands that assign the addresses of the interfaces
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    exec: Vec<String>,
+}
+
+// the nodes keep the order of the file, an endpoint is written as
+// node:interface
+pub(crate) fn parse(text: &str) -> Result<RawGraph, String> {
+    let lab: Lab =
+        serde_yaml::from_str(text).map_err(|e| format!("invalid containerlab topology, {}", e))?;
+
+    let mut graph = RawGraph::default();
+    // the addresses assigned by the exec commands of the nodes
+    let mut ips = HashMap::new();
+    for (name, node) in lab.topology.nodes.iter() {
+        let name = name
+            .as_str()
+            .ok_or("containerlab node name is not a string".to_string())?;
+        let mut attrs = HashMap::new();
+        attrs.insert("name".to_string(), name.to_string());
+        for field in ["kind", "image"].iter() {
+            if let Some(value) = node.get(*field).and_then(|v| v.as_str()) {
+                attrs.insert(field.to_string(), value.to_string());
+            }
+        }
+        if let Some(labels) = node.get("labels").and_then(|v| v.as_mapping()) {
+            for (key, value) in labels.iter() {
+                if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
+                    if key.starts_with(LABEL_PREFIX) {
+                        let key = key[LABEL_PREFIX.len()..].replace('-', "_");
+                        attrs.insert(key, value.to_string());
+                    }
+                }
+            }
+        }
+        if let Some(exec) = node.get("exec").and_then(|v| v.as_sequence()) {
+            for cmd in exec.iter().filter_map(|cmd| cmd.as_str()) {
+                let words: Vec<&str> = cmd.split_whitespace().collect();
+                if let ["ip", "addr", "add", ip, "dev", intf] = words[..] {
+                    ips.insert((name.to_string(), intf.to_string()), ip.to_string());
+                }
+            }
+        }
+        graph.add_node(name, attrs);
+    }
+
+    for link in lab.topology.links.iter() {
+        let endpoints = link
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let mut parts = endpoint.splitn(2, ':');
+                let node = parts.next().unwrap();
+                let intf = parts.next().ok_or(format!(
+                    "containerlab endpoint {} has no interface",
+                    endpoint
+                ))?;
+                Ok((node, intf))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if endpoints.len() != 2 {
+            return Err(format!(
+                "containerlab link {:?} does not have two endpoints",
+                link.endpoints
+            ));
+        }
+        let mut attrs = HashMap::new();
+        attrs.insert("src_intf".to_string(), endpoints[0].1.to_string());
+        attrs.insert("dst_intf".to_string(), endpoints[1].1.to_string());
+        for (end, (node, intf)) in ["src_ip", "dst_ip"].iter().zip(endpoints.iter()) {
+            if let Some(ip) = ips.get(&(node.to_string(), intf.to_string())) {
+                attrs.insert(end.to_string(), ip.clone());
+            }
+        }
+        graph.add_edge(endpoints[0].0, endpoints[1].0, attrs);
+    }
+
+    Ok(graph)
+}
+
+fn node_name(id: u64) -> String {
+    format!("dev{}", id)
+}
+
+// the devices are linux nodes named after their ids, the addresses are
+// assigned by exec commands once the lab is deployed
+pub(crate) fn write(exported: &Exported) -> Result<String, String> {
+    let mut nodes: BTreeMap<u64, LabNode> = exported
+        .devs
+        .iter()
+        .map(|(id, desc)| {
+            let labels = desc
+                .attrs()
+                .into_iter()
+                .filter(|(name, _)| *name != "kind" && *name != "image")
+                .map(|(name, value)| (format!("{}{}", LABEL_PREFIX, name.replace('_', "-")), value))
+                .collect();
+            let node = LabNode {
+                kind: desc.kind.clone().unwrap_or("linux".to_string()),
+                image: desc.image.clone(),
+                labels,
+                exec: Vec::new(),
+            };
+            (*id, node)
+        })
+        .collect();
+
+    let mut links = Vec::new();
+    for ((src, dst), desc) in exported.links.iter() {
+        let (src_intf, dst_intf) = desc.interfaces.as_ref().ok_or(format!(
+            "the interfaces of link ({}, {}) are unknown",
+            src, dst
+        ))?;
+        if let Some((src_ip, dst_ip)) = desc.ips.as_ref() {
+            for (id, intf, ip) in [(src, src_intf, src_ip), (dst, dst_intf, dst_ip)].iter() {
+                if let Some(node) = nodes.get_mut(id) {
+                    node.exec.push(format!("ip addr add {} dev {}", ip, intf));
+                }
+            }
+        }
+        links.push(LabLink {
+            endpoints: vec![
+                format!("{}:{}", node_name(*src), src_intf),
+                format!("{}:{}", node_name(*dst), dst_intf),
+            ],
+        });
+    }
+
+    let mut mapping = serde_yaml::Mapping::new();
+    for (id, node) in nodes.into_iter() {
+        let node = serde_yaml::to_value(node).map_err(|e| format!("{}", e))?;
+        mapping.insert(serde_yaml::Value::String(node_name(id)), node);
+    }
+    let lab = Lab {
+        name: exported.name.clone(),
+        topology: LabTopology {
+            nodes: mapping,
+            links,
+        },
+    };
+    serde_yaml::to_string(&lab).map_err(|e| format!("can not write containerlab topology, {}", e))
+}
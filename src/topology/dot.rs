@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use super::{Exported, RawGraph};
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    // an unquoted id may be a keyword
+    Id(String, bool),
+    Punct(char),
+    EdgeOp,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start = true;
+    while let Some(c) = chars.next() {
+        let at_line_start = line_start;
+        line_start = c == '\n' || (line_start && c.is_whitespace());
+        match c {
+            c if c.is_whitespace() => {}
+            // preprocessor lines and comments
+            '#' if at_line_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                line_start = true;
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                line_start = true;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => last = c,
+                        None => return Err("invalid DOT, a comment is not closed".to_string()),
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') || chars.peek() == Some(&'>') => {
+                chars.next();
+                tokens.push(Token::EdgeOp);
+            }
+            '{' | '}' | '[' | ']' | ';' | ',' | '=' | ':' => tokens.push(Token::Punct(c)),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') if chars.peek() == Some(&'"') => {
+                            s.push('"');
+                            chars.next();
+                        }
+                        // a line continuation
+                        Some('\\') if chars.peek() == Some(&'\n') => {
+                            chars.next();
+                        }
+                        Some(c) => s.push(c),
+                        None => return Err("invalid DOT, a string is not closed".to_string()),
+                    }
+                }
+                tokens.push(Token::Id(s, true));
+            }
+            '<' => {
+                let mut s = String::new();
+                let mut depth = 1;
+                loop {
+                    let c = match chars.next() {
+                        Some('>') if depth == 1 => break,
+                        Some(c) => c,
+                        None => return Err("invalid DOT, a HTML string is not closed".to_string()),
+                    };
+                    match c {
+                        '<' => depth += 1,
+                        '>' => depth -= 1,
+                        _ => {}
+                    }
+                    s.push(c);
+                }
+                tokens.push(Token::Id(s, true));
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' || !c.is_ascii() => {
+                let mut s = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.' || !c.is_ascii()) {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Id(s, false));
+            }
+            c => return Err(format!("invalid DOT, unexpected {}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    graph: RawGraph,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Id(s, false)) => s.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("invalid DOT, {} is expected", c))
+        }
+    }
+
+    fn id(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Id(s, _)) => Ok(s),
+            _ => Err("invalid DOT, an id is expected".to_string()),
+        }
+    }
+
+    // [a=1, b=2][c=3]
+    fn attr_list(&mut self) -> Result<HashMap<String, String>, String> {
+        let mut attrs = HashMap::new();
+        while self.eat('[') {
+            while !self.eat(']') {
+                let key = self.id()?;
+                let value = if self.eat('=') {
+                    self.id()?
+                } else {
+                    "true".to_string()
+                };
+                attrs.insert(key, value);
+                let _ = self.eat(',') || self.eat(';');
+            }
+        }
+        Ok(attrs)
+    }
+
+    // a node id, whose port is dropped, or a subgraph
+    fn operand(
+        &mut self,
+        node_attrs: &HashMap<String, String>,
+        edge_attrs: &HashMap<String, String>,
+    ) -> Result<Vec<String>, String> {
+        if self.peek_keyword("subgraph") {
+            self.pos += 1;
+            if let Some(Token::Id(..)) = self.peek() {
+                self.pos += 1;
+            }
+        }
+        if self.eat('{') {
+            let nodes = self.stmt_list(node_attrs.clone(), edge_attrs.clone())?;
+            self.expect('}')?;
+            return Ok(nodes);
+        }
+
+        let id = self.id()?;
+        if self.eat(':') {
+            self.id()?;
+            if self.eat(':') {
+                self.id()?;
+            }
+        }
+        Ok(vec![id])
+    }
+
+    fn stmt_list(
+        &mut self,
+        mut node_attrs: HashMap<String, String>,
+        mut edge_attrs: HashMap<String, String>,
+    ) -> Result<Vec<String>, String> {
+        let mut nodes = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Punct('}')) => return Ok(nodes),
+                Some(Token::Punct(';')) => {
+                    self.pos += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // the attributes that apply to the graph, or the nodes and edges
+            // that follow
+            if self.peek_keyword("graph") || self.peek_keyword("node") || self.peek_keyword("edge")
+            {
+                let keyword = self.id()?.to_lowercase();
+                let attrs = self.attr_list()?;
+                match keyword.as_str() {
+                    "node" => node_attrs.extend(attrs),
+                    "edge" => edge_attrs.extend(attrs),
+                    _ => {}
+                }
+                continue;
+            }
+            if let (Some(Token::Id(..)), Some(Token::Punct('='))) =
+                (self.peek(), self.tokens.get(self.pos + 1))
+            {
+                self.pos += 2;
+                self.id()?;
+                continue;
+            }
+
+            let is_subgraph =
+                self.peek_keyword("subgraph") || self.peek() == Some(&Token::Punct('{'));
+            let mut operands = vec![self.operand(&node_attrs, &edge_attrs)?];
+            while self.peek() == Some(&Token::EdgeOp) {
+                self.pos += 1;
+                operands.push(self.operand(&node_attrs, &edge_attrs)?);
+            }
+            let attrs = self.attr_list()?;
+
+            if operands.len() == 1 && !is_subgraph {
+                let mut node = node_attrs.clone();
+                node.extend(attrs);
+                self.graph.add_node(&operands[0][0], node);
+            } else {
+                for id in operands.iter().flatten() {
+                    self.graph.add_node(id, HashMap::new());
+                }
+                let mut edge = edge_attrs.clone();
+                edge.extend(attrs);
+                for pair in operands.windows(2) {
+                    for src in pair[0].iter() {
+                        for dst in pair[1].iter() {
+                            self.graph.add_edge(src, dst, edge.clone());
+                        }
+                    }
+                }
+            }
+            nodes.extend(operands.into_iter().flatten());
+        }
+    }
+}
+
+// the nodes and edges of the graph and its subgraphs, the default
+// attributes declared by node and edge statements are applied
+pub(crate) fn parse(text: &str) -> Result<RawGraph, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        graph: RawGraph::default(),
+    };
+
+    if parser.peek_keyword("strict") {
+        parser.pos += 1;
+    }
+    if !(parser.peek_keyword("graph") || parser.peek_keyword("digraph")) {
+        return Err("invalid DOT, graph or digraph is expected".to_string());
+    }
+    parser.pos += 1;
+    if let Some(Token::Id(..)) = parser.peek() {
+        parser.pos += 1;
+    }
+    parser.expect('{')?;
+    parser.stmt_list(HashMap::new(), HashMap::new())?;
+    parser.expect('}')?;
+
+    Ok(parser.graph)
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\\\""))
+}
+
+fn write_attrs(attrs: &[(&str, String)]) -> String {
+    let attrs: Vec<String> = attrs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, quote(value)))
+        .collect();
+    attrs.join(", ")
+}
+
+// the nodes are labeled with their k8s nodes and the edges with the
+// interfaces at both ends, so that the rendered graph shows the placement
+// and the addressing
+pub(crate) fn write(exported: &Exported) -> String {
+    let mut out = format!("graph {} {{\n", quote(&exported.name));
+    for (id, desc) in exported.devs.iter() {
+        let mut attrs = desc.attrs();
+        if let Some(k8s_node) = desc.k8s_node.as_ref() {
+            attrs.push(("xlabel", k8s_node.clone()));
+        }
+        out.push_str(&format!("  {} [{}];\n", id, write_attrs(&attrs)));
+    }
+    for ((src, dst), desc) in exported.links.iter() {
+        let mut attrs = desc.attrs();
+        if let (Some((src_intf, dst_intf)), Some((src_ip, dst_ip))) =
+            (desc.interfaces.as_ref(), desc.ips.as_ref())
+        {
+            attrs.push(("taillabel", format!("{}\\n{}", src_intf, src_ip)));
+            attrs.push(("headlabel", format!("{}\\n{}", dst_intf, dst_ip)));
+        }
+        out.push_str(&format!(
+            "  {} -- {} [{}];\n",
+            src,
+            dst,
+            write_attrs(&attrs)
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
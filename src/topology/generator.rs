@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{description, DeviceDescription};
+use crate::algo::UndirectedGraph;
+use crate::emunet::{InputDevice, InputLink, MAX_DIRECTED_LINK_POWER};
+
+// the synthetic topologies, the random ones are the same for the same seed
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Shape {
+    Line {
+        nodes: u64,
+    },
+    Ring {
+        nodes: u64,
+    },
+    // a hub linked to nodes - 1 leaves
+    Star {
+        nodes: u64,
+    },
+    Grid {
+        rows: u64,
+        cols: u64,
+        // the last row and column wrap around
+        #[serde(default)]
+        torus: bool,
+    },
+    // the k-ary fat-tree with its k^3/4 hosts
+    FatTree {
+        k: u64,
+    },
+    // every leaf is linked to every spine
+    LeafSpine {
+        leaves: u64,
+        spines: u64,
+    },
+    // every pair of nodes is linked with probability p
+    ErdosRenyi {
+        nodes: u64,
+        p: f64,
+        #[serde(default)]
+        seed: u64,
+    },
+    // every new node is linked to m nodes chosen by their degrees
+    BarabasiAlbert {
+        nodes: u64,
+        m: u64,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+// splitmix64, which keeps the generated graphs stable across platforms
+// and versions
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+// collect the named devices and the links, self-loops and the links that
+// are added twice are dropped
+struct Builder {
+    names: Vec<String>,
+    edges: BTreeSet<(u64, u64)>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            names: Vec::new(),
+            edges: BTreeSet::new(),
+        }
+    }
+
+    fn with_nodes(prefix: &str, nodes: u64) -> Result<Self, String> {
+        let mut builder = Self::new();
+        builder.add_nodes(prefix, nodes)?;
+        Ok(builder)
+    }
+
+    // add the nodes named prefix-0, prefix-1..., returning the first id
+    fn add_nodes(&mut self, prefix: &str, nodes: u64) -> Result<u64, String> {
+        let max_nodes = (2 as u64).pow(MAX_DIRECTED_LINK_POWER);
+        let first = self.names.len() as u64;
+        if nodes > max_nodes - first {
+            return Err(format!(
+                "generated topology can only have at most {} devices",
+                max_nodes
+            ));
+        }
+        for i in 0..nodes {
+            self.names.push(format!("{}-{}", prefix, i));
+        }
+        Ok(first)
+    }
+
+    fn link(&mut self, a: u64, b: u64) -> Result<(), String> {
+        let max_edges = (2 as usize).pow(MAX_DIRECTED_LINK_POWER - 1);
+        if a != b {
+            self.edges.insert((a.min(b), a.max(b)));
+        }
+        if self.edges.len() > max_edges {
+            return Err(format!(
+                "generated topology can only have at most {} edges",
+                max_edges
+            ));
+        }
+        Ok(())
+    }
+
+    fn build(self) -> UndirectedGraph<u64, InputDevice<String>, InputLink<String>> {
+        let nodes = self
+            .names
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| {
+                let desc = DeviceDescription {
+                    name: Some(name),
+                    ..DeviceDescription::default()
+                };
+                let dev = InputDevice {
+                    id: id as u64,
+                    description: description(desc),
+                };
+                (id as u64, dev)
+            })
+            .collect();
+        let edges = self
+            .edges
+            .into_iter()
+            .map(|edge_id| {
+                let link = InputLink {
+                    edge_id,
+                    description: String::new(),
+                };
+                (edge_id, link)
+            })
+            .collect();
+        UndirectedGraph::new(nodes, edges).unwrap()
+    }
+}
+
+fn fat_tree(k: u64) -> Result<Builder, String> {
+    if k < 2 || k % 2 != 0 {
+        return Err("k of a fat-tree should be a positive even number".to_string());
+    }
+    // k^3/4 hosts are already beyond the limit of the devices
+    if k > 64 {
+        return Err("k of a fat-tree is too large".to_string());
+    }
+    let half = k / 2;
+    let mut builder = Builder::new();
+    let core = builder.add_nodes("core", half * half)?;
+    let agg = builder.add_nodes("agg", k * half)?;
+    let edge = builder.add_nodes("edge", k * half)?;
+    let host = builder.add_nodes("host", k * half * half)?;
+    for pod in 0..k {
+        for i in 0..half {
+            let agg_id = agg + pod * half + i;
+            // the i-th aggregation switch reaches the i-th group of cores
+            for j in 0..half {
+                builder.link(agg_id, core + i * half + j)?;
+            }
+            for j in 0..half {
+                builder.link(agg_id, edge + pod * half + j)?;
+            }
+            let edge_id = edge + pod * half + i;
+            for j in 0..half {
+                builder.link(edge_id, host + (pod * half + i) * half + j)?;
+            }
+        }
+    }
+    Ok(builder)
+}
+
+// the G(n, p) model, the pairs that are not linked are skipped over
+// following Batagelj and Brandes
+fn erdos_renyi(nodes: u64, p: f64, seed: u64) -> Result<Builder, String> {
+    if !(0.0..=1.0).contains(&p) {
+        return Err("p should be between 0 and 1".to_string());
+    }
+    let mut builder = Builder::with_nodes("node", nodes)?;
+    if p == 0.0 {
+        return Ok(builder);
+    }
+    let mut rng = Rng(seed);
+    let lp = (1.0 - p).ln();
+    let (mut v, mut w) = (1u64, -1i64);
+    while v < nodes {
+        // a skip beyond the last pair ends the loop all the same
+        let skip = if p == 1.0 {
+            0.0
+        } else {
+            ((1.0 - rng.next_f64()).ln() / lp)
+                .floor()
+                .min((nodes * nodes) as f64)
+        };
+        w += 1 + skip as i64;
+        while w >= v as i64 && v < nodes {
+            w -= v as i64;
+            v += 1;
+        }
+        if v < nodes {
+            builder.link(v, w as u64)?;
+        }
+    }
+    Ok(builder)
+}
+
+// preferential attachment, starting from a star of m + 1 nodes
+fn barabasi_albert(nodes: u64, m: u64, seed: u64) -> Result<Builder, String> {
+    if m < 1 || m >= nodes {
+        return Err("m should be at least 1 and smaller than the number of nodes".to_string());
+    }
+    let mut builder = Builder::with_nodes("node", nodes)?;
+    let mut rng = Rng(seed);
+    // every node appears once for each of its links
+    let mut repeated = Vec::new();
+    for leaf in 1..=m {
+        builder.link(0, leaf)?;
+        repeated.push(0);
+        repeated.push(leaf);
+    }
+    for source in (m + 1)..nodes {
+        let mut targets = BTreeSet::new();
+        while (targets.len() as u64) < m {
+            targets.insert(repeated[rng.below(repeated.len() as u64) as usize]);
+        }
+        for target in targets {
+            builder.link(source, target)?;
+            repeated.push(target);
+            repeated.push(source);
+        }
+    }
+    Ok(builder)
+}
+
+pub(crate) fn generate(
+    shape: &Shape,
+) -> Result<UndirectedGraph<u64, InputDevice<String>, InputLink<String>>, String> {
+    let builder = match *shape {
+        Shape::Line { nodes } => {
+            let mut builder = Builder::with_nodes("node", nodes)?;
+            for i in 1..nodes {
+                builder.link(i - 1, i)?;
+            }
+            builder
+        }
+        Shape::Ring { nodes } => {
+            if nodes < 3 {
+                return Err("a ring needs at least 3 nodes".to_string());
+            }
+            let mut builder = Builder::with_nodes("node", nodes)?;
+            for i in 0..nodes {
+                builder.link(i, (i + 1) % nodes)?;
+            }
+            builder
+        }
+        Shape::Star { nodes } => {
+            let mut builder = Builder::new();
+            builder.add_nodes("hub", 1.min(nodes))?;
+            builder.add_nodes("leaf", nodes.saturating_sub(1))?;
+            for i in 1..nodes {
+                builder.link(0, i)?;
+            }
+            builder
+        }
+        Shape::Grid { rows, cols, torus } => {
+            if torus && (rows < 3 || cols < 3) {
+                return Err("a torus needs at least 3 rows and 3 columns".to_string());
+            }
+            let nodes = rows
+                .checked_mul(cols)
+                .ok_or("the grid is too large".to_string())?;
+            let mut builder = Builder::with_nodes("node", nodes)?;
+            for r in 0..rows {
+                for c in 0..cols {
+                    let id = r * cols + c;
+                    if c + 1 < cols || torus {
+                        builder.link(id, r * cols + (c + 1) % cols)?;
+                    }
+                    if r + 1 < rows || torus {
+                        builder.link(id, ((r + 1) % rows) * cols + c)?;
+                    }
+                }
+            }
+            builder
+        }
+        Shape::FatTree { k } => fat_tree(k)?,
+        Shape::LeafSpine { leaves, spines } => {
+            let mut builder = Builder::new();
+            let spine = builder.add_nodes("spine", spines)?;
+            let leaf = builder.add_nodes("leaf", leaves)?;
+            for l in 0..leaves {
+                for s in 0..spines {
+                    builder.link(leaf + l, spine + s)?;
+                }
+            }
+            builder
+        }
+        Shape::ErdosRenyi { nodes, p, seed } => erdos_renyi(nodes, p, seed)?,
+        Shape::BarabasiAlbert { nodes, m, seed } => barabasi_albert(nodes, m, seed)?,
+    };
+    if builder.names.len() == 0 {
+        return Err("generated topology has no device".to_string());
+    }
+    Ok(builder.build())
+}
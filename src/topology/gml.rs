@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use super::RawGraph;
+
+enum Token {
+    Word(String),
+    Str(String),
+    Open,
+    Close,
+}
+
+enum Value {
+    Scalar(String),
+    List(Vec<(String, Value)>),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => tokens.push(Token::Open),
+            ']' => tokens.push(Token::Close),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err("invalid GML, a string is not closed".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            // comments run to the end of the line
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut s = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '[' || c == ']' || c == '"' {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(s));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_list(
+    tokens: &mut Peekable<IntoIter<Token>>,
+    nested: bool,
+) -> Result<Vec<(String, Value)>, String> {
+    let mut list = Vec::new();
+    loop {
+        let key = match tokens.next() {
+            Some(Token::Word(key)) => key,
+            Some(Token::Close) if nested => return Ok(list),
+            None if !nested => return Ok(list),
+            _ => return Err("invalid GML, a key is expected".to_string()),
+        };
+        let value = match tokens.next() {
+            Some(Token::Word(s)) | Some(Token::Str(s)) => Value::Scalar(s),
+            Some(Token::Open) => Value::List(parse_list(tokens, true)?),
+            _ => return Err(format!("invalid GML, {} has no value", key)),
+        };
+        list.push((key, value));
+    }
+}
+
+// the scalar attributes of a node or an edge, the nested lists such as
+// graphics are left out
+fn scalars(list: Vec<(String, Value)>) -> HashMap<String, String> {
+    list.into_iter()
+        .filter_map(|(key, value)| match value {
+            Value::Scalar(s) => Some((key, s)),
+            Value::List(_) => None,
+        })
+        .collect()
+}
+
+pub(crate) fn parse(text: &str) -> Result<RawGraph, String> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let items = parse_list(&mut tokens, false)?
+        .into_iter()
+        .find_map(|(key, value)| match value {
+            Value::List(items) if key == "graph" => Some(items),
+            _ => None,
+        })
+        .ok_or("invalid GML, there is no graph".to_string())?;
+
+    let mut graph = RawGraph::default();
+    for (key, value) in items {
+        let mut attrs = match value {
+            Value::List(list) if key == "node" || key == "edge" => scalars(list),
+            _ => continue,
+        };
+        if key == "node" {
+            let id = attrs.remove("id").ok_or("GML node has no id".to_string())?;
+            graph.add_node(&id, attrs);
+        } else {
+            let src = attrs
+                .remove("source")
+                .ok_or("GML edge has no source".to_string())?;
+            let dst = attrs
+                .remove("target")
+                .ok_or("GML edge has no target".to_string())?;
+            graph.add_edge(&src, &dst, attrs);
+        }
+    }
+
+    Ok(graph)
+}
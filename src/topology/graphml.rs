@@ -0,0 +1,159 @@
+use std::collections::{BTreeSet, HashMap};
+
+use xml::reader::{EventReader, XmlEvent};
+
+use super::{Exported, RawGraph};
+
+// the element that the data elements belong to
+enum Owner {
+    Graph,
+    Node(String),
+    Edge(usize),
+}
+
+// the data of nodes and edges are named by the attr.name of their keys,
+// the edges are undirected whatever the edgedefault of the graph is
+pub(crate) fn parse(text: &str) -> Result<RawGraph, String> {
+    let mut graph = RawGraph::default();
+    let mut keys: HashMap<String, String> = HashMap::new();
+    let mut owner = Owner::Graph;
+    let mut data_key: Option<String> = None;
+    let mut data = String::new();
+
+    for event in EventReader::from_str(text) {
+        match event.map_err(|e| format!("invalid GraphML, {}", e))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let get = |attr: &str| {
+                    attributes
+                        .iter()
+                        .find(|a| a.name.local_name == attr)
+                        .map(|a| a.value.clone())
+                };
+                match name.local_name.as_str() {
+                    "key" => {
+                        if let Some(id) = get("id") {
+                            keys.insert(id.clone(), get("attr.name").unwrap_or(id));
+                        }
+                    }
+                    "node" => {
+                        let id = get("id").ok_or("GraphML node has no id".to_string())?;
+                        graph.add_node(&id, HashMap::new());
+                        owner = Owner::Node(id);
+                    }
+                    "edge" => {
+                        let src = get("source").ok_or("GraphML edge has no source".to_string())?;
+                        let dst = get("target").ok_or("GraphML edge has no target".to_string())?;
+                        graph.add_edge(&src, &dst, HashMap::new());
+                        owner = Owner::Edge(graph.edges.len() - 1);
+                    }
+                    "data" => {
+                        data_key = get("key");
+                        data.clear();
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::Characters(s) | XmlEvent::CData(s) => {
+                if data_key.is_some() {
+                    data.push_str(&s);
+                }
+            }
+            XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                "data" => {
+                    if let Some(key) = data_key.take() {
+                        let name = keys.get(&key).cloned().unwrap_or(key);
+                        let value = data.trim().to_string();
+                        match &owner {
+                            Owner::Graph => {}
+                            Owner::Node(id) => {
+                                let attrs = std::iter::once((name, value)).collect();
+                                graph.add_node(id, attrs);
+                            }
+                            Owner::Edge(idx) => {
+                                graph.edges[*idx].2.insert(name, value);
+                            }
+                        }
+                    }
+                }
+                "node" | "edge" => owner = Owner::Graph,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    Ok(graph)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// every attribute is declared as a key named after it, the keys of the
+// nodes and the edges are prefixed to keep them apart
+pub(crate) fn write(exported: &Exported) -> String {
+    let devs: Vec<_> = exported
+        .devs
+        .iter()
+        .map(|(id, desc)| (id, desc.attrs()))
+        .collect();
+    let links: Vec<_> = exported
+        .links
+        .iter()
+        .map(|(edge_id, desc)| (edge_id, desc.attrs()))
+        .collect();
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    let mut declared = BTreeSet::new();
+    let node_keys = devs
+        .iter()
+        .flat_map(|(_, attrs)| attrs.iter().map(|a| ("node", a.0)));
+    let edge_keys = links
+        .iter()
+        .flat_map(|(_, attrs)| attrs.iter().map(|a| ("edge", a.0)));
+    for (domain, name) in node_keys.chain(edge_keys) {
+        if declared.insert((domain, name)) {
+            let ty = if name == "speed" { "long" } else { "string" };
+            out.push_str(&format!(
+                "  <key id=\"{}_{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>\n",
+                domain, name, domain, name, ty
+            ));
+        }
+    }
+
+    out.push_str(&format!(
+        "  <graph id=\"{}\" edgedefault=\"undirected\">\n",
+        escape(&exported.name)
+    ));
+    for (id, attrs) in devs.iter() {
+        out.push_str(&format!("    <node id=\"{}\">", id));
+        for (name, value) in attrs.iter() {
+            out.push_str(&format!(
+                "<data key=\"node_{}\">{}</data>",
+                name,
+                escape(value)
+            ));
+        }
+        out.push_str("</node>\n");
+    }
+    for ((src, dst), attrs) in links.iter() {
+        out.push_str(&format!("    <edge source=\"{}\" target=\"{}\">", src, dst));
+        for (name, value) in attrs.iter() {
+            out.push_str(&format!(
+                "<data key=\"edge_{}\">{}</data>",
+                name,
+                escape(value)
+            ));
+        }
+        out.push_str("</edge>\n");
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::emunet::{InputDevice, InputLink};
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    DuplicateDevice,
+    UnknownDevice,
+    SelfLoop,
+    // a link that is given twice, possibly in both directions, only the
+    // first one is kept by init_emunet and update_emunet
+    DuplicateLink,
+    IsolatedDevice,
+    // a group of devices that can not reach the largest component
+    Disconnected,
+    DegreeExceeded,
+    TooManyLinks,
+    CapacityExceeded,
+    QuotaExceeded,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // the topology would be rejected, or would break the emunet
+    Error,
+    // the topology is accepted, but may not be what the user meant
+    Warning,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub severity: Severity,
+    // the devices involved, empty if the issue is about the whole topology
+    pub devices: Vec<u64>,
+    pub message: String,
+}
+
+impl Issue {
+    pub(crate) fn new(kind: IssueKind, devices: Vec<u64>, message: String) -> Self {
+        let severity = match kind {
+            IssueKind::DuplicateLink | IssueKind::IsolatedDevice | IssueKind::Disconnected => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        };
+        Self {
+            kind,
+            severity,
+            devices,
+            message,
+        }
+    }
+}
+
+// the structure of a topology as init_emunet and update_emunet will see it
+pub(crate) struct Lint {
+    pub(crate) issues: Vec<Issue>,
+    // the devices and the links that are kept
+    pub(crate) devices: u64,
+    pub(crate) links: u64,
+    pub(crate) components: u64,
+}
+
+// check the topology without building it, every problem is reported
+// instead of the first one
+pub(crate) fn lint<T>(
+    devs: &[InputDevice<T>],
+    links: &[InputLink<T>],
+    max_degree: Option<u64>,
+) -> Lint {
+    let mut issues = Vec::new();
+
+    let mut adjacency: BTreeMap<u64, BTreeSet<u64>> = BTreeMap::new();
+    for dev in devs.iter() {
+        if adjacency.insert(dev.id(), BTreeSet::new()).is_some() {
+            issues.push(Issue::new(
+                IssueKind::DuplicateDevice,
+                vec![dev.id()],
+                format!("device {} is given more than once", dev.id()),
+            ));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for link in links.iter() {
+        let (src, dst) = link.link_id();
+        let unknown: Vec<u64> = [src, dst]
+            .iter()
+            .filter(|id| !adjacency.contains_key(id))
+            .cloned()
+            .collect();
+        if unknown.len() > 0 {
+            issues.push(Issue::new(
+                IssueKind::UnknownDevice,
+                unknown,
+                format!(
+                    "link ({}, {}) connects a device that is not given",
+                    src, dst
+                ),
+            ));
+            continue;
+        }
+        if src == dst {
+            issues.push(Issue::new(
+                IssueKind::SelfLoop,
+                vec![src],
+                format!("link ({}, {}) connects device {} to itself", src, dst, src),
+            ));
+            continue;
+        }
+        let (low, high) = if src < dst { (src, dst) } else { (dst, src) };
+        if !seen.insert((low, high)) {
+            issues.push(Issue::new(
+                IssueKind::DuplicateLink,
+                vec![src, dst],
+                format!(
+                    "link ({}, {}) duplicates an earlier link between the same devices and is dropped",
+                    src, dst
+                ),
+            ));
+            continue;
+        }
+        adjacency.get_mut(&src).unwrap().insert(dst);
+        adjacency.get_mut(&dst).unwrap().insert(src);
+    }
+
+    for (id, peers) in adjacency.iter() {
+        if peers.len() == 0 && adjacency.len() > 1 {
+            issues.push(Issue::new(
+                IssueKind::IsolatedDevice,
+                vec![*id],
+                format!("device {} has no link", id),
+            ));
+        }
+        match max_degree {
+            Some(max_degree) if peers.len() as u64 > max_degree => issues.push(Issue::new(
+                IssueKind::DegreeExceeded,
+                vec![*id],
+                format!(
+                    "device {} has {} links, more than the {} interfaces allowed",
+                    id,
+                    peers.len(),
+                    max_degree
+                ),
+            )),
+            _ => {}
+        }
+    }
+
+    // the isolated devices are reported already, the other components are
+    // compared against the largest one
+    let mut visited = HashSet::new();
+    let mut components = Vec::new();
+    for id in adjacency.keys() {
+        if !visited.insert(*id) {
+            continue;
+        }
+        let mut component = vec![*id];
+        let mut idx = 0;
+        while idx < component.len() {
+            for peer in adjacency[&component[idx]].iter() {
+                if visited.insert(*peer) {
+                    component.push(*peer);
+                }
+            }
+            idx += 1;
+        }
+        components.push(component);
+    }
+    let largest = components
+        .iter()
+        .enumerate()
+        .max_by_key(|(idx, component)| (component.len(), std::cmp::Reverse(*idx)))
+        .map(|(idx, _)| idx);
+    for (idx, component) in components.iter().enumerate() {
+        if Some(idx) == largest || component.len() == 1 {
+            continue;
+        }
+        let mut devices = component.clone();
+        devices.sort();
+        issues.push(Issue::new(
+            IssueKind::Disconnected,
+            devices,
+            format!(
+                "{} devices can not reach the largest component of {} devices",
+                component.len(),
+                components[largest.unwrap()].len()
+            ),
+        ));
+    }
+
+    Lint {
+        issues,
+        devices: adjacency.len() as u64,
+        links: seen.len() as u64,
+        components: components.len() as u64,
+    }
+}
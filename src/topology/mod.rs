@@ -0,0 +1,646 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::emunet::{InputDevice, InputLink, OutputDevice, OutputLink};
+
+mod clab;
+mod dot;
+mod generator;
+mod gml;
+mod graphml;
+mod lint;
+
+pub use generator::Shape;
+pub(crate) use lint::lint;
+pub use lint::{Issue, IssueKind, Severity};
+
+// the formats that a network graph can be read from and written to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // the {devs, links} JSON taken by the restful api
+    Json,
+    // GraphML, including the files of the Internet Topology Zoo
+    GraphMl,
+    Gml,
+    // Graphviz DOT
+    Dot,
+    // containerlab topology YAML
+    Clab,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "json" => Ok(Format::Json),
+            "graphml" => Ok(Format::GraphMl),
+            "gml" => Ok(Format::Gml),
+            "dot" => Ok(Format::Dot),
+            "clab" => Ok(Format::Clab),
+            _ => Err(format!("unknown topology format {}", name)),
+        }
+    }
+
+    // guess the format from the extension of the file
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.to_lowercase();
+        let ext = path.rsplit('.').next()?;
+        match ext {
+            "json" => Some(Format::Json),
+            "graphml" | "xml" => Some(Format::GraphMl),
+            "gml" => Some(Format::Gml),
+            "dot" | "gv" => Some(Format::Dot),
+            "yml" | "yaml" => Some(Format::Clab),
+            _ => None,
+        }
+    }
+}
+
+// a graph as it is written in the file, nodes are named by strings and
+// carry the attributes found in the file
+#[derive(Default)]
+pub(crate) struct RawGraph {
+    pub(crate) nodes: Vec<(String, HashMap<String, String>)>,
+    pub(crate) edges: Vec<(String, String, HashMap<String, String>)>,
+    index: HashMap<String, usize>,
+}
+
+impl RawGraph {
+    // add a node, the attributes of a node that is already added are
+    // updated instead
+    pub(crate) fn add_node(&mut self, id: &str, attrs: HashMap<String, String>) {
+        match self.index.get(id) {
+            Some(idx) => self.nodes[*idx].1.extend(attrs),
+            None => {
+                self.index.insert(id.to_string(), self.nodes.len());
+                self.nodes.push((id.to_string(), attrs));
+            }
+        }
+    }
+
+    pub(crate) fn add_edge(&mut self, src: &str, dst: &str, attrs: HashMap<String, String>) {
+        self.edges.push((src.to_string(), dst.to_string(), attrs));
+    }
+}
+
+// the description of an imported device, encoded as a JSON string, the
+// placement is only known for the devices of a running emunet
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub(crate) struct DeviceDescription {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) k8s_node: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pod: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) login_ip: Option<String>,
+}
+
+impl DeviceDescription {
+    // the attributes written to the nodes of the exported graphs
+    pub(crate) fn attrs(&self) -> Vec<(&'static str, String)> {
+        let fields = [
+            ("name", &self.name),
+            ("kind", &self.kind),
+            ("image", &self.image),
+            ("k8s_node", &self.k8s_node),
+            ("pod", &self.pod),
+            ("login_ip", &self.login_ip),
+        ];
+        fields
+            .iter()
+            .filter_map(|(name, value)| value.as_ref().map(|v| (*name, v.clone())))
+            .collect()
+    }
+}
+
+// the description of an imported link, encoded as a JSON string
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub(crate) struct LinkDescription {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    // bits per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) speed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) latency: Option<String>,
+    // the interfaces of the source and the destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) interfaces: Option<(String, String)>,
+    // the addresses of the interfaces, with their prefix lengths
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ips: Option<(String, String)>,
+}
+
+impl LinkDescription {
+    // the attributes written to the edges of the exported graphs
+    pub(crate) fn attrs(&self) -> Vec<(&'static str, String)> {
+        let mut attrs = Vec::new();
+        if let Some(name) = self.name.as_ref() {
+            attrs.push(("name", name.clone()));
+        }
+        if let Some(speed) = self.speed {
+            attrs.push(("speed", speed.to_string()));
+        }
+        if let Some(latency) = self.latency.as_ref() {
+            attrs.push(("latency", latency.clone()));
+        }
+        if let Some((src, dst)) = self.interfaces.as_ref() {
+            attrs.push(("src_intf", src.clone()));
+            attrs.push(("dst_intf", dst.clone()));
+        }
+        if let Some((src, dst)) = self.ips.as_ref() {
+            attrs.push(("src_ip", src.clone()));
+            attrs.push(("dst_ip", dst.clone()));
+        }
+        attrs
+    }
+}
+
+// a running emunet with the placement and the addressing of its devices
+pub(crate) struct Exported {
+    pub(crate) name: String,
+    pub(crate) devs: Vec<(u64, DeviceDescription)>,
+    pub(crate) links: Vec<((u64, u64), LinkDescription)>,
+}
+
+// an input network graph read from a file
+pub struct Imported {
+    pub(crate) devs: Vec<InputDevice<String>>,
+    pub(crate) links: Vec<InputLink<String>>,
+    // the ids in the file that are renumbered, empty if every id in the
+    // file is an integer
+    pub id_map: BTreeMap<String, u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct InputNetworkGraph {
+    devs: Vec<InputDevice<String>>,
+    links: Vec<InputLink<String>>,
+}
+
+pub fn import(format: Format, text: &str) -> Result<Imported, String> {
+    let raw = match format {
+        Format::Json => {
+            let input_graph: InputNetworkGraph = serde_json::from_str(text)
+                .map_err(|_| "invalid network graph format".to_string())?;
+            return Ok(Imported {
+                devs: input_graph.devs,
+                links: input_graph.links,
+                id_map: BTreeMap::new(),
+            });
+        }
+        Format::GraphMl => graphml::parse(text)?,
+        Format::Gml => gml::parse(text)?,
+        Format::Dot => dot::parse(text)?,
+        Format::Clab => clab::parse(text)?,
+    };
+    into_imported(raw)
+}
+
+fn attr<'a>(attrs: &'a HashMap<String, String>, names: &[&str]) -> Option<&'a String> {
+    names
+        .iter()
+        .find_map(|name| attrs.get(*name))
+        .filter(|v| v.len() > 0)
+}
+
+fn description<T: Serialize + Default + PartialEq>(desc: T) -> String {
+    if desc == T::default() {
+        String::new()
+    } else {
+        serde_json::to_string(&desc).unwrap()
+    }
+}
+
+// parse speeds like 10000000000, 10G, 10 Gbps or 100Mb/s into bits per second
+pub(crate) fn parse_speed(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let value: f64 = s[..split].parse().ok()?;
+    let unit = s[split..].trim().to_lowercase();
+    let scale = match unit.chars().next() {
+        None | Some('b') => 1.0,
+        Some('k') => 1e3,
+        Some('m') => 1e6,
+        Some('g') => 1e9,
+        Some('t') => 1e12,
+        _ => return None,
+    };
+    Some((value * scale) as u64)
+}
+
+fn link_speed(attrs: &HashMap<String, String>) -> Option<u64> {
+    // the Topology Zoo stores the speed in bps in LinkSpeedRaw, and the
+    // number and the unit in LinkSpeed and LinkSpeedUnits
+    if let Some(speed) = attr(attrs, &["LinkSpeedRaw"]).and_then(|s| parse_speed(s)) {
+        return Some(speed);
+    }
+    if let Some(speed) = attr(attrs, &["LinkSpeed"]) {
+        let units = attr(attrs, &["LinkSpeedUnits"]).map(|s| s.as_str());
+        return parse_speed(&format!("{}{}", speed, units.unwrap_or("")));
+    }
+    attr(attrs, &["speed", "bandwidth", "bw", "capacity"]).and_then(|s| parse_speed(s))
+}
+
+// assign the u64 ids and map the attributes into the descriptions
+fn into_imported(raw: RawGraph) -> Result<Imported, String> {
+    let numeric = raw.nodes.iter().all(|(id, _)| id.parse::<u64>().is_ok());
+    let mut ids = HashMap::new();
+    let mut dev_ids = HashSet::new();
+    let mut id_map = BTreeMap::new();
+    for (idx, (id, _)) in raw.nodes.iter().enumerate() {
+        let dev_id = if numeric {
+            id.parse::<u64>().unwrap()
+        } else {
+            id_map.insert(id.clone(), idx as u64);
+            idx as u64
+        };
+        // ids like 01 and 1 are the same device
+        if !dev_ids.insert(dev_id) {
+            return Err(format!("node {} collides with another node", id));
+        }
+        ids.insert(id.as_str(), dev_id);
+    }
+
+    let devs = raw
+        .nodes
+        .iter()
+        .map(|(id, attrs)| {
+            let desc = DeviceDescription {
+                name: attr(attrs, &["name", "label"]).cloned().or(if numeric {
+                    None
+                } else {
+                    Some(id.clone())
+                }),
+                kind: attr(attrs, &["kind"]).cloned(),
+                image: attr(attrs, &["image"]).cloned(),
+                k8s_node: attr(attrs, &["k8s_node"]).cloned(),
+                pod: attr(attrs, &["pod"]).cloned(),
+                login_ip: attr(attrs, &["login_ip"]).cloned(),
+            };
+            InputDevice {
+                id: ids[id.as_str()],
+                description: description(desc),
+            }
+        })
+        .collect();
+
+    let links = raw
+        .edges
+        .iter()
+        .map(|(src, dst, attrs)| {
+            let src_id = ids
+                .get(src.as_str())
+                .ok_or(format!("edge refers to unknown node {}", src))?;
+            let dst_id = ids
+                .get(dst.as_str())
+                .ok_or(format!("edge refers to unknown node {}", dst))?;
+            let pair = |src: &str, dst: &str| match (attrs.get(src), attrs.get(dst)) {
+                (Some(s), Some(d)) => Some((s.clone(), d.clone())),
+                _ => None,
+            };
+            let desc = LinkDescription {
+                name: attr(attrs, &["LinkLabel", "label", "name"]).cloned(),
+                speed: link_speed(attrs),
+                latency: attr(attrs, &["latency", "delay", "LinkDelay"]).cloned(),
+                interfaces: pair("src_intf", "dst_intf"),
+                ips: pair("src_ip", "dst_ip"),
+            };
+            Ok(InputLink {
+                edge_id: (*src_id, *dst_id),
+                description: description(desc),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(Imported {
+        devs,
+        links,
+        id_map,
+    })
+}
+
+// a synthetic topology, which is sent to init_emunet or update_emunet like
+// an imported one
+pub fn generate(shape: &Shape) -> Result<Imported, String> {
+    let graph = generator::generate(shape)?;
+    let mut devs: Vec<_> = graph
+        .nodes()
+        .map(|(id, dev)| InputDevice {
+            id: *id,
+            description: dev.description.clone(),
+        })
+        .collect();
+    devs.sort_by_key(|dev| dev.id);
+    let links = graph
+        .edges()
+        .map(|(edge_id, link)| InputLink {
+            edge_id: *edge_id,
+            description: link.description.clone(),
+        })
+        .collect();
+    Ok(Imported {
+        devs,
+        links,
+        id_map: BTreeMap::new(),
+    })
+}
+
+// annotate the devices and links of a running emunet with their placement
+// and addressing
+fn into_exported(name: &str, devices: &[OutputDevice], links: &[OutputLink]) -> Exported {
+    let devs = devices
+        .iter()
+        .map(|dev| {
+            let desc = DeviceDescription {
+                k8s_node: Some(dev.k8s_node_name.clone()),
+                pod: Some(dev.k8s_pod_name.clone()),
+                login_ip: dev.pod_login_ip.clone(),
+                ..DeviceDescription::default()
+            };
+            (dev.id, desc)
+        })
+        .collect();
+
+    let links = links
+        .iter()
+        .map(|link| {
+            let (src, dst) = link.link_id;
+            let desc = match (link.details.get(&src), link.details.get(&dst)) {
+                (Some(s), Some(d)) => LinkDescription {
+                    interfaces: Some((s.intf_name.clone(), d.intf_name.clone())),
+                    ips: Some((s.ip.clone(), d.ip.clone())),
+                    ..LinkDescription::default()
+                },
+                _ => LinkDescription::default(),
+            };
+            (link.link_id, desc)
+        })
+        .collect();
+
+    Exported {
+        name: name.to_string(),
+        devs,
+        links,
+    }
+}
+
+pub(crate) fn export(
+    format: Format,
+    name: &str,
+    devices: &[OutputDevice],
+    links: &[OutputLink],
+) -> Result<String, String> {
+    let exported = into_exported(name, devices, links);
+    match format {
+        // the input network graph that recreates the emunet
+        Format::Json => {
+            let input_graph = InputNetworkGraph {
+                devs: exported
+                    .devs
+                    .into_iter()
+                    .map(|(id, desc)| InputDevice {
+                        id,
+                        description: description(desc),
+                    })
+                    .collect(),
+                links: exported
+                    .links
+                    .into_iter()
+                    .map(|(edge_id, desc)| InputLink {
+                        edge_id,
+                        description: description(desc),
+                    })
+                    .collect(),
+            };
+            Ok(serde_json::to_string_pretty(&input_graph).unwrap())
+        }
+        Format::GraphMl => Ok(graphml::write(&exported)),
+        Format::Dot => Ok(dot::write(&exported)),
+        Format::Clab => clab::write(&exported),
+        Format::Gml => Err("emunets can not be exported to GML".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(imported: &Imported) -> Vec<(u64, u64)> {
+        imported.links.iter().map(|l| l.edge_id).collect()
+    }
+
+    #[test]
+    fn speeds_are_parsed_into_bps() {
+        assert_eq!(parse_speed("10G"), Some(10_000_000_000));
+        assert_eq!(parse_speed("2.5 Gbps"), Some(2_500_000_000));
+        assert_eq!(parse_speed("100Mb/s"), Some(100_000_000));
+        assert_eq!(parse_speed("45000000"), Some(45_000_000));
+        assert_eq!(parse_speed("fast"), None);
+    }
+
+    #[test]
+    fn graphml_of_topology_zoo_is_imported() {
+        let text = r#"<?xml version="1.0" encoding="utf-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key attr.name="LinkSpeedRaw" attr.type="double" for="edge" id="d1" />
+  <key attr.name="LinkLabel" attr.type="string" for="edge" id="d2" />
+  <key attr.name="label" attr.type="string" for="node" id="d3" />
+  <graph edgedefault="undirected">
+    <node id="0"><data key="d3">Chicago</data></node>
+    <node id="1"><data key="d3">New York</data></node>
+    <edge source="0" target="1">
+      <data key="d1">10000000000.0</data>
+      <data key="d2">OC-192</data>
+    </edge>
+  </graph>
+</graphml>"#;
+        let imported = import(Format::GraphMl, text).unwrap();
+        assert_eq!(edges(&imported), vec![(0, 1)]);
+        assert!(imported.id_map.is_empty());
+        assert_eq!(imported.devs[1].description, r#"{"name":"New York"}"#);
+        assert_eq!(
+            imported.links[0].description,
+            r#"{"name":"OC-192","speed":10000000000}"#
+        );
+    }
+
+    #[test]
+    fn gml_is_imported() {
+        let text = r#"graph [
+  directed 0
+  node [ id 4 label "a" graphics [ x 1.0 ] ]
+  node [ id 7 label "b" ]
+  edge [ source 4 target 7 latency "2ms" speed "1G" ]
+]"#;
+        let imported = import(Format::Gml, text).unwrap();
+        assert_eq!(edges(&imported), vec![(4, 7)]);
+        assert_eq!(
+            imported.links[0].description,
+            r#"{"speed":1000000000,"latency":"2ms"}"#
+        );
+    }
+
+    #[test]
+    fn dot_ids_are_remapped() {
+        let text = r#"graph g {
+  node [shape=box];
+  r1 -- r2 -- "r 3" [latency="1ms"];
+  subgraph cluster0 { r4; }
+  r4 -- r1;
+}"#;
+        let imported = import(Format::Dot, text).unwrap();
+        assert_eq!(edges(&imported), vec![(0, 1), (1, 2), (3, 0)]);
+        assert_eq!(imported.id_map["r 3"], 2);
+        assert_eq!(imported.devs[2].description, r#"{"name":"r 3"}"#);
+        assert_eq!(imported.links[1].description, r#"{"latency":"1ms"}"#);
+    }
+
+    #[test]
+    fn exported_emunets_are_imported_back() {
+        let devices: Vec<OutputDevice> = serde_json::from_str(
+            r#"[
+                {"id": 3, "k8s_node_name": "node-a", "k8s_pod_name": "pod-3", "pod_login_ip": null,
+                 "pod_login_user": null, "pod_login_pwd": null, "links": []},
+                {"id": 5, "k8s_node_name": "node-b", "k8s_pod_name": "pod-5", "pod_login_ip": null,
+                 "pod_login_user": null, "pod_login_pwd": null, "links": []}
+            ]"#,
+        )
+        .unwrap();
+        let links: Vec<OutputLink> = serde_json::from_str(
+            r#"[{"link_id": [3, 5], "details": {
+                "3": {"dest_dev_id": 5, "intf_name": "eth1", "ip": "10.0.0.1/30"},
+                "5": {"dest_dev_id": 3, "intf_name": "eth2", "ip": "10.0.0.2/30"}
+            }}]"#,
+        )
+        .unwrap();
+
+        for format in [Format::Json, Format::GraphMl, Format::Dot, Format::Clab].iter() {
+            let text = export(*format, "net", &devices, &links).unwrap();
+            let imported = import(*format, &text).unwrap();
+            let link: LinkDescription =
+                serde_json::from_str(&imported.links[0].description).unwrap();
+            assert_eq!(
+                link.ips,
+                Some(("10.0.0.1/30".to_string(), "10.0.0.2/30".to_string())),
+                "{:?}",
+                format
+            );
+            assert_eq!(
+                link.interfaces,
+                Some(("eth1".to_string(), "eth2".to_string())),
+                "{:?}",
+                format
+            );
+            let dev: DeviceDescription =
+                serde_json::from_str(&imported.devs[1].description).unwrap();
+            assert_eq!(dev.k8s_node.as_deref(), Some("node-b"), "{:?}", format);
+            assert_eq!(dev.pod.as_deref(), Some("pod-5"), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn containerlab_is_imported() {
+        let text = r#"
+name: lab
+topology:
+  nodes:
+    srl1:
+      kind: srl
+      image: ghcr.io/nokia/srlinux
+    srl2:
+      kind: srl
+  links:
+    - endpoints: ["srl1:e1-1", "srl2:e1-1"]
+"#;
+        let imported = import(Format::Clab, text).unwrap();
+        assert_eq!(edges(&imported), vec![(0, 1)]);
+        assert_eq!(imported.id_map["srl2"], 1);
+        assert_eq!(
+            imported.devs[0].description,
+            r#"{"name":"srl1","kind":"srl","image":"ghcr.io/nokia/srlinux"}"#
+        );
+        assert_eq!(
+            imported.links[0].description,
+            r#"{"interfaces":["e1-1","e1-1"]}"#
+        );
+    }
+
+    #[test]
+    fn topologies_are_generated() {
+        let fat_tree = generate(&Shape::FatTree { k: 4 }).unwrap();
+        assert_eq!((fat_tree.devs.len(), fat_tree.links.len()), (36, 48));
+
+        let torus = generate(&Shape::Grid {
+            rows: 3,
+            cols: 4,
+            torus: true,
+        })
+        .unwrap();
+        assert_eq!((torus.devs.len(), torus.links.len()), (12, 24));
+
+        let shape = Shape::BarabasiAlbert {
+            nodes: 50,
+            m: 2,
+            seed: 7,
+        };
+        let first = generate(&shape).unwrap();
+        assert_eq!(edges(&first), edges(&generate(&shape).unwrap()));
+        assert_eq!(first.links.len(), 2 + 2 * 47);
+
+        assert!(generate(&Shape::FatTree { k: 3 }).is_err());
+        assert!(generate(&Shape::Ring { nodes: 0 }).is_err());
+    }
+
+    #[test]
+    fn topologies_are_linted() {
+        let input = r#"{
+            "devs": [
+                {"id": 0, "description": ""}, {"id": 1, "description": ""},
+                {"id": 2, "description": ""}, {"id": 3, "description": ""},
+                {"id": 4, "description": ""}, {"id": 5, "description": ""},
+                {"id": 6, "description": ""}
+            ],
+            "links": [
+                {"edge_id": [0, 1], "description": ""},
+                {"edge_id": [1, 2], "description": ""},
+                {"edge_id": [2, 0], "description": ""},
+                {"edge_id": [1, 0], "description": ""},
+                {"edge_id": [3, 3], "description": ""},
+                {"edge_id": [4, 5], "description": ""},
+                {"edge_id": [5, 9], "description": ""}
+            ]
+        }"#;
+        let imported = import(Format::Json, input).unwrap();
+        let lint = lint(&imported.devs, &imported.links, Some(1));
+        assert_eq!((lint.devices, lint.links, lint.components), (7, 4, 4));
+
+        let issues: Vec<(IssueKind, Vec<u64>)> = lint
+            .issues
+            .iter()
+            .map(|issue| (issue.kind, issue.devices.clone()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (IssueKind::DuplicateLink, vec![1, 0]),
+                (IssueKind::SelfLoop, vec![3]),
+                (IssueKind::UnknownDevice, vec![9]),
+                (IssueKind::DegreeExceeded, vec![0]),
+                (IssueKind::DegreeExceeded, vec![1]),
+                (IssueKind::DegreeExceeded, vec![2]),
+                (IssueKind::IsolatedDevice, vec![3]),
+                (IssueKind::IsolatedDevice, vec![6]),
+                (IssueKind::Disconnected, vec![4, 5]),
+            ]
+        );
+    }
+}